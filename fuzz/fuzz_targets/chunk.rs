@@ -0,0 +1,14 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use pngme::chunk::{Chunk, ChunkRef};
+use pngme::ihdr::Ihdr;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((chunk, _)) = ChunkRef::parse(data) {
+        let _ = Ihdr::try_from(chunk.data());
+        let _ = chunk.data_as_text();
+    }
+    if let Ok(chunk) = Chunk::try_from(data) {
+        assert_eq!(chunk.as_bytes(), data);
+    }
+});
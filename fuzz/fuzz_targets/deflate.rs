@@ -0,0 +1,17 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use pngme::deflate::{deflate, BlockType, Compression};
+use pngme::inflate::inflate;
+use pngme::limits::Limits;
+
+// the first byte picks the level and block type, exhaustive past level 9
+fuzz_target!(|data: &[u8]| {
+    let Some((&options, data)) = data.split_first() else { return };
+    let blocks = [BlockType::Auto, BlockType::Fixed, BlockType::Dynamic][options as usize / 16 % 3];
+    let compression = match options % 16 {
+        level @ 0..=9 => Compression::level(level),
+        _ => Compression::exhaustive(2),
+    };
+    let compressed = deflate(data, compression.blocks(blocks));
+    assert_eq!(inflate(&compressed, Limits::none()).unwrap(), data);
+});
@@ -0,0 +1,9 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use pngme::dump::PngDump;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(dump) = serde_json::from_slice::<PngDump>(data) {
+        let _ = dump.to_png();
+    }
+});
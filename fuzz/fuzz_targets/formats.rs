@@ -0,0 +1,15 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use pngme::encode::EncodeOptions;
+use pngme::formats::{self, Format};
+use pngme::limits::Limits;
+
+// whatever reads has to come back the same from PAM, which holds any pixels
+fuzz_target!(|data: &[u8]| {
+    let Some(format) = Format::detect(data) else { return };
+    let limits = Limits { max_decompressed: 1 << 24, ..Limits::default() };
+    if let Ok(pixels) = formats::read(data, format, limits) {
+        let pam = formats::write(&pixels, Format::Pam, &EncodeOptions::default()).unwrap();
+        assert_eq!(formats::read(&pam, Format::Pam, limits).unwrap(), pixels);
+    }
+});
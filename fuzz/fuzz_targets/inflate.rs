@@ -0,0 +1,17 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use pngme::inflate::{inflate, ZlibDecoder};
+use pngme::limits::Limits;
+
+// the first byte says where to split the stream, which must not change the result
+fuzz_target!(|data: &[u8]| {
+    let Some((&split, data)) = data.split_first() else { return };
+    let limits = Limits { max_decompressed: 1 << 20, ..Limits::default() };
+    let whole = inflate(data, limits);
+    let pieces = data.chunks(split as usize + 1);
+    let streamed = ZlibDecoder::new(pieces, limits).decode_to_vec();
+    assert_eq!(whole.is_ok(), streamed.is_ok());
+    if let (Ok(whole), Ok(streamed)) = (whole, streamed) {
+        assert_eq!(whole, streamed);
+    }
+});
@@ -0,0 +1,18 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use pngme::limits::Limits;
+use pngme::png::{Png, PngRef};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(png) = PngRef::try_from(data) {
+        png.chunks().for_each(drop);
+    }
+    if let Ok(png) = Png::try_from(data) {
+        png.validate();
+        let _ = png.ihdr();
+        let _ = png.orientation();
+        let limits = Limits { max_decompressed: 1 << 24, ..Limits::default() };
+        let _ = png.decode_with_limits(limits);
+        assert_eq!(Png::try_from(png.as_bytes().as_slice()).unwrap(), png);
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use pngme::limits::Limits;
+use pngme::png::Png;
+use pngme::recover::recover;
+
+// whatever goes in, recover has to hand back a png the strict parser accepts.
+// Recovery doesn't apply limits, so neither does the check.
+fuzz_target!(|data: &[u8]| {
+    let recovery = recover(data);
+    Png::from_bytes_with_limits(&recovery.png.as_bytes(), Limits::none()).unwrap();
+});
@@ -0,0 +1,311 @@
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum BatchError {
+    #[error("Invalid glob pattern {0}: {1}")]
+    Pattern(String, glob::PatternError),
+    #[error("No files match {0}")]
+    NoMatch(String),
+    #[error("Couldn't read {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("{0} and {1} would both be written to the same output file")]
+    SameOutput(PathBuf, PathBuf),
+}
+fn is_pattern(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '['])
+}
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension().is_some_and(|ext| extensions.iter().any(|extension| ext.eq_ignore_ascii_case(extension)))
+}
+fn walk(dir: &Path, extensions: &[&str], files: &mut Vec<PathBuf>) -> Result<(), BatchError> {
+    let mut entries = fs::read_dir(dir)
+        .and_then(|entries| entries.map(|entry| entry.map(|entry| entry.path())).collect::<Result<Vec<_>, _>>())
+        .map_err(|e| BatchError::Io(dir.to_path_buf(), e))?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            walk(&path, extensions, files)?;
+        } else if has_extension(&path, extensions) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+// A file the inputs expanded to, and where its output goes under an output
+// directory: its path below the directory, or the literal start of the glob,
+// it was found in, or just its name if it was given directly
+#[derive(Debug, Clone, PartialEq)]
+pub struct Input {
+    pub path: PathBuf,
+    pub relative: PathBuf,
+}
+impl Input {
+    fn under(path: PathBuf, root: &Path) -> Input {
+        let relative = match path.strip_prefix(root) {
+            Ok(relative) if !relative.as_os_str().is_empty() => relative.to_path_buf(),
+            _ => path.file_name().map_or_else(|| path.clone(), PathBuf::from),
+        };
+        Input { path, relative }
+    }
+}
+// the directories of a glob before the first one with a wildcard
+fn glob_root(pattern: &Path) -> PathBuf {
+    let parent = pattern.parent().unwrap_or(Path::new(""));
+    parent.components().take_while(|component| !is_pattern(Path::new(component.as_os_str()))).collect()
+}
+fn is_glob(input: &Path) -> bool {
+    !input.exists() && is_pattern(input)
+}
+// Inputs are a batch, with outputs going into a directory, when there's more
+// than one or they're a directory or glob, however many files those match
+pub fn is_batch(inputs: &[PathBuf]) -> bool {
+    inputs.len() > 1 || inputs.iter().any(|input| input.is_dir() || is_glob(input))
+}
+// Turns the inputs into a list of files. Directories are searched recursively for
+// files with one of `extensions`; anything else that exists or doesn't look like a glob is
+// passed through as is, so a missing file fails when it's processed, not here.
+pub fn expand(inputs: &[PathBuf], extensions: &[&str]) -> Result<Vec<Input>, BatchError> {
+    let mut files = Vec::new();
+    for input in inputs {
+        if input.is_dir() {
+            let mut found = Vec::new();
+            walk(input, extensions, &mut found)?;
+            files.extend(found.into_iter().map(|path| Input::under(path, input)));
+        } else if is_glob(input) {
+            let pattern = input.to_string_lossy();
+            let matches: Vec<PathBuf> = glob::glob(&pattern)
+                .map_err(|e| BatchError::Pattern(pattern.to_string(), e))?
+                .filter_map(Result::ok)
+                .filter(|path| path.is_file())
+                .collect();
+            if matches.is_empty() {
+                return Err(BatchError::NoMatch(pattern.to_string()));
+            }
+            let root = glob_root(input);
+            files.extend(matches.into_iter().map(|path| Input::under(path, &root)));
+        } else {
+            let relative = input.file_name().map_or_else(|| input.clone(), PathBuf::from);
+            files.push(Input { path: input.clone(), relative });
+        }
+    }
+    Ok(files)
+}
+// Two inputs whose outputs would land on the same file in an output directory,
+// whatever extension the output gets
+pub fn check_outputs(files: &[Input]) -> Result<(), BatchError> {
+    let mut seen = HashMap::new();
+    for input in files {
+        if let Some(first) = seen.insert(input.relative.with_extension(""), &input.path) {
+            return Err(BatchError::SameOutput(first.clone(), input.path.clone()));
+        }
+    }
+    Ok(())
+}
+// Runs `f` over every file on the rayon thread pool, keeping results in input order
+pub fn run<I, T, E, F>(files: &[I], f: F) -> Vec<Result<T, E>>
+where
+    I: Sync,
+    T: Send,
+    E: Send,
+    F: Fn(&I) -> Result<T, E> + Sync,
+{
+    files.par_iter().map(&f).collect()
+}
+// `-` stands for stdin as an input and stdout as an output
+pub fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+pub fn read(path: &Path) -> io::Result<Vec<u8>> {
+    if is_stdio(path) {
+        let mut bytes = Vec::new();
+        io::stdin().lock().read_to_end(&mut bytes)?;
+        Ok(bytes)
+    } else {
+        fs::read(path)
+    }
+}
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct WriteOptions {
+    /// Keep the original file next to the new one, named with this suffix
+    #[arg(long, value_name = "SUFFIX", num_args = 0..=1, require_equals = true, default_missing_value = ".bak")]
+    pub backup: Option<String>,
+    /// Give the new file the modification time of the one it replaces
+    #[arg(long)]
+    pub preserve_mtime: bool,
+}
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    let count = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(".{}.pngme-{}-{}.tmp", name, std::process::id(), count))
+}
+fn backup_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+// Writes to a temp file in the same directory and renames it over `path`, so a
+// crash or a full disk never leaves a half written file behind. Permissions of
+// the file being replaced are kept, and missing directories are created.
+pub fn write(path: &Path, bytes: &[u8], options: &WriteOptions) -> io::Result<()> {
+    if is_stdio(path) {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(bytes)?;
+        return stdout.flush();
+    }
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let original = fs::metadata(path).ok();
+    let temp = temp_path(path);
+    let written = (|| {
+        let mut file = File::create_new(&temp)?;
+        file.write_all(bytes)?;
+        if let Some(original) = &original {
+            file.set_permissions(original.permissions())?;
+            if options.preserve_mtime {
+                file.set_modified(original.modified()?)?;
+            }
+        }
+        file.sync_all()?;
+        if let (Some(_), Some(suffix)) = (&original, &options.backup) {
+            let backup = backup_path(path, suffix);
+            let _ = fs::remove_file(&backup);
+            fs::hard_link(path, &backup).or_else(|_| fs::copy(path, &backup).map(|_| ()))?;
+        }
+        fs::rename(&temp, path)
+    })();
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written
+}
+// Where output goes when the user asked for `output`. In a batch, where the
+// input's `relative` path is given, `output` is a directory to put it under.
+pub fn output_path(output: &Path, relative: Option<&Path>) -> PathBuf {
+    match relative {
+        Some(relative) if !is_stdio(output) => output.join(relative),
+        _ => output.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pngme-batch-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        for file in ["a.png", "b.PNG", "notes.txt", "nested/c.png"] {
+            fs::write(dir.join(file), b"").unwrap();
+        }
+        dir
+    }
+
+    fn paths(files: &[Input]) -> Vec<PathBuf> {
+        files.iter().map(|input| input.path.clone()).collect()
+    }
+
+    #[test]
+    fn test_expand_directory() {
+        let dir = testing_dir("dir");
+        let files = expand(std::slice::from_ref(&dir), &["png"]).unwrap();
+        assert_eq!(paths(&files), vec![dir.join("a.png"), dir.join("b.PNG"), dir.join("nested/c.png")]);
+        // the directories below the input are kept
+        assert_eq!(files[2].relative, PathBuf::from("nested/c.png"));
+        assert!(is_batch(std::slice::from_ref(&dir)));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_expand_glob() {
+        let dir = testing_dir("glob");
+        let files = expand(&[dir.join("*.png"), dir.join("notes.txt")], &["png"]).unwrap();
+        assert_eq!(paths(&files), vec![dir.join("a.png"), dir.join("notes.txt")]);
+        let files = expand(&[dir.join("*/c.png")], &["png"]).unwrap();
+        assert_eq!(files[0].relative, PathBuf::from("nested/c.png"));
+        // a glob is a batch even when it matches one file
+        assert!(is_batch(&[dir.join("*/c.png")]));
+        assert!(!is_batch(&[dir.join("nested/c.png")]));
+        assert!(matches!(expand(&[dir.join("*.jpg")], &["png"]), Err(BatchError::NoMatch(_))));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_expand_stdin() {
+        assert_eq!(expand(&[PathBuf::from("-")], &["png"]).unwrap(), vec![Input { path: PathBuf::from("-"), relative: PathBuf::from("-") }]);
+    }
+
+    #[test]
+    fn test_run_keeps_order() {
+        let files: Vec<PathBuf> = (0..100).map(|i| PathBuf::from(i.to_string())).collect();
+        let results = run(&files, |path| path.to_str().unwrap().parse::<u32>().map_err(|_| ()));
+        assert_eq!(results, (0..100).map(Ok).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_write_atomic() {
+        let dir = testing_dir("write");
+        let path = dir.join("a.png");
+        write(&path, b"new", &WriteOptions::default()).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        let leftovers: Vec<_> = fs::read_dir(&dir).unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .filter(|name| name.to_string_lossy().ends_with(".tmp"))
+            .collect();
+        assert!(leftovers.is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_backup_and_mtime() {
+        let dir = testing_dir("backup");
+        let path = dir.join("a.png");
+        fs::write(&path, b"old").unwrap();
+        let mtime = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        File::options().write(true).open(&path).unwrap().set_modified(mtime).unwrap();
+        let options = WriteOptions { backup: Some(".orig".to_string()), preserve_mtime: true };
+        write(&path, b"new", &options).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(fs::read(dir.join("a.png.orig")).unwrap(), b"old");
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), mtime);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = testing_dir("permissions");
+        let path = dir.join("a.png");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        write(&path, b"new", &WriteOptions::default()).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_check_outputs() {
+        let dir = testing_dir("outputs");
+        fs::create_dir_all(dir.join("other")).unwrap();
+        fs::write(dir.join("other/c.png"), b"").unwrap();
+        assert!(check_outputs(&expand(std::slice::from_ref(&dir), &["png"]).unwrap()).is_ok());
+        let files = expand(&[dir.join("nested/c.png"), dir.join("other/c.png")], &["png"]).unwrap();
+        assert!(matches!(check_outputs(&files), Err(BatchError::SameOutput(..))));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_output_path() {
+        assert_eq!(output_path(Path::new("out.png"), None), PathBuf::from("out.png"));
+        assert_eq!(output_path(Path::new("out"), Some(Path::new("in/a.png"))), PathBuf::from("out/in/a.png"));
+        assert_eq!(output_path(Path::new("-"), Some(Path::new("a.png"))), PathBuf::from("-"));
+    }
+}
@@ -1,9 +1,10 @@
 #![allow(unused_variables)]
-// TODO 32nd bit in the length can't be a 1
 use crc::{Crc,CRC_32_ISO_HDLC};
+use std::borrow::Cow;
 use std::fmt;
 use crate::chunk_type::{ChunkType,ChunkTypeError};
 use thiserror::Error;
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Chunk {
     length : u32,
     pub ctype: ChunkType,
@@ -20,29 +21,93 @@ pub enum ChunkError {
     Crc,
     #[error("Invalid chunk type: {0}")]
     ChunkType(#[from] ChunkTypeError),
+    #[error("Chunk data is {0} bytes long, the limit is 2^31-1")]
+    TooLong(usize),
 }
-impl TryFrom<&[u8]> for Chunk {
-    type Error = ChunkError;
-    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+// Latin-1 maps every byte to the code point with the same value
+pub fn latin1_to_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+// Chunk data as text: UTF-8 when it's valid, otherwise Latin-1 (what tEXt and
+// zTXt are specified to use) as long as the only control bytes are tabs, line
+// breaks and the nul keyword separator. None means the data is binary.
+pub fn data_as_text(data: &[u8]) -> Option<Cow<'_, str>> {
+    if let Ok(s) = std::str::from_utf8(data) {
+        return Some(Cow::Borrowed(s));
+    }
+    data.iter()
+        .all(|b| matches!(b, 0 | b'\t' | b'\n' | b'\r' | 0x20..=0x7e | 0xa0..=0xff))
+        .then(|| Cow::Owned(latin1_to_string(data)))
+}
+// Borrowed view of a chunk inside a larger buffer. Nothing is copied until
+// it is turned into an owned `Chunk`, and the crc is only checked on request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkRef<'a> {
+    ctype: ChunkType,
+    data: &'a [u8],
+    crc: u32,
+}
+impl<'a> ChunkRef<'a> {
+    // Parses the chunk at the start of `bytes` and returns it along with whatever follows it
+    pub fn parse(bytes: &'a [u8]) -> Result<(ChunkRef<'a>, &'a [u8]), ChunkError> {
         if bytes.len() < 12 {
             return Err(ChunkError::Short)
         }
-        let (length_bytes, rest) = bytes.split_at(4);
-        let length = u32::from_be_bytes(length_bytes.try_into().unwrap());
-        let (ctype_bytes, rest) = rest.split_at(4);
-        if length != rest.len() as u32 - 4 {
-            return Err(ChunkError::Length(length, rest.len()));
+        let length = u32::from_be_bytes(bytes[..4].try_into().unwrap());
+        if length > Chunk::MAX_LENGTH {
+            return Err(ChunkError::TooLong(length as usize));
         }
-        let (data_bytes, crc_bytes) = rest.split_at(rest.len() - 4);
-
-        let ctype: ChunkType = ChunkType::try_from(<[u8; 4]>::try_from(ctype_bytes).unwrap())?;
-        let data: Vec<u8> = data_bytes.try_into().unwrap();
+        if length as usize > bytes.len() - 12 {
+            return Err(ChunkError::Length(length, bytes.len() - 12));
+        }
+        let ctype = ChunkType::try_from(<[u8; 4]>::try_from(&bytes[4..8]).unwrap())?;
+        let (data, rest) = bytes[8..].split_at(length as usize);
+        let (crc_bytes, rest) = rest.split_at(4);
         let crc = u32::from_be_bytes(crc_bytes.try_into().unwrap());
-
-        if crc != Chunk::CRC_32.checksum(&[&ctype.bytes(), data.as_slice()].concat()) {
-            return Err(ChunkError::Crc);
+        Ok((ChunkRef { ctype, data, crc }, rest))
+    }
+    pub fn length(&self) -> u32 {
+        self.data.len() as u32
+    }
+    pub fn chunk_type(&self) -> &ChunkType {
+        &self.ctype
+    }
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+    pub fn is_crc_valid(&self) -> bool {
+        self.crc == Chunk::checksum(&self.ctype, self.data)
+    }
+    pub fn verify_crc(self) -> Result<Self, ChunkError> {
+        if self.is_crc_valid() { Ok(self) } else { Err(ChunkError::Crc) }
+    }
+    pub fn data_as_str(&self) -> Result<&'a str, std::str::Utf8Error> {
+        std::str::from_utf8(self.data)
+    }
+    pub fn data_as_text(&self) -> Option<Cow<'a, str>> {
+        data_as_text(self.data)
+    }
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk { length: self.length(), ctype: self.ctype, data: self.data.to_vec(), crc: self.crc }
+    }
+}
+impl<'a> TryFrom<&'a [u8]> for ChunkRef<'a> {
+    type Error = ChunkError;
+    fn try_from(bytes: &'a [u8]) -> Result<Self, Self::Error> {
+        let (chunk, rest) = ChunkRef::parse(bytes)?;
+        if !rest.is_empty() {
+            return Err(ChunkError::Length(chunk.length(), bytes.len() - 12));
         }
-        return Ok(Chunk { length, ctype, data, crc})
+        chunk.verify_crc()
+    }
+}
+impl TryFrom<&[u8]> for Chunk {
+    type Error = ChunkError;
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        ChunkRef::try_from(bytes).map(|chunk| chunk.to_chunk())
     }
 }
 // copied from https://github.com/gabebw/pngme/blob/main/src/chunk.rs#L152C1-L162C2
@@ -52,8 +117,8 @@ impl fmt::Display for Chunk {
             f,
             "{}\t{}",
             self.chunk_type(),
-            self.data_as_string()
-                .unwrap_or_else(|_| "[data]".to_string())
+            self.data_as_text()
+                .unwrap_or(Cow::Borrowed("[data]"))
         )
     }
 }
@@ -62,14 +127,26 @@ impl fmt::Display for Chunk {
 //         write!(f, "Chunk of length {} ctype {}, CRC = {}", self.length, self.ctype, self.crc)
 //     }
 // }
-#[allow(dead_code)]
 impl Chunk {
-    const BIT_32: u32 = 0b1000_0000_0000_0000;
+    pub const MAX_LENGTH: u32 = (1 << 31) - 1;
     const CRC_32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
     // const CRC 
+    pub fn checksum(ctype: &ChunkType, data: &[u8]) -> u32 {
+        let mut digest = Self::CRC_32.digest();
+        digest.update(&ctype.bytes());
+        digest.update(data);
+        digest.finalize()
+    }
+    // Panics if `data` is longer than `MAX_LENGTH`, see `try_new`
     pub fn new(ctype: ChunkType, data: Vec<u8>) -> Chunk {
-        let crc = Self::CRC_32.checksum(&[&ctype.bytes(), data.as_slice()].concat());
-        return Chunk{length: data.len() as u32, ctype, data, crc}
+        Self::try_new(ctype, data).expect("chunk data over the 2^31-1 byte limit")
+    }
+    pub fn try_new(ctype: ChunkType, data: Vec<u8>) -> Result<Chunk, ChunkError> {
+        if data.len() > Self::MAX_LENGTH as usize {
+            return Err(ChunkError::TooLong(data.len()));
+        }
+        let crc = Self::checksum(&ctype, &data);
+        Ok(Chunk{length: data.len() as u32, ctype, data, crc})
     }
     pub fn length(&self) -> u32 {
         self.length
@@ -86,6 +163,12 @@ impl Chunk {
     pub fn data_as_string(&self) -> Result<String, std::string::FromUtf8Error> {
         String::from_utf8(self.data.clone())
     }
+    pub fn data_as_str(&self) -> Result<&str, std::str::Utf8Error> {
+        std::str::from_utf8(&self.data)
+    }
+    pub fn data_as_text(&self) -> Option<Cow<'_, str>> {
+        data_as_text(&self.data)
+    }
     pub fn as_bytes(&self) -> Vec<u8> {
         self.length.to_be_bytes() 
             .into_iter()
@@ -96,11 +179,17 @@ impl Chunk {
     }
 }
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use crate::chunk_type::ChunkType;
+    use proptest::prelude::*;
     use std::str::FromStr;
 
+    pub(crate) fn arb_chunk() -> impl Strategy<Value = Chunk> {
+        ("[a-zA-Z]{4}", prop::collection::vec(any::<u8>(), 0..64))
+            .prop_map(|(ctype, data)| Chunk::new(ChunkType::from_str(&ctype).unwrap(), data))
+    }
+
     fn testing_chunk() -> Chunk {
         let data_length: u32 = 42;
         let chunk_type = "RuSt".as_bytes();
@@ -201,6 +290,55 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    fn test_chunk_ref_parse() {
+        let bytes = testing_chunk().as_bytes();
+        let trailing = [bytes.as_slice(), b"trailing"].concat();
+        let (chunk, rest) = ChunkRef::parse(&trailing).unwrap();
+        assert_eq!(chunk.length(), 42);
+        assert_eq!(chunk.data_as_str().unwrap(), "This is where your secret message will be!");
+        assert!(chunk.is_crc_valid());
+        assert_eq!(rest, b"trailing");
+        assert!(ChunkRef::try_from(trailing.as_slice()).is_err());
+        assert_eq!(chunk.to_chunk().as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_chunk_ref_bad_crc() {
+        let mut bytes = testing_chunk().as_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let (chunk, _) = ChunkRef::parse(&bytes).unwrap();
+        assert!(!chunk.is_crc_valid());
+        assert!(matches!(ChunkRef::try_from(bytes.as_slice()), Err(ChunkError::Crc)));
+    }
+
+    #[test]
+    fn test_chunk_ref_length_past_end() {
+        let mut bytes = testing_chunk().as_bytes();
+        bytes[1] = 0xff;
+        assert!(matches!(ChunkRef::parse(&bytes), Err(ChunkError::Length(_, 42))));
+    }
+
+    #[test]
+    fn test_chunk_length_over_limit() {
+        let mut bytes = testing_chunk().as_bytes();
+        bytes[0] = 0x80;
+        assert!(matches!(ChunkRef::parse(&bytes), Err(ChunkError::TooLong(0x8000_002a))));
+    }
+
+    #[test]
+    fn test_chunk_data_as_text() {
+        let ctype = ChunkType::from_str("tEXt").unwrap();
+        let utf8 = Chunk::new(ctype, "Title\0caf\u{e9}".as_bytes().to_vec());
+        assert_eq!(utf8.data_as_text().unwrap(), "Title\0caf\u{e9}");
+        let latin1 = Chunk::new(ctype, b"Title\0caf\xe9".to_vec());
+        assert_eq!(latin1.data_as_text().unwrap(), "Title\0caf\u{e9}");
+        assert_eq!(latin1.to_string(), "tEXt\tTitle\0caf\u{e9}");
+        let binary = Chunk::new(ctype, vec![0x89, 0x01, 0xff]);
+        assert!(binary.data_as_text().is_none());
+    }
+
     #[test]
     pub fn test_chunk_trait_impls() {
         let data_length: u32 = 42;
@@ -221,4 +359,18 @@ mod tests {
         
         let _chunk_string = format!("{}", chunk);
     }
-}
\ No newline at end of file
+
+    proptest! {
+        #[test]
+        fn prop_chunk_round_trip(chunk in arb_chunk()) {
+            let bytes = chunk.as_bytes();
+            prop_assert_eq!(Chunk::try_from(bytes.as_slice()).unwrap(), chunk);
+        }
+
+        #[test]
+        fn prop_chunk_parse_never_panics(bytes in prop::collection::vec(any::<u8>(), 0..64)) {
+            let _ = ChunkRef::parse(&bytes);
+            let _ = Chunk::try_from(bytes.as_slice());
+        }
+    }
+}
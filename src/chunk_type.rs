@@ -2,22 +2,40 @@
 use std::fmt;
 use std::str::FromStr;
 use thiserror::Error;
-#[derive (PartialEq, Eq, Debug, Clone)]
+#[derive (PartialEq, Eq, Debug, Clone, Copy, Hash, PartialOrd, Ord)]
 pub struct ChunkType {
     bytes: [u8;4]
 }
 impl ChunkType {
     const BIT_6: u8 = 0b0010_0000;
+    pub const IHDR: ChunkType = ChunkType { bytes: *b"IHDR" };
+    pub const PLTE: ChunkType = ChunkType { bytes: *b"PLTE" };
+    pub const IDAT: ChunkType = ChunkType { bytes: *b"IDAT" };
+    pub const IEND: ChunkType = ChunkType { bytes: *b"IEND" };
+    pub const CHRM: ChunkType = ChunkType { bytes: *b"cHRM" };
+    pub const GAMA: ChunkType = ChunkType { bytes: *b"gAMA" };
+    pub const ICCP: ChunkType = ChunkType { bytes: *b"iCCP" };
+    pub const SBIT: ChunkType = ChunkType { bytes: *b"sBIT" };
+    pub const SRGB: ChunkType = ChunkType { bytes: *b"sRGB" };
+    pub const BKGD: ChunkType = ChunkType { bytes: *b"bKGD" };
+    pub const HIST: ChunkType = ChunkType { bytes: *b"hIST" };
+    pub const TRNS: ChunkType = ChunkType { bytes: *b"tRNS" };
+    pub const PHYS: ChunkType = ChunkType { bytes: *b"pHYs" };
+    pub const SPLT: ChunkType = ChunkType { bytes: *b"sPLT" };
+    pub const TIME: ChunkType = ChunkType { bytes: *b"tIME" };
+    pub const ITXT: ChunkType = ChunkType { bytes: *b"iTXt" };
+    pub const TEXT: ChunkType = ChunkType { bytes: *b"tEXt" };
+    pub const ZTXT: ChunkType = ChunkType { bytes: *b"zTXt" };
+    pub const EXIF: ChunkType = ChunkType { bytes: *b"eXIf" };
     pub fn bytes(&self) -> [u8; 4] {
-        self.bytes.clone()
+        self.bytes
     }
     pub fn is_valid(&self) -> bool {
         (self.bytes[2] & ChunkType::BIT_6) == 0
     }
     pub fn is_critical(&self) -> bool {
-        println!("{} {} {}", std::str::from_utf8(&self.bytes).unwrap(), self.bytes[0], (self.bytes[0] & ChunkType::BIT_6));
         (self.bytes[0] & ChunkType::BIT_6) == 0
-    } 
+    }
     pub fn is_public(&self) -> bool {
         (self.bytes[1] & ChunkType::BIT_6) == 0
     }
@@ -27,37 +45,61 @@ impl ChunkType {
     pub fn is_safe_to_copy(&self) -> bool {
         (self.bytes[3] & ChunkType::BIT_6) != 0
     }
+    // Like `try_from`, but also rejects types with the reserved bit set, which
+    // no chunk defined by the current spec has
+    pub fn new_checked(bytes: [u8; 4]) -> Result<ChunkType, ChunkTypeError> {
+        let ctype = ChunkType::try_from(bytes)?;
+        if !ctype.is_reserved_bit_valid() {
+            return Err(ChunkTypeError::ReservedBit(ctype));
+        }
+        Ok(ctype)
+    }
+    // The property bits are the case of each letter, so these only change case,
+    // e.g. `ChunkType::new_checked(*b"RUST")?.with_critical(false).with_safe_to_copy(true)` is ruSt
+    fn with_bit(self, index: usize, set: bool) -> ChunkType {
+        let mut bytes = self.bytes;
+        if set {
+            bytes[index] |= ChunkType::BIT_6;
+        } else {
+            bytes[index] &= !ChunkType::BIT_6;
+        }
+        ChunkType { bytes }
+    }
+    pub fn with_critical(self, critical: bool) -> ChunkType {
+        self.with_bit(0, !critical)
+    }
+    pub fn with_public(self, public: bool) -> ChunkType {
+        self.with_bit(1, !public)
+    }
+    pub fn with_safe_to_copy(self, safe_to_copy: bool) -> ChunkType {
+        self.with_bit(3, safe_to_copy)
+    }
 }
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone, PartialEq)]
 pub enum ChunkTypeError {
-    #[error("Bad Length {0}: expected 4")]
-    NonAlpha(u8),
-    #[error("Not Ascii Alphabetic {0}: ({0:b}")]
-    BadLength(usize)
+    #[error("Chunk type is {0} bytes long, expected 4")]
+    BadLength(usize),
+    #[error("Byte {byte:#04x} at position {position} of the chunk type isn't an ASCII letter")]
+    NonAlpha { position: usize, byte: u8 },
+    #[error("Chunk type {0} has the reserved bit set (its third letter is lowercase)")]
+    ReservedBit(ChunkType),
 }
 
 impl TryFrom<[u8;4]> for ChunkType {
     type Error = ChunkTypeError;
     fn try_from(bytes: [u8;4]) -> Result<Self, Self::Error> {
-        if let Some(byte) = bytes.iter().find(|&x| !x.is_ascii_alphabetic()) {
-            Err(ChunkTypeError::NonAlpha(*byte))
-        } else {
-            Ok(ChunkType{bytes})
+        match bytes.iter().position(|x| !x.is_ascii_alphabetic()) {
+            Some(position) => Err(ChunkTypeError::NonAlpha { position, byte: bytes[position] }),
+            None => Ok(ChunkType{bytes}),
         }
     }
 }
 impl FromStr for ChunkType {
     type Err = ChunkTypeError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.len() != 4 {
-            return Err(ChunkTypeError::BadLength(s.len()))
-        }
-        if let Some(byte) = s.bytes().find(|c| !c.is_ascii_alphabetic()) {
-            Err(ChunkTypeError::NonAlpha(byte))
-        } else {
-            let bytes: [u8;4] = s.as_bytes().try_into().unwrap();
-            Ok(ChunkType{bytes})
-        }
+        let bytes: [u8;4] = s.as_bytes().try_into()
+            .map_err(|_| ChunkTypeError::BadLength(s.len()))?;
+        ChunkType::try_from(bytes)
     }
 }
 impl fmt::Display for ChunkType {
@@ -150,12 +192,46 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    pub fn test_chunk_type_errors() {
+        assert_eq!(ChunkType::from_str("RuSty"), Err(ChunkTypeError::BadLength(5)));
+        let err = ChunkType::from_str("Ru1t").unwrap_err();
+        assert_eq!(err, ChunkTypeError::NonAlpha { position: 2, byte: b'1' });
+        assert_eq!(err.to_string(), "Byte 0x31 at position 2 of the chunk type isn't an ASCII letter");
+        assert_eq!(
+            ChunkType::new_checked(*b"Rust"),
+            Err(ChunkTypeError::ReservedBit(ChunkType::from_str("Rust").unwrap()))
+        );
+        assert_eq!(ChunkType::new_checked(*b"RuSt").unwrap().to_string(), "RuSt");
+    }
+
+    #[test]
+    pub fn test_chunk_type_with_properties() {
+        let ctype = ChunkType::new_checked(*b"RUST").unwrap()
+            .with_critical(false)
+            .with_safe_to_copy(true);
+        assert_eq!(ctype.to_string(), "rUSt");
+        assert!(!ctype.is_critical());
+        assert!(ctype.is_public());
+        assert!(ctype.is_safe_to_copy());
+        let ctype = ctype.with_critical(true).with_public(false).with_safe_to_copy(false);
+        assert_eq!(ctype.to_string(), "RuST");
+    }
+
     #[test]
     pub fn test_chunk_type_string() {
         let chunk = ChunkType::from_str("RuSt").unwrap();
         assert_eq!(&chunk.to_string(), "RuSt");
     }
 
+    #[test]
+    pub fn test_chunk_type_constants() {
+        assert_eq!(ChunkType::IHDR, ChunkType::from_str("IHDR").unwrap());
+        assert_eq!(ChunkType::GAMA.to_string(), "gAMA");
+        assert!(ChunkType::IDAT.is_critical());
+        assert!(!ChunkType::TEXT.is_critical());
+    }
+
     #[test]
     pub fn test_chunk_type_trait_impls() {
         let chunk_type_1: ChunkType = TryFrom::try_from([82, 117, 83, 116]).unwrap();
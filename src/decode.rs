@@ -0,0 +1,244 @@
+use thiserror::Error;
+use crate::chunk_type::ChunkType;
+use crate::ihdr::Ihdr;
+use crate::inflate::{InflateError, ZlibDecoder};
+use crate::limits::{LimitError, Limits};
+use crate::png::{Png, PngError};
+
+#[derive(Error, Debug)]
+pub enum DecodeError {
+    #[error("{0}")]
+    Png(#[from] PngError),
+    #[error("Invalid image data: {0}")]
+    Inflate(#[from] InflateError),
+    #[error("Unknown filter type {0} on scanline {1}")]
+    Filter(u8, usize),
+    #[error("Image data is {0} bytes, expected {1}")]
+    Length(u64, u64),
+    #[error("{0}")]
+    Limit(#[from] LimitError),
+}
+// Decoded pixels, still in the image's own sample format: rows of packed samples,
+// sub-byte ones starting from the high bits and 16-bit ones big-endian, with the
+// filter bytes and any interlacing gone
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub ihdr: Ihdr,
+    pub data: Vec<u8>,
+}
+impl Image {
+    pub fn row(&self, y: u32) -> &[u8] {
+        let row_bytes = self.ihdr.row_bytes();
+        &self.data[y as usize * row_bytes..(y as usize + 1) * row_bytes]
+    }
+}
+// x and y of the first pixel and the spacing between pixels of each Adam7 pass
+pub const ADAM7: [(u32, u32, u32, u32); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+// Dimensions of the reduced images that make up the data, one unless interlaced.
+// Passes can be empty for small images.
+pub fn passes(ihdr: &Ihdr) -> Vec<(u32, u32)> {
+    if !ihdr.interlaced {
+        return vec![(ihdr.width, ihdr.height)];
+    }
+    ADAM7.iter()
+        .map(|&(x0, y0, dx, dy)| {
+            (ihdr.width.saturating_sub(x0).div_ceil(dx), ihdr.height.saturating_sub(y0).div_ceil(dy))
+        })
+        .collect()
+}
+pub(crate) fn row_bytes(ihdr: &Ihdr, width: u32) -> usize {
+    (width as usize * ihdr.bits_per_pixel()).div_ceil(8)
+}
+// size of the decompressed data, filter bytes included
+pub fn data_size(ihdr: &Ihdr) -> u64 {
+    passes(ihdr).into_iter()
+        .filter(|&(width, height)| width > 0 && height > 0)
+        .map(|(width, height)| height as u64 * (1 + row_bytes(ihdr, width) as u64))
+        .sum()
+}
+pub(crate) fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+// Undoes `filter` on `row` in place. `previous` is the unfiltered row above, all
+// zeros for the first row, and `bpp` the bytes per complete pixel, at least 1.
+pub fn unfilter(filter: u8, row: &mut [u8], previous: &[u8], bpp: usize) -> Result<(), u8> {
+    match filter {
+        0 => {}
+        1 => {
+            for i in bpp..row.len() {
+                row[i] = row[i].wrapping_add(row[i - bpp]);
+            }
+        }
+        2 => {
+            for (x, &b) in row.iter_mut().zip(previous) {
+                *x = x.wrapping_add(b);
+            }
+        }
+        3 => {
+            for i in 0..row.len() {
+                let a = if i >= bpp { row[i - bpp] } else { 0 };
+                row[i] = row[i].wrapping_add(((a as u16 + previous[i] as u16) / 2) as u8);
+            }
+        }
+        4 => {
+            for i in 0..row.len() {
+                let (a, c) = if i >= bpp { (row[i - bpp], previous[i - bpp]) } else { (0, 0) };
+                row[i] = row[i].wrapping_add(paeth(a, previous[i], c));
+            }
+        }
+        _ => return Err(filter),
+    }
+    Ok(())
+}
+// copies pixel `from` of `source` to pixel `to` of `target`, for any pixel size
+pub(crate) fn copy_pixel(source: &[u8], from: usize, target: &mut [u8], to: usize, bits: usize) {
+    if bits >= 8 {
+        let bytes = bits / 8;
+        target[to * bytes..(to + 1) * bytes].copy_from_slice(&source[from * bytes..(from + 1) * bytes]);
+        return;
+    }
+    let mask = (1u8 << bits) - 1;
+    let value = (source[from * bits / 8] >> (8 - bits - from * bits % 8)) & mask;
+    let shift = 8 - bits - to * bits % 8;
+    target[to * bits / 8] = (target[to * bits / 8] & !(mask << shift)) | (value << shift);
+}
+impl Png {
+    pub fn decode(&self) -> Result<Image, DecodeError> {
+        self.decode_with_limits(Limits::default())
+    }
+    pub fn decode_with_limits(&self, limits: Limits) -> Result<Image, DecodeError> {
+        let ihdr = self.ihdr()?;
+        limits.check_dimensions(ihdr.width, ihdr.height)?;
+        if self.position_of(ChunkType::IDAT).is_none() {
+            return Err(PngError::MissingChunk(ChunkType::IDAT).into());
+        }
+        let expected = data_size(&ihdr);
+        limits.check_decompressed(expected)?;
+        let mut decoder = ZlibDecoder::new(self.chunks_by_type(ChunkType::IDAT).map(|chunk| chunk.data()), limits);
+        let mut filtered = vec![0; expected as usize];
+        let mut filled = 0;
+        while filled < filtered.len() {
+            match decoder.decode(&mut filtered[filled..])? {
+                0 => return Err(DecodeError::Length(filled as u64, expected)),
+                n => filled += n,
+            }
+        }
+        // anything after the image data is ignored, but the checksum is still checked
+        let mut rest = [0; 1024];
+        while decoder.decode(&mut rest)? > 0 {}
+
+        let bits = ihdr.bits_per_pixel();
+        let bpp = bits.div_ceil(8);
+        let mut data = vec![0; ihdr.image_bytes() as usize];
+        let mut offset = 0;
+        let mut scanline = 0;
+        for (pass, (width, height)) in passes(&ihdr).into_iter().enumerate() {
+            if width == 0 || height == 0 {
+                continue;
+            }
+            let length = row_bytes(&ihdr, width);
+            let mut previous = vec![0; length];
+            let mut pixels = Vec::with_capacity(length * height as usize);
+            for _ in 0..height {
+                let filter = filtered[offset];
+                let row = &mut filtered[offset + 1..offset + 1 + length];
+                unfilter(filter, row, &previous, bpp).map_err(|filter| DecodeError::Filter(filter, scanline))?;
+                previous.copy_from_slice(row);
+                pixels.extend_from_slice(row);
+                offset += 1 + length;
+                scanline += 1;
+            }
+            if !ihdr.interlaced {
+                data = pixels;
+                break;
+            }
+            let (x0, y0, dx, dy) = ADAM7[pass];
+            let target_length = ihdr.row_bytes();
+            for y in 0..height as usize {
+                let source = &pixels[y * length..(y + 1) * length];
+                let target_y = y0 as usize + y * dy as usize;
+                let target = &mut data[target_y * target_length..(target_y + 1) * target_length];
+                for x in 0..width as usize {
+                    copy_pixel(source, x, target, x0 as usize + x * dx as usize, bits);
+                }
+            }
+        }
+        Ok(Image { ihdr, data })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ihdr::ColorType;
+
+    fn ihdr(width: u32, height: u32, bit_depth: u8, color_type: ColorType, interlaced: bool) -> Ihdr {
+        Ihdr { width, height, bit_depth, color_type, interlaced }
+    }
+
+    #[test]
+    fn test_passes() {
+        assert_eq!(passes(&ihdr(8, 8, 8, ColorType::Rgb, true)), vec![(1, 1), (1, 1), (2, 1), (2, 2), (4, 2), (4, 4), (8, 4)]);
+        assert_eq!(passes(&ihdr(1, 1, 8, ColorType::Rgb, true))[1..], [(0, 1), (1, 0), (0, 1), (1, 0), (0, 1), (1, 0)]);
+        assert_eq!(data_size(&ihdr(1, 1, 1, ColorType::Grayscale, true)), 2);
+        assert_eq!(data_size(&ihdr(10, 3, 16, ColorType::Rgba, false)), 3 * 81);
+    }
+
+    #[test]
+    fn test_unfilter() {
+        let previous = [10, 20, 30, 40];
+        let mut row = [1, 2, 3, 4];
+        unfilter(1, &mut row, &previous, 2).unwrap();
+        assert_eq!(row, [1, 2, 4, 6]);
+        let mut row = [1, 2, 3, 4];
+        unfilter(2, &mut row, &previous, 2).unwrap();
+        assert_eq!(row, [11, 22, 33, 44]);
+        let mut row = [1, 2, 3, 4];
+        unfilter(3, &mut row, &previous, 2).unwrap();
+        assert_eq!(row, [6, 12, 3 + (6 + 30) / 2, 4 + (12 + 40) / 2]);
+        let mut row = [1, 2, 3, 4];
+        unfilter(4, &mut row, &previous, 2).unwrap();
+        assert_eq!(row, [11, 22, 3 + 30, 4 + 40]);
+        assert_eq!(unfilter(5, &mut row, &previous, 2), Err(5));
+    }
+
+    #[test]
+    fn test_copy_sub_byte_pixel() {
+        let source = [0b1011_0000];
+        let mut target = [0b0000_0000];
+        copy_pixel(&source, 1, &mut target, 3, 2);
+        assert_eq!(target, [0b0000_0011]);
+        copy_pixel(&source, 0, &mut target, 0, 1);
+        assert_eq!(target, [0b1000_0011]);
+    }
+
+    #[test]
+    fn test_decode_image_file() {
+        let png = Png::try_from(&include_bytes!("../dice.png")[..]).unwrap();
+        let image = png.decode().unwrap();
+        assert_eq!(image.data.len(), 330 * 247 * 4);
+        assert_eq!(image.row(0).len(), 330 * 4);
+    }
+
+    #[test]
+    fn test_decode_missing_idat() {
+        let png = Png::from_chunks(vec![ihdr(1, 1, 8, ColorType::Grayscale, false).to_chunk()]);
+        assert!(matches!(png.decode(), Err(DecodeError::Png(PngError::MissingChunk(ChunkType::IDAT)))));
+    }
+}
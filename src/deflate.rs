@@ -0,0 +1,896 @@
+use clap::ValueEnum;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::{self, Write};
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::decode::DecodeError;
+use crate::inflate::{adler32, ZlibDecoder, CODE_LENGTH_ORDER, DISTANCE_BASE, DISTANCE_EXTRA, LENGTH_BASE, LENGTH_EXTRA, WINDOW};
+use crate::limits::Limits;
+use crate::png::{Png, PngError};
+
+// zlib (RFC 1950) wrapped DEFLATE (RFC 1951) encoding, the counterpart of inflate.rs.
+// Input is compressed a block at a time as it's written and the output goes
+// straight to any `Write`, an `IdatWriter` to end up with IDAT chunks.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlockType {
+    /// Whichever of stored, fixed and dynamic Huffman is smallest, block by block
+    #[default]
+    Auto,
+    Fixed,
+    Dynamic,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression {
+    // 0 stores, 1 to 3 match greedily, 4 to 9 lazily and search harder, like zlib
+    pub level: u8,
+    pub blocks: BlockType,
+    // rounds of zopfli-style optimal parsing, 0 to parse by `level`
+    pub iterations: u32,
+}
+impl Default for Compression {
+    fn default() -> Self {
+        Compression { level: 6, blocks: BlockType::Auto, iterations: 0 }
+    }
+}
+impl Compression {
+    pub const MAX_LEVEL: u8 = 9;
+    pub fn level(level: u8) -> Self {
+        Compression { level: level.min(Self::MAX_LEVEL), ..Default::default() }
+    }
+    // Much slower than any level, for when every byte counts
+    pub fn exhaustive(iterations: u32) -> Self {
+        Compression { level: Self::MAX_LEVEL, iterations: iterations.max(1), ..Default::default() }
+    }
+    pub fn blocks(self, blocks: BlockType) -> Self {
+        Compression { blocks, ..self }
+    }
+}
+// zlib's configuration table: with a match this good already, only a quarter of
+// the chain is searched for a lazy one; lazy matching stops at `lazy`, or for
+// greedy levels, later positions are only hashed up to that length; searching
+// stops at a `nice` match or after `chain` candidates
+struct Config {
+    good: usize,
+    lazy: usize,
+    nice: usize,
+    chain: usize,
+}
+const CONFIGS: [Config; 10] = [
+    Config { good: 0, lazy: 0, nice: 0, chain: 0 },
+    Config { good: 4, lazy: 4, nice: 8, chain: 4 },
+    Config { good: 4, lazy: 5, nice: 16, chain: 8 },
+    Config { good: 4, lazy: 6, nice: 32, chain: 32 },
+    Config { good: 4, lazy: 4, nice: 16, chain: 16 },
+    Config { good: 8, lazy: 16, nice: 32, chain: 32 },
+    Config { good: 8, lazy: 16, nice: 128, chain: 128 },
+    Config { good: 8, lazy: 32, nice: 128, chain: 256 },
+    Config { good: 32, lazy: 128, nice: 258, chain: 1024 },
+    Config { good: 32, lazy: 258, nice: 258, chain: 4096 },
+];
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+// a 3 byte match this far back costs more than the literals
+const TOO_FAR: usize = 4096;
+const EXHAUSTIVE_CHAIN: usize = 8192;
+// input compressed per block
+const BLOCK: usize = 1 << 16;
+const MAX_STORED: usize = 65535;
+const HASH_BITS: u32 = 15;
+const NONE: u32 = u32::MAX;
+const END_OF_BLOCK: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    Literal(u8),
+    Match(u16, u16),
+}
+fn length_code(length: usize) -> usize {
+    if length == MAX_MATCH {
+        return LENGTH_BASE.len() - 1;
+    }
+    LENGTH_BASE.partition_point(|&base| base as usize <= length) - 1
+}
+fn distance_code(distance: usize) -> usize {
+    DISTANCE_BASE.partition_point(|&base| base as usize <= distance) - 1
+}
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+impl BitWriter {
+    // DEFLATE packs values starting from the least significant bit
+    fn write(&mut self, value: u32, n: u32) {
+        self.bits |= (value as u64) << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.write(0, 8 - self.count);
+        }
+    }
+}
+// Hash chains over every 3 byte sequence, as in zlib
+struct Matcher<'a> {
+    data: &'a [u8],
+    head: Vec<u32>,
+    prev: Vec<u32>,
+}
+impl<'a> Matcher<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Matcher { data, head: vec![NONE; 1 << HASH_BITS], prev: vec![NONE; data.len()] }
+    }
+    fn hash(&self, i: usize) -> usize {
+        let bytes = u32::from_be_bytes([0, self.data[i], self.data[i + 1], self.data[i + 2]]);
+        (bytes.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+    }
+    fn insert(&mut self, i: usize) {
+        if i + MIN_MATCH <= self.data.len() {
+            let hash = self.hash(i);
+            self.prev[i] = self.head[hash];
+            self.head[hash] = i as u32;
+        }
+    }
+    fn match_length(&self, candidate: usize, i: usize, max: usize) -> usize {
+        self.data[candidate..candidate + max].iter().zip(&self.data[i..i + max]).take_while(|(a, b)| a == b).count()
+    }
+    // Calls `f` with each earlier position sharing the hash of `i`, nearest first,
+    // until it returns false or the chain or window runs out
+    fn candidates<F: FnMut(usize) -> bool>(&self, i: usize, mut chain: usize, mut f: F) {
+        if i + MIN_MATCH > self.data.len() {
+            return;
+        }
+        let mut candidate = self.head[self.hash(i)];
+        while candidate != NONE && chain > 0 && i - candidate as usize <= WINDOW {
+            if !f(candidate as usize) {
+                return;
+            }
+            candidate = self.prev[candidate as usize];
+            chain -= 1;
+        }
+    }
+    // Longest match for `i` as (length, distance), (0, 0) if there's none
+    fn longest(&self, i: usize, chain: usize, nice: usize) -> (usize, usize) {
+        let max = (self.data.len() - i).min(MAX_MATCH);
+        let mut best = (0, 0);
+        self.candidates(i, chain, |candidate| {
+            // a longer match has to get past the end of the best one
+            if best.0 > 0 && self.data[candidate + best.0] != self.data[i + best.0] {
+                return true;
+            }
+            let length = self.match_length(candidate, i, max);
+            if length > best.0 {
+                best = (length, i - candidate);
+            }
+            best.0 < nice.min(max)
+        });
+        if best.0 < MIN_MATCH || (best.0 == MIN_MATCH && best.1 > TOO_FAR) {
+            return (0, 0);
+        }
+        best
+    }
+    // Every useful match for `i`: (length, distance) pairs with growing lengths,
+    // each the nearest match of at least that length
+    fn all(&self, i: usize, out: &mut Vec<(u16, u16)>) {
+        let max = (self.data.len() - i).min(MAX_MATCH);
+        let mut best = MIN_MATCH - 1;
+        self.candidates(i, EXHAUSTIVE_CHAIN, |candidate| {
+            if best >= MIN_MATCH && self.data[candidate + best] != self.data[i + best] {
+                return true;
+            }
+            let length = self.match_length(candidate, i, max);
+            if length > best {
+                best = length;
+                out.push((length as u16, (i - candidate) as u16));
+            }
+            best < max
+        });
+    }
+}
+// Greedy or lazy LZ77 of data[start..], data[..start] being earlier input that
+// matches can still refer to
+fn lz77(data: &[u8], start: usize, level: u8) -> Vec<Token> {
+    let config = &CONFIGS[level as usize];
+    let mut matcher = Matcher::new(data);
+    (start.saturating_sub(WINDOW)..start).for_each(|i| matcher.insert(i));
+    let mut tokens = Vec::new();
+    let mut i = start;
+    if level <= 3 {
+        while i < data.len() {
+            let (length, distance) = matcher.longest(i, config.chain, config.nice);
+            matcher.insert(i);
+            if length == 0 {
+                tokens.push(Token::Literal(data[i]));
+                i += 1;
+                continue;
+            }
+            tokens.push(Token::Match(length as u16, distance as u16));
+            if length <= config.lazy {
+                (i + 1..i + length).for_each(|j| matcher.insert(j));
+            }
+            i += length;
+        }
+        return tokens;
+    }
+    // a match found at i - 1, kept back in case i has a longer one
+    let mut previous: Option<(usize, usize)> = None;
+    while i < data.len() {
+        let current = match previous {
+            Some((length, _)) if length >= config.lazy => (0, 0),
+            Some((length, _)) if length >= config.good => matcher.longest(i, config.chain / 4, config.nice),
+            _ => matcher.longest(i, config.chain, config.nice),
+        };
+        matcher.insert(i);
+        if let Some((length, distance)) = previous {
+            if length >= current.0 {
+                tokens.push(Token::Match(length as u16, distance as u16));
+                (i + 1..i - 1 + length).for_each(|j| matcher.insert(j));
+                i = i - 1 + length;
+                previous = None;
+                continue;
+            }
+            tokens.push(Token::Literal(data[i - 1]));
+        }
+        if current.0 > 0 {
+            previous = Some(current);
+        } else {
+            tokens.push(Token::Literal(data[i]));
+            previous = None;
+        }
+        i += 1;
+    }
+    if let Some((length, distance)) = previous {
+        tokens.push(Token::Match(length as u16, distance as u16));
+    }
+    tokens
+}
+// Bits per literal/length and distance symbol, used to price a parse
+struct Costs {
+    literals: [f64; 286],
+    distances: [f64; 30],
+}
+impl Costs {
+    fn fixed() -> Self {
+        let (literals, distances) = fixed_lengths();
+        Costs {
+            literals: std::array::from_fn(|symbol| literals[symbol] as f64),
+            distances: distances.map(|length| length as f64),
+        }
+    }
+    // -log2 of each symbol's frequency in `tokens`
+    fn from_tokens(tokens: &[Token]) -> Self {
+        let (literals, distances) = frequencies(tokens);
+        fn entropy<const N: usize>(counts: &[u32; N]) -> [f64; N] {
+            let total = counts.iter().sum::<u32>().max(1) as f64;
+            counts.map(|count| if count == 0 { total.log2() + 1.0 } else { (total / count as f64).log2() })
+        }
+        Costs { literals: entropy(&literals), distances: entropy(&distances) }
+    }
+    fn literal(&self, byte: u8) -> f64 {
+        self.literals[byte as usize]
+    }
+    fn matched(&self, length: usize, distance: usize) -> f64 {
+        let (length_code, distance_code) = (length_code(length), distance_code(distance));
+        self.literals[257 + length_code] + LENGTH_EXTRA[length_code] as f64
+            + self.distances[distance_code] + DISTANCE_EXTRA[distance_code] as f64
+    }
+}
+// Shortest path through data[start..] under `costs`, given every position's matches
+fn optimal_parse(data: &[u8], start: usize, matches: &[Vec<(u16, u16)>], costs: &Costs) -> Vec<Token> {
+    let n = data.len() - start;
+    let mut cost = vec![f64::INFINITY; n + 1];
+    let mut step = vec![(0u16, 0u16); n + 1];
+    cost[0] = 0.0;
+    for k in 0..n {
+        let here = cost[k];
+        let literal = here + costs.literal(data[start + k]);
+        if literal < cost[k + 1] {
+            cost[k + 1] = literal;
+            step[k + 1] = (1, 0);
+        }
+        let mut consider = |length: usize, distance: usize| {
+            let total = here + costs.matched(length, distance);
+            if total < cost[k + length] {
+                cost[k + length] = total;
+                step[k + length] = (length as u16, distance as u16);
+            }
+        };
+        // a run as long as a match can be is always taken whole
+        if let Some(&(length, distance)) = matches[k].last()
+            && length as usize == MAX_MATCH
+        {
+            consider(MAX_MATCH, distance as usize);
+            continue;
+        }
+        let mut shortest = MIN_MATCH;
+        for &(length, distance) in &matches[k] {
+            for length in shortest..=length as usize {
+                consider(length, distance as usize);
+            }
+            shortest = length as usize + 1;
+        }
+    }
+    let mut tokens = Vec::new();
+    let mut k = n;
+    while k > 0 {
+        let (length, distance) = step[k];
+        if length == 1 {
+            tokens.push(Token::Literal(data[start + k - 1]));
+        } else {
+            tokens.push(Token::Match(length, distance));
+        }
+        k -= length as usize;
+    }
+    tokens.reverse();
+    tokens
+}
+// Zopfli's approach: parse optimally under fixed Huffman costs, then again under
+// the costs the previous parse's statistics give, keeping the smallest
+fn exhaustive(data: &[u8], start: usize, iterations: u32, blocks: BlockType) -> Vec<Token> {
+    let mut matcher = Matcher::new(data);
+    (start.saturating_sub(WINDOW)..start).for_each(|i| matcher.insert(i));
+    let matches: Vec<Vec<(u16, u16)>> = (start..data.len())
+        .map(|i| {
+            let mut out = Vec::new();
+            matcher.all(i, &mut out);
+            matcher.insert(i);
+            out
+        })
+        .collect();
+    let mut costs = Costs::fixed();
+    let mut best: Option<(usize, Vec<Token>)> = None;
+    for _ in 0..iterations {
+        let tokens = optimal_parse(data, start, &matches, &costs);
+        let size = Block::new(&tokens, &data[start..], blocks).size();
+        costs = Costs::from_tokens(&tokens);
+        if best.as_ref().is_none_or(|(best_size, _)| size < *best_size) {
+            best = Some((size, tokens));
+        }
+    }
+    best.map(|(_, tokens)| tokens).unwrap_or_default()
+}
+fn frequencies(tokens: &[Token]) -> ([u32; 286], [u32; 30]) {
+    let mut literals = [0u32; 286];
+    let mut distances = [0u32; 30];
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => literals[byte as usize] += 1,
+            Token::Match(length, distance) => {
+                literals[257 + length_code(length as usize)] += 1;
+                distances[distance_code(distance as usize)] += 1;
+            }
+        }
+    }
+    literals[END_OF_BLOCK] += 1;
+    (literals, distances)
+}
+// all 288 symbols count, the two unused ones shift the codes of the rest
+fn fixed_lengths() -> ([u8; 288], [u8; 30]) {
+    let mut literals = [8u8; 288];
+    literals[144..256].fill(9);
+    literals[256..280].fill(7);
+    (literals, [5; 30])
+}
+// Huffman code lengths for `counts`, none longer than `max_bits`. At least two
+// symbols always get a code, decoders differ on whether one is allowed.
+fn code_lengths(counts: &[u32], max_bits: u8) -> Vec<u8> {
+    let mut counts = counts.to_vec();
+    for i in 0..counts.len() {
+        if counts.iter().filter(|&&count| count > 0).count() >= 2 {
+            break;
+        }
+        if counts[i] == 0 {
+            counts[i] = 1;
+        }
+    }
+    loop {
+        let lengths = huffman_lengths(&counts);
+        if lengths.iter().all(|&length| length <= max_bits) {
+            return lengths;
+        }
+        // flattening the counts shortens the longest codes, and at worst ends with
+        // every count 1 and a balanced tree
+        counts.iter_mut().filter(|count| **count > 0).for_each(|count| *count = count.div_ceil(2));
+    }
+}
+fn huffman_lengths(counts: &[u32]) -> Vec<u8> {
+    let mut parents: Vec<usize> = Vec::new();
+    let mut heap = BinaryHeap::new();
+    let mut leaves = Vec::new();
+    for (symbol, &count) in counts.iter().enumerate() {
+        if count > 0 {
+            leaves.push(symbol);
+            heap.push(Reverse((count as u64, parents.len())));
+            parents.push(usize::MAX);
+        }
+    }
+    while heap.len() > 1 {
+        let Reverse((a, left)) = heap.pop().unwrap();
+        let Reverse((b, right)) = heap.pop().unwrap();
+        let node = parents.len();
+        parents.push(usize::MAX);
+        parents[left] = node;
+        parents[right] = node;
+        heap.push(Reverse((a + b, node)));
+    }
+    let mut lengths = vec![0u8; counts.len()];
+    for (node, &symbol) in leaves.iter().enumerate() {
+        let mut depth = 0;
+        let mut current = node;
+        while parents[current] != usize::MAX {
+            current = parents[current];
+            depth += 1;
+        }
+        lengths[symbol] = depth.min(u8::MAX as usize) as u8;
+    }
+    lengths
+}
+// Canonical codes for `lengths`, bit reversed since Huffman codes are packed
+// starting from their most significant bit
+fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut counts = [0u16; 16];
+    lengths.iter().filter(|&&length| length > 0).for_each(|&length| counts[length as usize] += 1);
+    let mut next = [0u16; 16];
+    for length in 1..16 {
+        next[length] = (next[length - 1] + counts[length - 1]) << 1;
+    }
+    lengths
+        .iter()
+        .map(|&length| {
+            if length == 0 {
+                return 0;
+            }
+            let code = next[length as usize];
+            next[length as usize] += 1;
+            code.reverse_bits() >> (16 - length)
+        })
+        .collect()
+}
+// Run-length encodes code lengths as code length symbols and their extra bits
+fn run_lengths(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < lengths.len() {
+        let length = lengths[i];
+        let mut run = lengths[i..].iter().take_while(|&&l| l == length).count();
+        i += run;
+        if length == 0 {
+            while run >= 11 {
+                let n = run.min(138);
+                out.push((18, (n - 11) as u8));
+                run -= n;
+            }
+            if run >= 3 {
+                out.push((17, (run - 3) as u8));
+                run = 0;
+            }
+        } else {
+            out.push((length, 0));
+            run -= 1;
+            while run >= 3 {
+                let n = run.min(6);
+                out.push((16, (n - 3) as u8));
+                run -= n;
+            }
+        }
+        out.extend(std::iter::repeat_n((length, 0), run));
+    }
+    out
+}
+fn extra_bits(symbol: u8) -> u32 {
+    match symbol {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    }
+}
+// The code lengths of a dynamic block, as stored in its header
+struct DynamicHeader {
+    literal_count: usize,
+    distance_count: usize,
+    code_length_count: usize,
+    code_lengths: Vec<u8>,
+    runs: Vec<(u8, u8)>,
+}
+impl DynamicHeader {
+    fn new(literals: &[u8], distances: &[u8]) -> Self {
+        let used = |lengths: &[u8], minimum: usize| {
+            lengths.iter().rposition(|&length| length > 0).map_or(minimum, |last| (last + 1).max(minimum))
+        };
+        let literal_count = used(literals, 257);
+        let distance_count = used(distances, 1);
+        let all: Vec<u8> = literals[..literal_count].iter().chain(&distances[..distance_count]).copied().collect();
+        let runs = run_lengths(&all);
+        let mut counts = [0u32; 19];
+        runs.iter().for_each(|&(symbol, _)| counts[symbol as usize] += 1);
+        let code_lengths = code_lengths(&counts, 7);
+        let code_length_count =
+            CODE_LENGTH_ORDER.iter().rposition(|&symbol| code_lengths[symbol] > 0).map_or(4, |last| (last + 1).max(4));
+        DynamicHeader { literal_count, distance_count, code_length_count, code_lengths, runs }
+    }
+    fn size(&self) -> usize {
+        let runs: usize =
+            self.runs.iter().map(|&(symbol, _)| (self.code_lengths[symbol as usize] as u32 + extra_bits(symbol)) as usize).sum();
+        5 + 5 + 4 + 3 * self.code_length_count + runs
+    }
+    fn write(&self, out: &mut BitWriter) {
+        out.write((self.literal_count - 257) as u32, 5);
+        out.write((self.distance_count - 1) as u32, 5);
+        out.write((self.code_length_count - 4) as u32, 4);
+        for &symbol in &CODE_LENGTH_ORDER[..self.code_length_count] {
+            out.write(self.code_lengths[symbol] as u32, 3);
+        }
+        let codes = canonical_codes(&self.code_lengths);
+        for &(symbol, extra) in &self.runs {
+            out.write(codes[symbol as usize] as u32, self.code_lengths[symbol as usize] as u32);
+            out.write(extra as u32, extra_bits(symbol));
+        }
+    }
+}
+enum Encoding {
+    Stored,
+    Fixed,
+    Dynamic(DynamicHeader, Vec<u8>, Vec<u8>),
+}
+// One block's tokens and the input they came from, in whichever encoding was picked
+struct Block<'a> {
+    tokens: &'a [Token],
+    raw: &'a [u8],
+    encoding: Encoding,
+    size: usize,
+}
+impl<'a> Block<'a> {
+    fn new(tokens: &'a [Token], raw: &'a [u8], blocks: BlockType) -> Self {
+        let (literal_counts, distance_counts) = frequencies(tokens);
+        let data_size = |literals: &[u8], distances: &[u8]| -> usize {
+            let symbols: usize = literal_counts.iter().zip(literals).map(|(&count, &length)| count as usize * length as usize).sum();
+            let distances: usize = distance_counts.iter().zip(distances).map(|(&count, &length)| count as usize * length as usize).sum();
+            let extra: usize = tokens
+                .iter()
+                .map(|token| match *token {
+                    Token::Literal(_) => 0,
+                    Token::Match(length, distance) => {
+                        (LENGTH_EXTRA[length_code(length as usize)] + DISTANCE_EXTRA[distance_code(distance as usize)]) as usize
+                    }
+                })
+                .sum();
+            symbols + distances + extra
+        };
+        let (fixed_literals, fixed_distances) = fixed_lengths();
+        let fixed = 3 + data_size(&fixed_literals, &fixed_distances);
+        let literals = code_lengths(&literal_counts, 15);
+        let distances = code_lengths(&distance_counts, 15);
+        let header = DynamicHeader::new(&literals, &distances);
+        let dynamic = 3 + header.size() + data_size(&literals, &distances);
+        // block headers, the worst case alignment and the lengths, then the bytes
+        let stored = raw.len().div_ceil(MAX_STORED).max(1) * (3 + 7 + 32) + 8 * raw.len();
+        let (encoding, size) = match blocks {
+            BlockType::Fixed => (Encoding::Fixed, fixed),
+            BlockType::Dynamic => (Encoding::Dynamic(header, literals, distances), dynamic),
+            BlockType::Auto if stored < fixed.min(dynamic) => (Encoding::Stored, stored),
+            BlockType::Auto if fixed <= dynamic => (Encoding::Fixed, fixed),
+            BlockType::Auto => (Encoding::Dynamic(header, literals, distances), dynamic),
+        };
+        Block { tokens, raw, encoding, size }
+    }
+    fn stored(raw: &'a [u8]) -> Self {
+        Block { tokens: &[], raw, encoding: Encoding::Stored, size: 0 }
+    }
+    // in bits, stored blocks assuming the worst alignment
+    fn size(&self) -> usize {
+        self.size
+    }
+    fn write(&self, out: &mut BitWriter, last: bool) {
+        let (literals, distances) = match &self.encoding {
+            Encoding::Stored => return write_stored(out, self.raw, last),
+            Encoding::Fixed => {
+                out.write(last as u32 | 1 << 1, 3);
+                let (literals, distances) = fixed_lengths();
+                (literals.to_vec(), distances.to_vec())
+            }
+            Encoding::Dynamic(header, literals, distances) => {
+                out.write(last as u32 | 2 << 1, 3);
+                header.write(out);
+                (literals.clone(), distances.clone())
+            }
+        };
+        let literal_codes = canonical_codes(&literals);
+        let distance_codes = canonical_codes(&distances);
+        let symbol = |out: &mut BitWriter, symbol: usize| out.write(literal_codes[symbol] as u32, literals[symbol] as u32);
+        for token in self.tokens {
+            match *token {
+                Token::Literal(byte) => symbol(out, byte as usize),
+                Token::Match(length, distance) => {
+                    let (length, distance) = (length as usize, distance as usize);
+                    let code = length_code(length);
+                    symbol(out, 257 + code);
+                    out.write((length - LENGTH_BASE[code] as usize) as u32, LENGTH_EXTRA[code] as u32);
+                    let code = distance_code(distance);
+                    out.write(distance_codes[code] as u32, distances[code] as u32);
+                    out.write((distance - DISTANCE_BASE[code] as usize) as u32, DISTANCE_EXTRA[code] as u32);
+                }
+            }
+        }
+        symbol(out, END_OF_BLOCK);
+    }
+}
+fn write_stored(out: &mut BitWriter, raw: &[u8], last: bool) {
+    let pieces: Vec<&[u8]> = if raw.is_empty() { vec![&[]] } else { raw.chunks(MAX_STORED).collect() };
+    for (i, piece) in pieces.iter().enumerate() {
+        out.write((last && i == pieces.len() - 1) as u32, 3);
+        out.align();
+        let length = piece.len() as u16;
+        out.write(length as u32, 16);
+        out.write(!length as u32, 16);
+        out.out.extend_from_slice(piece);
+    }
+}
+// Streams a zlib stream to `writer`. Nothing is complete until `finish`.
+pub struct ZlibEncoder<W: Write> {
+    writer: W,
+    compression: Compression,
+    out: BitWriter,
+    // the last WINDOW bytes of already compressed input, followed by pending input
+    data: Vec<u8>,
+    history: usize,
+    adler: u32,
+}
+impl<W: Write> ZlibEncoder<W> {
+    pub fn new(writer: W, compression: Compression) -> Self {
+        let level = match compression.level {
+            0..=1 => 0,
+            2..=5 => 1,
+            6 => 2,
+            _ => 3,
+        };
+        // 32K window, deflate, and the check bits that make the header a multiple of 31
+        let cmf = 0x78u16;
+        let flg = level << 6;
+        let flg = flg + (31 - (cmf * 256 + flg) % 31) % 31;
+        let out = BitWriter { out: vec![cmf as u8, flg as u8], bits: 0, count: 0 };
+        ZlibEncoder { writer, compression, out, data: Vec::new(), history: 0, adler: 1 }
+    }
+    fn compress(&mut self, end: usize, last: bool) -> io::Result<()> {
+        let data = &self.data[..end];
+        let raw = &data[self.history..];
+        let Compression { level, blocks, iterations } = self.compression;
+        let tokens = match (level, iterations) {
+            (0, _) => Vec::new(),
+            (_, 0) => lz77(data, self.history, level),
+            _ => exhaustive(data, self.history, iterations, blocks),
+        };
+        let block = if level == 0 { Block::stored(raw) } else { Block::new(&tokens, raw, blocks) };
+        block.write(&mut self.out, last);
+        self.writer.write_all(&self.out.out)?;
+        self.out.out.clear();
+        let keep = end.saturating_sub(WINDOW);
+        self.data.drain(..keep);
+        self.history = end - keep;
+        Ok(())
+    }
+    // Compresses what's left and writes the checksum, returning the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.compress(self.data.len(), true)?;
+        self.out.align();
+        self.out.out.extend(self.adler.to_be_bytes());
+        self.writer.write_all(&self.out.out)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+impl<W: Write> Write for ZlibEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.data.extend_from_slice(buf);
+        self.adler = adler32(self.adler, buf);
+        while self.data.len() - self.history >= BLOCK {
+            self.compress(self.history + BLOCK, false)?;
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+pub fn deflate(data: &[u8], compression: Compression) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), compression);
+    encoder.write_all(data).expect("writing to a Vec can't fail");
+    encoder.finish().expect("writing to a Vec can't fail")
+}
+// Collects what's written to it as IDAT chunks of at most `max_length` bytes
+pub struct IdatWriter {
+    max_length: usize,
+    chunks: Vec<Chunk>,
+    current: Vec<u8>,
+}
+impl IdatWriter {
+    // what libpng writes by default
+    pub const DEFAULT_LENGTH: usize = 8192;
+    pub fn new(max_length: usize) -> Self {
+        let max_length = max_length.clamp(1, Chunk::MAX_LENGTH as usize);
+        IdatWriter { max_length, chunks: Vec::new(), current: Vec::new() }
+    }
+    // There's always at least one chunk, even if nothing was written
+    pub fn into_chunks(mut self) -> Vec<Chunk> {
+        if !self.current.is_empty() || self.chunks.is_empty() {
+            self.chunks.push(Chunk::new(ChunkType::IDAT, self.current));
+        }
+        self.chunks
+    }
+}
+impl Default for IdatWriter {
+    fn default() -> Self {
+        IdatWriter::new(Self::DEFAULT_LENGTH)
+    }
+}
+impl Write for IdatWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.max_length - self.current.len());
+        self.current.extend_from_slice(&buf[..n]);
+        if self.current.len() == self.max_length {
+            let data = std::mem::take(&mut self.current);
+            self.chunks.push(Chunk::new(ChunkType::IDAT, data));
+        }
+        Ok(n)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+pub fn deflate_to_idat(data: &[u8], compression: Compression, max_length: usize) -> Vec<Chunk> {
+    let mut encoder = ZlibEncoder::new(IdatWriter::new(max_length), compression);
+    encoder.write_all(data).expect("writing to an IdatWriter can't fail");
+    encoder.finish().expect("writing to an IdatWriter can't fail").into_chunks()
+}
+impl Png {
+    // Inflates the image data and deflates it again, leaving the filtering as it was
+    pub fn recompress(&mut self, compression: Compression, max_idat_length: usize, limits: Limits) -> Result<(), DecodeError> {
+        if self.position_of(ChunkType::IDAT).is_none() {
+            return Err(PngError::MissingChunk(ChunkType::IDAT).into());
+        }
+        let data = ZlibDecoder::new(self.chunks_by_type(ChunkType::IDAT).map(|chunk| chunk.data()), limits).decode_to_vec()?;
+        self.replace_image_data(deflate_to_idat(&data, compression, max_idat_length))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inflate::{inflate, ZlibDecoder};
+    use crate::limits::Limits;
+    use proptest::prelude::*;
+
+    fn samples() -> Vec<Vec<u8>> {
+        let mut state = 12345u32;
+        let noise: Vec<u8> = (0..5000)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect();
+        let text = b"It was the best of times, it was the worst of times, it was the age of wisdom".repeat(40);
+        let gradient: Vec<u8> = (0..200_000u32).map(|i| (i / 7 % 256) as u8 ^ (i % 3) as u8).collect();
+        vec![Vec::new(), vec![42], vec![0; 100_000], noise, text, gradient]
+    }
+
+    fn round_trip(compression: Compression) {
+        for data in samples() {
+            let compressed = deflate(&data, compression);
+            assert_eq!(inflate(&compressed, Limits::none()).unwrap(), data, "{:?}", compression);
+        }
+    }
+
+    #[test]
+    fn test_deflate_round_trip_levels() {
+        for level in 0..=Compression::MAX_LEVEL {
+            round_trip(Compression::level(level));
+        }
+    }
+
+    #[test]
+    fn test_deflate_round_trip_block_types() {
+        for blocks in [BlockType::Fixed, BlockType::Dynamic] {
+            round_trip(Compression::level(1).blocks(blocks));
+            round_trip(Compression::level(6).blocks(blocks));
+        }
+        round_trip(Compression::exhaustive(3).blocks(BlockType::Dynamic));
+    }
+
+    #[test]
+    fn test_deflate_sizes() {
+        let text = &samples()[4];
+        let size = |compression| deflate(text, compression).len();
+        assert!(size(Compression::level(0)) > text.len());
+        assert!(size(Compression::level(9)) <= size(Compression::level(1)));
+        assert!(size(Compression::exhaustive(5)) <= size(Compression::level(9)));
+        let auto = size(Compression::default());
+        assert!(auto <= size(Compression::default().blocks(BlockType::Fixed)));
+        assert!(auto <= size(Compression::default().blocks(BlockType::Dynamic)));
+        // incompressible data is stored rather than grown
+        let noise = &samples()[3];
+        assert!(deflate(noise, Compression::default()).len() <= noise.len() + 2 + 5 + 4);
+    }
+
+    #[test]
+    fn test_zlib_header() {
+        for level in [0, 2, 6, 9] {
+            let compressed = deflate(b"", Compression::level(level));
+            assert_eq!(compressed[0], 0x78);
+            assert!((compressed[0] as u16 * 256 + compressed[1] as u16).is_multiple_of(31));
+        }
+    }
+
+    #[test]
+    fn test_code_lengths() {
+        // fibonacci counts make the deepest possible tree
+        let mut counts = vec![1u32, 1];
+        while counts.len() < 30 {
+            counts.push(counts[counts.len() - 1] + counts[counts.len() - 2]);
+        }
+        let lengths = code_lengths(&counts, 7);
+        assert!(lengths.iter().all(|&length| (1..=7).contains(&length)));
+        let kraft: f64 = lengths.iter().map(|&length| 0.5f64.powi(length as i32)).sum();
+        assert!(kraft <= 1.0);
+        assert_eq!(code_lengths(&[0, 0, 5, 0], 15), [1, 0, 1, 0]);
+        assert_eq!(canonical_codes(&[2, 1, 3, 3]), [0b01, 0b0, 0b011, 0b111]);
+    }
+
+    #[test]
+    fn test_run_lengths() {
+        let mut lengths = vec![0; 150];
+        lengths.extend([5; 8]);
+        lengths.extend([0, 0, 3]);
+        assert_eq!(run_lengths(&lengths), [(18, 127), (18, 1), (5, 0), (16, 3), (5, 0), (0, 0), (0, 0), (3, 0)]);
+    }
+
+    #[test]
+    fn test_deflate_to_idat() {
+        let data = &samples()[5];
+        let chunks = deflate_to_idat(data, Compression::default(), 1000);
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|chunk| chunk.ctype == ChunkType::IDAT && chunk.length() <= 1000));
+        let decoded = ZlibDecoder::new(chunks.iter().map(|chunk| chunk.data()), Limits::none()).decode_to_vec().unwrap();
+        assert_eq!(&decoded, data);
+        assert_eq!(deflate_to_idat(&[], Compression::default(), 1).len(), 8);
+    }
+
+    #[test]
+    fn test_streaming_matches_one_shot() {
+        let data = &samples()[5];
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        for piece in data.chunks(777) {
+            encoder.write_all(piece).unwrap();
+        }
+        assert_eq!(encoder.finish().unwrap(), deflate(data, Compression::default()));
+    }
+
+    #[test]
+    fn test_recompress() {
+        let mut png = Png::try_from(&include_bytes!("../dice.png")[..]).unwrap();
+        let pixels = png.decode().unwrap();
+        png.recompress(Compression::level(1), 4096, Limits::default()).unwrap();
+        assert!(png.chunks_by_type(ChunkType::IDAT).all(|chunk| chunk.length() <= 4096));
+        assert_eq!(png.decode().unwrap(), pixels);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_deflate_round_trip(data in prop::collection::vec(0u8..4, 0..3000), level in 0u8..=9) {
+            let compressed = deflate(&data, Compression::level(level));
+            prop_assert_eq!(inflate(&compressed, Limits::none()).unwrap(), data);
+        }
+
+        #[test]
+        fn prop_exhaustive_round_trip(data in prop::collection::vec(0u8..4, 0..500)) {
+            let compressed = deflate(&data, Compression::exhaustive(2));
+            prop_assert_eq!(inflate(&compressed, Limits::none()).unwrap(), data);
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use thiserror::Error;
+use crate::ihdr::ColorType;
+use crate::pixels::{ConvertError, Pixels};
+
+// Compares what two images look like rather than how they're stored: both are
+// made 16-bit RGBA, with color weighted by alpha so invisible pixels match
+// whatever their color. Errors are in 8-bit levels, 0 to 255.
+#[derive(Error, Debug)]
+pub enum DiffError {
+    #[error("Images are {0}x{1} and {2}x{3}, they have to be the same size")]
+    Dimensions(u32, u32, u32, u32),
+    #[error("{0}")]
+    Convert(#[from] ConvertError),
+}
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    pub width: u32,
+    pub height: u32,
+    // pixels with a channel off by more than the tolerance
+    pub differing: u64,
+    pub max_error: f64,
+    pub mean_error: f64,
+    // in dB, infinite for identical images
+    pub psnr: f64,
+    // which pixels differ, row by row
+    pub mask: Vec<bool>,
+}
+// premultiplied 16-bit RGBA
+fn normalize(pixels: &Pixels) -> Result<Vec<u32>, ConvertError> {
+    let rgba = pixels.to_color_type(ColorType::Rgba, None)?.to_bit_depth(16)?;
+    Ok(rgba.samples.chunks(4)
+        .flat_map(|pixel| {
+            let alpha = pixel[3] as u32;
+            [0, 1, 2].map(|i| (pixel[i] as u32 * alpha + 32767) / 65535).into_iter().chain([alpha])
+        })
+        .collect())
+}
+pub fn compare(left: &Pixels, right: &Pixels, tolerance: f64) -> Result<Difference, DiffError> {
+    if (left.width, left.height) != (right.width, right.height) {
+        return Err(DiffError::Dimensions(left.width, left.height, right.width, right.height));
+    }
+    let (left_samples, right_samples) = (normalize(left)?, normalize(right)?);
+    let mut mask = Vec::with_capacity(left_samples.len() / 4);
+    let (mut max_error, mut total_error, mut squared_error) = (0.0f64, 0.0, 0.0);
+    for (a, b) in left_samples.chunks(4).zip(right_samples.chunks(4)) {
+        let mut pixel_error = 0.0f64;
+        for (&a, &b) in a.iter().zip(b) {
+            let error = a.abs_diff(b) as f64 / 65535.0;
+            pixel_error = pixel_error.max(error);
+            total_error += error;
+            squared_error += error * error;
+        }
+        max_error = max_error.max(pixel_error);
+        mask.push(pixel_error * 255.0 > tolerance);
+    }
+    let samples = left_samples.len().max(1) as f64;
+    Ok(Difference {
+        width: left.width,
+        height: left.height,
+        differing: mask.iter().filter(|&&differs| differs).count() as u64,
+        max_error: max_error * 255.0,
+        mean_error: total_error / samples * 255.0,
+        psnr: 10.0 * (samples / squared_error).log10(),
+        mask,
+    })
+}
+impl Difference {
+    // `base`, one of the compared images, faded to light gray with the pixels
+    // that differ in red
+    pub fn highlight(&self, base: &Pixels) -> Result<Pixels, ConvertError> {
+        let gray = base.to_color_type(ColorType::GrayscaleAlpha, None)?.to_bit_depth(8)?;
+        let mut samples = Vec::with_capacity(self.mask.len() * 3);
+        for (pixel, &differs) in gray.samples.chunks(2).zip(&self.mask) {
+            if differs {
+                samples.extend([255, 0, 0]);
+            } else {
+                // transparent is white, like a page
+                let shade = 255 - (255 - pixel[0]) * pixel[1] / 255;
+                samples.extend([255 - (255 - shade) / 4; 3]);
+            }
+        }
+        Ok(Pixels { width: self.width, height: self.height, color_type: ColorType::Rgb, bit_depth: 8, samples })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgba(samples: Vec<u16>) -> Pixels {
+        Pixels { width: (samples.len() / 4) as u32, height: 1, color_type: ColorType::Rgba, bit_depth: 8, samples }
+    }
+
+    #[test]
+    fn test_identical_in_any_format() {
+        let gray = Pixels { width: 2, height: 1, color_type: ColorType::Grayscale, bit_depth: 8, samples: vec![0, 0xab] };
+        let deep = gray.to_color_type(ColorType::Rgb, None).unwrap().to_bit_depth(16).unwrap();
+        let difference = compare(&gray, &deep, 0.0).unwrap();
+        assert_eq!((difference.differing, difference.max_error, difference.mean_error), (0, 0.0, 0.0));
+        assert!(difference.psnr.is_infinite());
+        // invisible pixels match whatever their color
+        assert_eq!(compare(&rgba(vec![255, 0, 0, 0]), &rgba(vec![0, 9, 0, 0]), 0.0).unwrap().differing, 0);
+    }
+
+    #[test]
+    fn test_errors() {
+        let left = rgba(vec![0, 0, 0, 255, 100, 100, 100, 255]);
+        let right = rgba(vec![0, 0, 0, 255, 110, 100, 100, 255]);
+        let difference = compare(&left, &right, 0.0).unwrap();
+        assert_eq!((difference.differing, difference.mask.clone()), (1, vec![false, true]));
+        assert!((difference.max_error - 10.0).abs() < 1e-9);
+        assert!((difference.mean_error - 10.0 / 8.0).abs() < 1e-9);
+        // one channel of eight off by 10/255
+        let expected = 10.0 * (8.0 / (10.0f64 / 255.0).powi(2)).log10();
+        assert!((difference.psnr - expected).abs() < 1e-9);
+        assert_eq!(compare(&left, &right, 10.0).unwrap().differing, 0);
+        assert_eq!(compare(&left, &right, 9.5).unwrap().differing, 1);
+        assert!(matches!(compare(&left, &rgba(vec![0; 4]), 0.0), Err(DiffError::Dimensions(2, 1, 1, 1))));
+    }
+
+    #[test]
+    fn test_highlight() {
+        let left = rgba(vec![0, 0, 0, 255, 100, 100, 100, 255, 0, 0, 0, 0]);
+        let right = rgba(vec![0, 0, 0, 255, 200, 100, 100, 255, 0, 0, 0, 0]);
+        let highlight = compare(&left, &right, 0.0).unwrap().highlight(&left).unwrap();
+        assert_eq!(highlight.samples, [192, 192, 192, 255, 0, 0, 255, 255, 255]);
+    }
+}
@@ -0,0 +1,167 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use thiserror::Error;
+use crate::chunk::{Chunk, ChunkError};
+use crate::chunk_type::{ChunkType, ChunkTypeError};
+use crate::png::Png;
+
+// Declarative description of a png. `build` recomputes lengths and crcs,
+// so only the chunk types and their data are recorded.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PngDump {
+    pub signature: String,
+    pub chunks: Vec<ChunkDump>,
+}
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ChunkDump {
+    #[serde(rename = "type")]
+    pub ctype: String,
+    pub data: ChunkData,
+}
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChunkData {
+    Text(String),
+    Hex(String),
+    Base64(String),
+}
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum BinaryEncoding {
+    #[default]
+    Hex,
+    Base64,
+}
+#[derive(Error, Debug)]
+pub enum DumpError {
+    #[error("Invalid hex data: {0}")]
+    Hex(String),
+    #[error("Invalid base64 data: {0}")]
+    Base64(#[from] base64::DecodeError),
+    #[error("Invalid chunk type: {0}")]
+    ChunkType(#[from] ChunkTypeError),
+    #[error("Invalid chunk: {0}")]
+    Chunk(#[from] ChunkError),
+    #[error("Bad Png Signature")]
+    PngSignature,
+}
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+pub fn from_hex(s: &str) -> Result<Vec<u8>, DumpError> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return Err(DumpError::Hex(s.to_string()));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| DumpError::Hex(s.to_string())))
+        .collect()
+}
+impl ChunkData {
+    // text is only used when it survives a round trip through an editor unharmed
+    pub fn encode(data: &[u8], encoding: BinaryEncoding) -> ChunkData {
+        match std::str::from_utf8(data) {
+            Ok(s) if s.chars().all(|c| !c.is_control() || matches!(c, '\n' | '\r' | '\t')) => {
+                ChunkData::Text(s.to_string())
+            }
+            _ => match encoding {
+                BinaryEncoding::Hex => ChunkData::Hex(to_hex(data)),
+                BinaryEncoding::Base64 => ChunkData::Base64(BASE64.encode(data)),
+            },
+        }
+    }
+    pub fn decode(&self) -> Result<Vec<u8>, DumpError> {
+        match self {
+            ChunkData::Text(s) => Ok(s.as_bytes().to_vec()),
+            ChunkData::Hex(s) => from_hex(s),
+            ChunkData::Base64(s) => Ok(BASE64.decode(s)?),
+        }
+    }
+}
+impl PngDump {
+    pub fn new(png: &Png, encoding: BinaryEncoding) -> PngDump {
+        let chunks = png.chunks().iter()
+            .map(|chunk| ChunkDump {
+                ctype: chunk.chunk_type().to_string(),
+                data: ChunkData::encode(chunk.data(), encoding),
+            })
+            .collect();
+        PngDump { signature: to_hex(png.header()), chunks }
+    }
+    pub fn to_png(&self) -> Result<Png, DumpError> {
+        if from_hex(&self.signature)? != Png::STANDARD_SIGNATURE {
+            return Err(DumpError::PngSignature);
+        }
+        let chunks = self.chunks.iter()
+            .map(|chunk| Ok(Chunk::try_new(ChunkType::from_str(&chunk.ctype)?, chunk.data.decode()?)?))
+            .collect::<Result<Vec<_>, DumpError>>()?;
+        Ok(Png::from_chunks(chunks))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::tests::arb_chunk;
+    use proptest::prelude::*;
+
+    fn testing_png() -> Png {
+        let chunks = vec![
+            Chunk::new(ChunkType::from_str("FrSt").unwrap(), b"I am the first chunk".to_vec()),
+            Chunk::new(ChunkType::from_str("gAMA").unwrap(), vec![0, 0, 177, 143]),
+            Chunk::new(ChunkType::from_str("LASt").unwrap(), vec![]),
+        ];
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_dump_text_and_binary() {
+        let dump = PngDump::new(&testing_png(), BinaryEncoding::Hex);
+        assert_eq!(dump.signature, "89504e470d0a1a0a");
+        assert_eq!(dump.chunks[0].data, ChunkData::Text("I am the first chunk".to_string()));
+        assert_eq!(dump.chunks[1].data, ChunkData::Hex("0000b18f".to_string()));
+    }
+
+    #[test]
+    fn test_dump_base64() {
+        let dump = PngDump::new(&testing_png(), BinaryEncoding::Base64);
+        assert_eq!(dump.chunks[1].data, ChunkData::Base64("AACxjw==".to_string()));
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let png = testing_png();
+        let json = serde_json::to_string(&PngDump::new(&png, BinaryEncoding::Hex)).unwrap();
+        let dump: PngDump = serde_json::from_str(&json).unwrap();
+        let built = dump.to_png().unwrap();
+        assert_eq!(built.as_bytes(), png.as_bytes());
+    }
+
+    #[test]
+    fn test_build_bad_hex() {
+        let dump = PngDump {
+            signature: "89504e470d0a1a0a".to_string(),
+            chunks: vec![ChunkDump { ctype: "RuSt".to_string(), data: ChunkData::Hex("0g".to_string()) }],
+        };
+        assert!(dump.to_png().is_err());
+    }
+
+    #[test]
+    fn test_build_bad_signature() {
+        let dump = PngDump { signature: "0000".to_string(), chunks: vec![] };
+        assert!(matches!(dump.to_png(), Err(DumpError::PngSignature)));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_dump_round_trip(chunks in prop::collection::vec(arb_chunk(), 0..8), base64 in any::<bool>()) {
+            let png = Png::from_chunks(chunks);
+            let encoding = if base64 { BinaryEncoding::Base64 } else { BinaryEncoding::Hex };
+            let json = serde_json::to_string(&PngDump::new(&png, encoding)).unwrap();
+            let dump: PngDump = serde_json::from_str(&json).unwrap();
+            prop_assert_eq!(dump.to_png().unwrap(), png);
+        }
+    }
+}
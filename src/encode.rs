@@ -0,0 +1,308 @@
+use clap::ValueEnum;
+use thiserror::Error;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::decode::{copy_pixel, data_size, paeth, passes, row_bytes, Image, ADAM7};
+use crate::deflate::{deflate_to_idat, Compression, IdatWriter};
+use crate::ihdr::ColorType;
+use crate::pixels::{ConvertError, Palette, Pixels};
+use crate::png::{Png, PngError};
+
+#[derive(Error, Debug)]
+pub enum EncodeError {
+    #[error("{0}")]
+    Png(#[from] PngError),
+    #[error("{0}")]
+    Convert(#[from] ConvertError),
+}
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FilterStrategy {
+    None,
+    Sub,
+    Up,
+    Average,
+    Paeth,
+    /// Per scanline, whichever filter gives the smallest sum of absolute
+    /// differences, or none for palette and sub-byte images, as libpng does
+    #[default]
+    Adaptive,
+}
+impl FilterStrategy {
+    fn filter_type(self) -> Option<u8> {
+        match self {
+            FilterStrategy::None => Some(0),
+            FilterStrategy::Sub => Some(1),
+            FilterStrategy::Up => Some(2),
+            FilterStrategy::Average => Some(3),
+            FilterStrategy::Paeth => Some(4),
+            FilterStrategy::Adaptive => None,
+        }
+    }
+}
+// How commands that rewrite the pixels encode them again
+#[derive(clap::Args, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodeOptions {
+    /// zlib compression level for the new image data, 0 to 9
+    #[arg(long, default_value_t = 6, value_parser = clap::value_parser!(u8).range(0..=9))]
+    pub level: u8,
+    #[arg(long, value_enum, default_value_t)]
+    pub filter: FilterStrategy,
+    /// Largest IDAT chunk to write
+    #[arg(long, default_value_t = IdatWriter::DEFAULT_LENGTH)]
+    pub idat_size: usize,
+}
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions { level: 6, filter: FilterStrategy::Adaptive, idat_size: IdatWriter::DEFAULT_LENGTH }
+    }
+}
+impl EncodeOptions {
+    pub fn compression(&self) -> Compression {
+        Compression::level(self.level)
+    }
+}
+// Filters `row` with filter type `kind` and appends it to `out`, filter byte
+// first. The reverse of `unfilter`, with the same `previous` and `bpp`.
+pub fn filter(kind: u8, row: &[u8], previous: &[u8], bpp: usize, out: &mut Vec<u8>) {
+    out.push(kind);
+    for i in 0..row.len() {
+        let (a, c) = if i >= bpp { (row[i - bpp], previous[i - bpp]) } else { (0, 0) };
+        let b = previous[i];
+        let predicted = match kind {
+            0 => 0,
+            1 => a,
+            2 => b,
+            3 => ((a as u16 + b as u16) / 2) as u8,
+            _ => paeth(a, b, c),
+        };
+        out.push(row[i].wrapping_sub(predicted));
+    }
+}
+// the heuristic libpng uses to pick a filter: bytes as signed, summed by size
+fn cost(filtered: &[u8]) -> u64 {
+    filtered.iter().map(|&byte| (byte as i8).unsigned_abs() as u64).sum()
+}
+impl Image {
+    // the pixels of reduced image `pass` of an interlaced image, packed into rows
+    fn reduced(&self, pass: usize, width: u32, height: u32) -> Vec<u8> {
+        let (x0, y0, dx, dy) = ADAM7[pass];
+        let bits = self.ihdr.bits_per_pixel();
+        let length = row_bytes(&self.ihdr, width);
+        let mut reduced = vec![0; length * height as usize];
+        for (y, target) in reduced.chunks_mut(length).enumerate() {
+            let source = self.row(y0 + y as u32 * dy);
+            for x in 0..width as usize {
+                copy_pixel(source, x0 as usize + x * dx as usize, target, x, bits);
+            }
+        }
+        reduced
+    }
+    // The data IDAT holds before compression: the scanlines of every pass, each
+    // filtered and preceded by its filter type
+    pub fn filtered(&self, strategy: FilterStrategy) -> Vec<u8> {
+        let ihdr = &self.ihdr;
+        let bpp = ihdr.bits_per_pixel().div_ceil(8);
+        let strategy = match strategy {
+            FilterStrategy::Adaptive if ihdr.color_type == ColorType::Indexed || ihdr.bit_depth < 8 => FilterStrategy::None,
+            strategy => strategy,
+        };
+        let mut out = Vec::with_capacity(data_size(ihdr) as usize);
+        for (pass, (width, height)) in passes(ihdr).into_iter().enumerate() {
+            if width == 0 || height == 0 {
+                continue;
+            }
+            let length = row_bytes(ihdr, width);
+            let reduced;
+            let rows = if ihdr.interlaced {
+                reduced = self.reduced(pass, width, height);
+                &reduced
+            } else {
+                &self.data
+            };
+            let mut previous = vec![0; length];
+            for row in rows.chunks(length) {
+                match strategy.filter_type() {
+                    Some(kind) => filter(kind, row, &previous, bpp, &mut out),
+                    None => {
+                        let best = (0..5)
+                            .map(|kind| {
+                                let mut candidate = Vec::with_capacity(length + 1);
+                                filter(kind, row, &previous, bpp, &mut candidate);
+                                candidate
+                            })
+                            .min_by_key(|candidate| cost(&candidate[1..]))
+                            .expect("there are five filters");
+                        out.extend(best);
+                    }
+                }
+                previous.copy_from_slice(row);
+            }
+        }
+        out
+    }
+    pub fn to_idat(&self, options: &EncodeOptions) -> Vec<Chunk> {
+        deflate_to_idat(&self.filtered(options.filter), options.compression(), options.idat_size)
+    }
+}
+// Whether a chunk still applies once the pixels are replaced. Chunks about what
+// samples mean stay, ones holding samples or palette indices only while the
+// sample format does, and anything else only if it's marked safe to copy.
+fn survives(ctype: ChunkType, same_format: bool, indexed: bool) -> bool {
+    match ctype {
+        ChunkType::IHDR | ChunkType::IDAT | ChunkType::IEND => true,
+        ChunkType::GAMA | ChunkType::CHRM | ChunkType::SRGB | ChunkType::ICCP => true,
+        ChunkType::PLTE => same_format || indexed,
+        ChunkType::TRNS | ChunkType::BKGD | ChunkType::SBIT => same_format,
+        ctype => !ctype.is_critical() && ctype.is_safe_to_copy(),
+    }
+}
+impl Png {
+    // A new png holding just `pixels`, and `palette` as PLTE and tRNS, which
+    // indexed pixels need
+    pub fn from_pixels(pixels: &Pixels, palette: Option<&Palette>, options: &EncodeOptions) -> Result<Png, EncodeError> {
+        let image = pixels.to_image()?;
+        let mut chunks = vec![image.ihdr.to_chunk()];
+        match palette {
+            Some(palette) => chunks.extend(palette.to_chunks()),
+            None if pixels.color_type == ColorType::Indexed => return Err(ConvertError::MissingPalette.into()),
+            None => {}
+        }
+        chunks.extend(image.to_idat(options));
+        chunks.push(Chunk::new(ChunkType::IEND, Vec::new()));
+        Ok(Png::from_chunks(chunks))
+    }
+    // Replaces the image with `pixels`, keeping the interlacing and the chunks
+    // that still apply to it. An indexed image keeps the existing palette, so
+    // its indices have to be into that.
+    pub fn set_pixels(&mut self, pixels: &Pixels, options: &EncodeOptions) -> Result<(), EncodeError> {
+        let old = self.ihdr()?;
+        let mut image = pixels.to_image()?;
+        image.ihdr.interlaced = old.interlaced;
+        let indexed = pixels.color_type == ColorType::Indexed;
+        let same_format = old.color_type == pixels.color_type && (indexed || old.bit_depth == pixels.bit_depth);
+        self.retain(|chunk| survives(*chunk.chunk_type(), same_format, indexed));
+        self.replace_image_data(image.to_idat(options))?;
+        let index = self.position_of(ChunkType::IHDR).ok_or(PngError::MissingChunk(ChunkType::IHDR))?;
+        self.remove_nth(ChunkType::IHDR, 0)?;
+        self.insert_chunk_at(index, image.ihdr.to_chunk())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::unfilter;
+    use crate::ihdr::Ihdr;
+    use crate::pixels::tests::pattern;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_from_pixels() {
+        let gray = pattern(5, 3, ColorType::Grayscale, 4);
+        let png = Png::from_pixels(&gray, None, &EncodeOptions::default()).unwrap();
+        assert_eq!(png.pixels().unwrap(), gray);
+        let indexed = pattern(2, 2, ColorType::Indexed, 1);
+        let palette = Palette { colors: vec![[0, 0, 0], [255, 0, 0]], alpha: vec![0] };
+        let png = Png::from_pixels(&indexed, Some(&palette), &EncodeOptions::default()).unwrap();
+        assert_eq!((png.pixels().unwrap(), png.palette()), (indexed.clone(), Some(palette)));
+        assert!(matches!(Png::from_pixels(&indexed, None, &EncodeOptions::default()), Err(EncodeError::Convert(ConvertError::MissingPalette))));
+    }
+
+    fn png(pixels: &Pixels, interlaced: bool, chunks: Vec<Chunk>) -> Png {
+        let mut image = pixels.to_image().unwrap();
+        image.ihdr.interlaced = interlaced;
+        let mut all = vec![image.ihdr.to_chunk()];
+        all.extend(chunks);
+        all.extend(image.to_idat(&EncodeOptions::default()));
+        all.push(Chunk::new(ChunkType::IEND, Vec::new()));
+        Png::from_chunks(all)
+    }
+
+    #[test]
+    fn test_filter_undoes_unfilter() {
+        let previous = [10, 200, 30, 40, 250, 60];
+        let row = [1, 2, 3, 4, 255, 0];
+        for kind in 0..5 {
+            let mut filtered = Vec::new();
+            filter(kind, &row, &previous, 2, &mut filtered);
+            assert_eq!(filtered[0], kind);
+            unfilter(kind, &mut filtered[1..], &previous, 2).unwrap();
+            assert_eq!(filtered[1..], row);
+        }
+    }
+
+    #[test]
+    fn test_adaptive_filtering() {
+        // a horizontal gradient is all ones after sub, and repeating it all zeros after up
+        let ihdr = Ihdr { width: 100, height: 2, bit_depth: 8, color_type: ColorType::Grayscale, interlaced: false };
+        let image = Image { ihdr, data: (0..100).chain(0..100).collect() };
+        let filtered = image.filtered(FilterStrategy::Adaptive);
+        assert_eq!((filtered[0], filtered[101]), (1, 2));
+        // palette images aren't filtered
+        let ihdr = Ihdr { color_type: ColorType::Indexed, ..ihdr };
+        let filtered = Image { ihdr, ..image }.filtered(FilterStrategy::Adaptive);
+        assert_eq!((filtered[0], filtered[101]), (0, 0));
+    }
+
+    #[test]
+    fn test_interlaced_round_trip() {
+        for (color_type, bit_depth) in [(ColorType::Grayscale, 1), (ColorType::Indexed, 4), (ColorType::Rgba, 16)] {
+            let pixels = pattern(13, 9, color_type, bit_depth);
+            let png = png(&pixels, true, Vec::new());
+            assert!(png.ihdr().unwrap().interlaced);
+            assert_eq!(png.pixels().unwrap(), pixels);
+        }
+    }
+
+    #[test]
+    fn test_set_pixels_keeps_chunks_that_apply() {
+        let gray = pattern(4, 4, ColorType::Grayscale, 8);
+        let chunk = |ctype: &str, data: &[u8]| Chunk::new(ctype.parse().unwrap(), data.to_vec());
+        let mut png = png(&gray, true, vec![
+            chunk("gAMA", &[0, 0, 0xb1, 0x8f]),
+            chunk("sBIT", &[5]),
+            chunk("tRNS", &[0, 7]),
+            chunk("tEXt", b"Title\0dice"),
+            chunk("tIME", &[7, 0xe6, 1, 1, 0, 0, 0]),
+            chunk("prVT", b"unsafe"),
+            chunk("prVt", b"safe"),
+        ]);
+        let smaller = Pixels { width: 2, height: 1, samples: vec![1, 2], ..gray.clone() };
+        png.set_pixels(&smaller, &EncodeOptions::default()).unwrap();
+        let types: Vec<String> = png.chunks().iter().map(|chunk| chunk.chunk_type().to_string()).collect();
+        assert_eq!(types, ["IHDR", "gAMA", "sBIT", "tRNS", "tEXt", "prVt", "IDAT", "IEND"]);
+        assert!(png.ihdr().unwrap().interlaced);
+        assert_eq!(png.pixels().unwrap(), smaller);
+        // a new format loses the chunks about the old samples
+        png.set_pixels(&smaller.to_color_type(ColorType::Rgb, None).unwrap(), &EncodeOptions::default()).unwrap();
+        let types: Vec<String> = png.chunks().iter().map(|chunk| chunk.chunk_type().to_string()).collect();
+        assert_eq!(types, ["IHDR", "gAMA", "tEXt", "prVt", "IDAT", "IEND"]);
+    }
+
+    proptest! {
+        #[test]
+        fn test_encode_decode_round_trip(
+            width in 1u32..20,
+            height in 1u32..20,
+            format in 0usize..5,
+            interlaced: bool,
+            strategy in prop::sample::select(FilterStrategy::value_variants()),
+        ) {
+            let (color_type, bit_depth) = [
+                (ColorType::Grayscale, 2),
+                (ColorType::Rgb, 8),
+                (ColorType::Indexed, 8),
+                (ColorType::GrayscaleAlpha, 16),
+                (ColorType::Rgba, 8),
+            ][format];
+            let pixels = pattern(width, height, color_type, bit_depth);
+            let mut image = pixels.to_image().unwrap();
+            image.ihdr.interlaced = interlaced;
+            let mut all = vec![image.ihdr.to_chunk()];
+            all.extend(image.to_idat(&EncodeOptions { filter: strategy, ..Default::default() }));
+            all.push(Chunk::new(ChunkType::IEND, Vec::new()));
+            prop_assert_eq!(Png::from_chunks(all).pixels().unwrap(), pixels);
+        }
+    }
+}
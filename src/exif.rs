@@ -0,0 +1,255 @@
+use std::fmt;
+use thiserror::Error;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::decode::DecodeError;
+use crate::encode::{EncodeError, EncodeOptions};
+use crate::pixels::Pixels;
+use crate::png::Png;
+
+// Just enough of EXIF to find the orientation tag: eXIf holds a TIFF header
+// followed by directories of 12-byte entries, and orientation is in the first.
+#[derive(Error, Debug)]
+pub enum ExifError {
+    #[error("eXIf doesn't start with a TIFF header")]
+    Header,
+    #[error("eXIf ends before offset {0}")]
+    Truncated(usize),
+    #[error("EXIF orientation has type {0}, expected SHORT (3)")]
+    Type(u16),
+    #[error("EXIF orientation {0} isn't one of 1 to 8")]
+    Orientation(u16),
+    #[error("{0}")]
+    Decode(#[from] DecodeError),
+    #[error("{0}")]
+    Encode(#[from] EncodeError),
+}
+const ORIENTATION: u16 = 0x0112;
+const SHORT: u16 = 3;
+// How the stored pixels have to be turned to be shown the right way up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    Normal = 1,
+    FlipHorizontal = 2,
+    Rotate180 = 3,
+    FlipVertical = 4,
+    Transpose = 5,
+    Rotate90 = 6,
+    Transverse = 7,
+    Rotate270 = 8,
+}
+impl TryFrom<u16> for Orientation {
+    type Error = ExifError;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        Ok(match value {
+            1 => Orientation::Normal,
+            2 => Orientation::FlipHorizontal,
+            3 => Orientation::Rotate180,
+            4 => Orientation::FlipVertical,
+            5 => Orientation::Transpose,
+            6 => Orientation::Rotate90,
+            7 => Orientation::Transverse,
+            8 => Orientation::Rotate270,
+            _ => return Err(ExifError::Orientation(value)),
+        })
+    }
+}
+impl fmt::Display for Orientation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            Orientation::Normal => "normal",
+            Orientation::FlipHorizontal => "flip left to right",
+            Orientation::Rotate180 => "rotate 180 degrees",
+            Orientation::FlipVertical => "flip top to bottom",
+            Orientation::Transpose => "transpose",
+            Orientation::Rotate90 => "rotate 90 degrees clockwise",
+            Orientation::Transverse => "transverse",
+            Orientation::Rotate270 => "rotate 270 degrees clockwise",
+        };
+        write!(f, "{} ({})", *self as u16, description)
+    }
+}
+impl Orientation {
+    pub fn apply(self, pixels: &Pixels) -> Pixels {
+        match self {
+            Orientation::Normal => pixels.clone(),
+            Orientation::FlipHorizontal => pixels.flip_horizontal(),
+            Orientation::Rotate180 => pixels.rotate_180(),
+            Orientation::FlipVertical => pixels.flip_vertical(),
+            Orientation::Transpose => pixels.transpose(),
+            Orientation::Rotate90 => pixels.rotate_90(),
+            Orientation::Transverse => pixels.transverse(),
+            Orientation::Rotate270 => pixels.rotate_270(),
+        }
+    }
+    // whether width and height trade places
+    pub fn swaps_axes(self) -> bool {
+        matches!(self, Orientation::Transpose | Orientation::Rotate90 | Orientation::Transverse | Orientation::Rotate270)
+    }
+}
+// The orientation entry of an eXIf chunk and where its value is, so it can be
+// changed in place without touching the rest of the data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OrientationTag {
+    pub orientation: Orientation,
+    offset: usize,
+    big_endian: bool,
+}
+impl OrientationTag {
+    pub fn write(&self, data: &mut [u8], orientation: Orientation) {
+        let value = orientation as u16;
+        let bytes = if self.big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+        data[self.offset..self.offset + 2].copy_from_slice(&bytes);
+    }
+}
+fn read<const N: usize>(data: &[u8], offset: usize) -> Result<[u8; N], ExifError> {
+    offset.checked_add(N)
+        .and_then(|end| data.get(offset..end))
+        .map(|bytes| bytes.try_into().expect("slice has N bytes"))
+        .ok_or(ExifError::Truncated(offset.saturating_add(N)))
+}
+// None if there's no orientation tag. A leading "Exif\0\0", which belongs to
+// JPEG's APP1 segment but some writers copy into eXIf, is skipped.
+pub fn find_orientation(data: &[u8]) -> Result<Option<OrientationTag>, ExifError> {
+    let start = if data.starts_with(b"Exif\0\0") { 6 } else { 0 };
+    let tiff = &data[start..];
+    let big_endian = match tiff.get(..4) {
+        Some(b"MM\0*") => true,
+        Some(b"II*\0") => false,
+        _ => return Err(ExifError::Header),
+    };
+    let u16_at = |offset| read::<2>(tiff, offset).map(|bytes| if big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) });
+    let u32_at = |offset| read::<4>(tiff, offset).map(|bytes| if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) });
+    let directory = u32_at(4)? as usize;
+    for i in 0..u16_at(directory)? as usize {
+        let entry = directory + 2 + 12 * i;
+        if u16_at(entry)? != ORIENTATION {
+            continue;
+        }
+        let kind = u16_at(entry + 2)?;
+        if kind != SHORT {
+            return Err(ExifError::Type(kind));
+        }
+        let orientation = Orientation::try_from(u16_at(entry + 8)?)?;
+        return Ok(Some(OrientationTag { orientation, offset: start + entry + 8, big_endian }));
+    }
+    Ok(None)
+}
+impl Png {
+    pub fn orientation(&self) -> Result<Option<Orientation>, ExifError> {
+        match self.chunks_by_type(ChunkType::EXIF).next() {
+            Some(exif) => Ok(find_orientation(exif.data())?.map(|tag| tag.orientation)),
+            None => Ok(None),
+        }
+    }
+    // Turns the pixels the way the EXIF orientation says and sets it to normal,
+    // so the image looks the same whether or not a viewer reads eXIf. Returns
+    // the orientation that was applied, None if there was nothing to do.
+    pub fn apply_orientation(&mut self, options: &EncodeOptions) -> Result<Option<Orientation>, ExifError> {
+        let Some(index) = self.position_of(ChunkType::EXIF) else {
+            return Ok(None);
+        };
+        let mut data = self.chunks()[index].data().to_vec();
+        let tag = match find_orientation(&data)? {
+            Some(tag) if tag.orientation != Orientation::Normal => tag,
+            _ => return Ok(None),
+        };
+        let pixels = tag.orientation.apply(&self.pixels()?);
+        tag.write(&mut data, Orientation::Normal);
+        // changed on a copy, so a failure leaves this png as it was
+        let mut png = self.clone();
+        png.remove_all(ChunkType::EXIF);
+        png.insert_chunk_at(index, Chunk::new(ChunkType::EXIF, data)).expect("index is within the chunks");
+        if tag.orientation.swaps_axes() {
+            png.swap_physical_axes();
+        }
+        png.set_pixels(&pixels, options)?;
+        *self = png;
+        Ok(Some(tag.orientation))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ihdr::ColorType;
+
+    // TIFF with one directory of a dummy entry, then orientation
+    fn exif(big_endian: bool, kind: u16, value: u16) -> Vec<u8> {
+        let u16_bytes = |n: u16| if big_endian { n.to_be_bytes().to_vec() } else { n.to_le_bytes().to_vec() };
+        let u32_bytes = |n: u32| if big_endian { n.to_be_bytes().to_vec() } else { n.to_le_bytes().to_vec() };
+        let mut data = if big_endian { b"MM\0*".to_vec() } else { b"II*\0".to_vec() };
+        data.extend(u32_bytes(8));
+        data.extend(u16_bytes(2));
+        for (tag, kind, value) in [(0x010f, 2, 0), (ORIENTATION, kind, value)] {
+            data.extend(u16_bytes(tag));
+            data.extend(u16_bytes(kind));
+            data.extend(u32_bytes(1));
+            data.extend(u16_bytes(value));
+            data.extend([0, 0]);
+        }
+        data.extend(u32_bytes(0));
+        data
+    }
+
+    fn pixels() -> Pixels {
+        Pixels { width: 3, height: 2, color_type: ColorType::Grayscale, bit_depth: 8, samples: vec![0, 1, 2, 3, 4, 5] }
+    }
+
+    #[test]
+    fn test_find_orientation() {
+        for big_endian in [false, true] {
+            let mut data = exif(big_endian, SHORT, 6);
+            let tag = find_orientation(&data).unwrap().unwrap();
+            assert_eq!(tag.orientation, Orientation::Rotate90);
+            tag.write(&mut data, Orientation::Normal);
+            assert_eq!(data, exif(big_endian, SHORT, 1));
+        }
+        let mut prefixed = b"Exif\0\0".to_vec();
+        prefixed.extend(exif(true, SHORT, 3));
+        let tag = find_orientation(&prefixed).unwrap().unwrap();
+        tag.write(&mut prefixed, Orientation::Normal);
+        assert_eq!(prefixed[6..], exif(true, SHORT, 1));
+    }
+
+    #[test]
+    fn test_bad_orientations() {
+        assert!(matches!(find_orientation(&exif(false, SHORT, 9)), Err(ExifError::Orientation(9))));
+        assert!(matches!(find_orientation(&exif(false, 4, 6)), Err(ExifError::Type(4))));
+        assert!(matches!(find_orientation(b"JFIF"), Err(ExifError::Header)));
+        assert!(matches!(find_orientation(&exif(false, SHORT, 6)[..20]), Err(ExifError::Truncated(24))));
+        // a directory without orientation
+        let mut data = exif(false, SHORT, 6);
+        data[8] = 1;
+        assert_eq!(find_orientation(&data).unwrap(), None);
+    }
+
+    #[test]
+    fn test_apply_every_orientation() {
+        // the pixels as stored for each orientation, for `pixels()` to be what's shown
+        let upright = pixels();
+        for value in 1..=8 {
+            let orientation = Orientation::try_from(value).unwrap();
+            let inverse = match orientation {
+                Orientation::Rotate90 => Orientation::Rotate270,
+                Orientation::Rotate270 => Orientation::Rotate90,
+                orientation => orientation,
+            };
+            let stored = inverse.apply(&upright);
+            let mut png = Png::from_chunks(vec![
+                stored.ihdr().unwrap().to_chunk(),
+                Chunk::new(ChunkType::EXIF, exif(value % 2 == 0, SHORT, value)),
+                Chunk::new(ChunkType::PHYS, vec![0, 0, 0, 1, 0, 0, 0, 2, 1]),
+                Chunk::new(ChunkType::IEND, Vec::new()),
+            ]);
+            png.set_pixels(&stored, &EncodeOptions::default()).unwrap();
+            let applied = png.apply_orientation(&EncodeOptions::default()).unwrap();
+            assert_eq!(applied, (value != 1).then_some(orientation));
+            assert_eq!(png.pixels().unwrap(), upright, "orientation {}", value);
+            assert_eq!(png.orientation().unwrap(), Some(Orientation::Normal));
+            let phys = png.chunks_by_type(ChunkType::PHYS).next().unwrap().data()[3];
+            assert_eq!(phys, if orientation.swaps_axes() { 2 } else { 1 });
+        }
+    }
+}
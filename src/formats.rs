@@ -0,0 +1,597 @@
+use clap::ValueEnum;
+use std::fmt;
+use std::path::Path;
+use thiserror::Error;
+use crate::decode::DecodeError;
+use crate::encode::{EncodeError, EncodeOptions};
+use crate::ihdr::ColorType;
+use crate::limits::{LimitError, Limits};
+use crate::ops::OpsError;
+use crate::pixels::{scale, unpack, ConvertError, Pixels};
+use crate::png::{Png, PngError};
+
+// Reading and writing the simple uncompressed formats other tools exchange
+// images in: PNM and PAM from netpbm, BMP and farbfeld. Everything goes through
+// `Pixels`, with palettes looked up, so colors survive but palettes don't.
+#[derive(Error, Debug)]
+pub enum FormatError {
+    #[error("Unrecognized image format")]
+    Unrecognized,
+    #[error("Invalid {0} header: {1}")]
+    Header(Format, &'static str),
+    #[error("{0} data ends early")]
+    Truncated(Format),
+    #[error("Unsupported {0}: {1}")]
+    Unsupported(Format, String),
+    #[error("{0}")]
+    Png(#[from] PngError),
+    #[error("{0}")]
+    Decode(#[from] DecodeError),
+    #[error("{0}")]
+    Encode(#[from] EncodeError),
+    #[error("{0}")]
+    Convert(#[from] ConvertError),
+    #[error("{0}")]
+    Ops(#[from] OpsError),
+    #[error("{0}")]
+    Limit(#[from] LimitError),
+}
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Png,
+    /// PGM for gray images, PPM for color ones, without alpha; PBM is read too
+    Pnm,
+    Pam,
+    Bmp,
+    Farbfeld,
+}
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Format::Png => "PNG",
+            Format::Pnm => "PNM",
+            Format::Pam => "PAM",
+            Format::Bmp => "BMP",
+            Format::Farbfeld => "farbfeld",
+        };
+        write!(f, "{}", name)
+    }
+}
+impl Format {
+    // by the magic bytes at the start of the file
+    pub fn detect(bytes: &[u8]) -> Option<Format> {
+        match bytes {
+            [0x89, b'P', b'N', b'G', ..] => Some(Format::Png),
+            [b'P', b'1'..=b'6', ..] => Some(Format::Pnm),
+            [b'P', b'7', ..] => Some(Format::Pam),
+            [b'B', b'M', ..] => Some(Format::Bmp),
+            _ if bytes.starts_with(b"farbfeld") => Some(Format::Farbfeld),
+            _ => None,
+        }
+    }
+    pub fn from_path(path: &Path) -> Option<Format> {
+        let extension = path.extension()?.to_string_lossy().to_ascii_lowercase();
+        Format::value_variants().iter().copied().find(|format| format.extensions().contains(&extension.as_str()))
+    }
+    // every extension files in this format go by, the one it's written with first
+    pub fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Format::Png => &["png"],
+            Format::Pnm => &["pnm", "pbm", "pgm", "ppm"],
+            Format::Pam => &["pam"],
+            Format::Bmp => &["bmp"],
+            Format::Farbfeld => &["ff"],
+        }
+    }
+    pub fn extension(self) -> &'static str {
+        self.extensions()[0]
+    }
+}
+pub fn read(bytes: &[u8], format: Format, limits: Limits) -> Result<Pixels, FormatError> {
+    match format {
+        Format::Png => {
+            let png = Png::from_bytes_with_limits(bytes, limits)?;
+            let pixels = png.decode_with_limits(limits)?.to_pixels();
+            Ok(png.to_continuous(pixels)?)
+        }
+        Format::Pnm => read_pnm(bytes, limits),
+        Format::Pam => read_pam(bytes, limits),
+        Format::Bmp => read_bmp(bytes, limits),
+        Format::Farbfeld => read_farbfeld(bytes, limits),
+    }
+}
+pub fn write(pixels: &Pixels, format: Format, options: &EncodeOptions) -> Result<Vec<u8>, FormatError> {
+    if pixels.color_type == ColorType::Indexed && format != Format::Png {
+        return Err(ConvertError::MissingPalette.into());
+    }
+    match format {
+        Format::Png => Ok(Png::from_pixels(pixels, None, options)?.as_bytes()),
+        Format::Pnm => write_pnm(pixels),
+        Format::Pam => Ok(write_pam(pixels)),
+        Format::Bmp => write_bmp(pixels),
+        Format::Farbfeld => write_farbfeld(pixels),
+    }
+}
+// before the header's sizes are trusted with anything
+fn check_dimensions(width: u32, height: u32, limits: Limits, format: Format) -> Result<(), FormatError> {
+    if width == 0 || height == 0 {
+        return Err(FormatError::Header(format, "width or height is 0"));
+    }
+    Ok(limits.check_dimensions(width, height)?)
+}
+// Checks the raster of `bytes` bytes at `start` is all there before anything
+// that size is allocated
+fn raster(data: &[u8], start: usize, bytes: u64, format: Format) -> Result<&[u8], FormatError> {
+    let end = (start as u64).checked_add(bytes).filter(|&end| end <= data.len() as u64).ok_or(FormatError::Truncated(format))?;
+    Ok(&data[start..end as usize])
+}
+// netpbm headers are whitespace separated, with comments from # to the end of a line
+fn token<'a>(data: &'a [u8], pos: &mut usize) -> Option<&'a [u8]> {
+    loop {
+        match *data.get(*pos)? {
+            b'#' => {
+                while data.get(*pos).is_some_and(|&byte| byte != b'\n') {
+                    *pos += 1;
+                }
+            }
+            byte if byte.is_ascii_whitespace() => *pos += 1,
+            _ => break,
+        }
+    }
+    let start = *pos;
+    while data.get(*pos).is_some_and(|byte| !byte.is_ascii_whitespace()) {
+        *pos += 1;
+    }
+    Some(&data[start..*pos])
+}
+fn number(data: &[u8], pos: &mut usize, format: Format, what: &'static str) -> Result<u32, FormatError> {
+    token(data, pos)
+        .and_then(|token| std::str::from_utf8(token).ok()?.parse().ok())
+        .ok_or(FormatError::Header(format, what))
+}
+// Samples up to `maxval` as pixels, at the depth whose maximum is `maxval` when
+// there's one the color type allows and rescaled to 8 or 16 bits otherwise
+fn from_maxval(width: u32, height: u32, color_type: ColorType, samples: Vec<u16>, maxval: u32) -> Pixels {
+    let bits = (32 - maxval.leading_zeros()) as u8;
+    let clamped = samples.into_iter().map(|sample| sample.min(maxval as u16));
+    if maxval == (1 << bits) - 1 && color_type.allowed_bit_depths().contains(&bits) {
+        return Pixels { width, height, color_type, bit_depth: bits, samples: clamped.collect() };
+    }
+    let bit_depth = if maxval < 256 { 8 } else { 16 };
+    let max = (1u32 << bit_depth) - 1;
+    let samples = clamped.map(|sample| ((sample as u32 * max + maxval / 2) / maxval) as u16).collect();
+    Pixels { width, height, color_type, bit_depth, samples }
+}
+// one or two bytes per sample, big-endian, as netpbm stores them
+fn raw_samples(raster: &[u8], maxval: u32) -> Vec<u16> {
+    if maxval < 256 {
+        raster.iter().map(|&byte| byte as u16).collect()
+    } else {
+        unpack(raster, 16, raster.len() / 2)
+    }
+}
+fn netpbm_maxval(maxval: u32, format: Format) -> Result<u32, FormatError> {
+    if !(1..=65535).contains(&maxval) {
+        return Err(FormatError::Header(format, "maxval isn't 1 to 65535"));
+    }
+    Ok(maxval)
+}
+// P1 to P6: plain (ASCII) and raw PBM, PGM and PPM
+fn read_pnm(data: &[u8], limits: Limits) -> Result<Pixels, FormatError> {
+    let format = Format::Pnm;
+    let kind = match data {
+        [b'P', kind @ b'1'..=b'6', ..] => kind - b'0',
+        _ => return Err(FormatError::Header(format, "no P1 to P6 magic number")),
+    };
+    let mut pos = 2;
+    let width = number(data, &mut pos, format, "width")?;
+    let height = number(data, &mut pos, format, "height")?;
+    let bitmap = kind == 1 || kind == 4;
+    let maxval = if bitmap { 1 } else { netpbm_maxval(number(data, &mut pos, format, "maxval")?, format)? };
+    check_dimensions(width, height, limits, format)?;
+    let color_type = if kind % 3 == 0 { ColorType::Rgb } else { ColorType::Grayscale };
+    let count = width as u64 * height as u64 * color_type.channels() as u64;
+    // a single whitespace byte separates the header from raw data
+    let start = pos + 1;
+    let mut samples = match kind {
+        1 => {
+            // digits don't need anything between them
+            let digits = data[pos.min(data.len())..].iter().filter(|byte| !byte.is_ascii_whitespace());
+            let samples: Vec<u16> = digits.take(count as usize).map(|&digit| (digit == b'1') as u16).collect();
+            if (samples.len() as u64) < count {
+                return Err(FormatError::Truncated(format));
+            }
+            samples
+        }
+        2 | 3 => {
+            let mut samples = Vec::new();
+            while (samples.len() as u64) < count {
+                let sample = number(data, &mut pos, format, "sample").map_err(|_| FormatError::Truncated(format))?;
+                samples.push(sample.min(maxval) as u16);
+            }
+            samples
+        }
+        4 => {
+            let row_bytes = (width as usize).div_ceil(8);
+            let raster = raster(data, start, row_bytes as u64 * height as u64, format)?;
+            raster.chunks(row_bytes.max(1)).flat_map(|row| unpack(row, 1, width as usize)).collect()
+        }
+        _ => {
+            let bytes = if maxval < 256 { 1 } else { 2 };
+            raw_samples(raster(data, start, count * bytes, format)?, maxval)
+        }
+    };
+    if bitmap {
+        // in PBM 1 is black
+        samples.iter_mut().for_each(|sample| *sample = 1 - *sample);
+    }
+    Ok(from_maxval(width, height, color_type, samples, maxval))
+}
+fn read_pam(data: &[u8], limits: Limits) -> Result<Pixels, FormatError> {
+    let format = Format::Pam;
+    if !data.starts_with(b"P7") {
+        return Err(FormatError::Header(format, "no P7 magic number"));
+    }
+    let mut pos = 2;
+    let (mut width, mut height, mut depth, mut maxval) = (None, None, None, None);
+    loop {
+        match token(data, &mut pos).ok_or(FormatError::Header(format, "no ENDHDR"))? {
+            b"WIDTH" => width = Some(number(data, &mut pos, format, "width")?),
+            b"HEIGHT" => height = Some(number(data, &mut pos, format, "height")?),
+            b"DEPTH" => depth = Some(number(data, &mut pos, format, "depth")?),
+            b"MAXVAL" => maxval = Some(number(data, &mut pos, format, "maxval")?),
+            b"ENDHDR" => break,
+            // TUPLTYPE and anything else: depth alone says what the channels are
+            _ => {
+                while data.get(pos).is_some_and(|&byte| byte != b'\n') {
+                    pos += 1;
+                }
+            }
+        }
+    }
+    let (Some(width), Some(height), Some(depth), Some(maxval)) = (width, height, depth, maxval) else {
+        return Err(FormatError::Header(format, "WIDTH, HEIGHT, DEPTH and MAXVAL are all needed"));
+    };
+    let maxval = netpbm_maxval(maxval, format)?;
+    let color_type = match depth {
+        1 => ColorType::Grayscale,
+        2 => ColorType::GrayscaleAlpha,
+        3 => ColorType::Rgb,
+        4 => ColorType::Rgba,
+        _ => return Err(FormatError::Unsupported(format, format!("depth {}", depth))),
+    };
+    check_dimensions(width, height, limits, format)?;
+    let bytes = if maxval < 256 { 1 } else { 2 };
+    let raster = raster(data, pos + 1, width as u64 * height as u64 * depth as u64 * bytes, format)?;
+    Ok(from_maxval(width, height, color_type, raw_samples(raster, maxval), maxval))
+}
+fn netpbm_raster(pixels: &Pixels) -> Vec<u8> {
+    if pixels.bit_depth == 16 {
+        pixels.samples.iter().flat_map(|sample| sample.to_be_bytes()).collect()
+    } else {
+        pixels.samples.iter().map(|&sample| sample as u8).collect()
+    }
+}
+// PNM has no alpha, so it's dropped, as pngtopnm does
+fn write_pnm(pixels: &Pixels) -> Result<Vec<u8>, FormatError> {
+    let (color_type, magic) = match pixels.color_type {
+        ColorType::Grayscale | ColorType::GrayscaleAlpha => (ColorType::Grayscale, "P5"),
+        _ => (ColorType::Rgb, "P6"),
+    };
+    let pixels = pixels.to_color_type(color_type, None)?;
+    let mut out = format!("{}\n{} {}\n{}\n", magic, pixels.width, pixels.height, pixels.max_sample()).into_bytes();
+    out.extend(netpbm_raster(&pixels));
+    Ok(out)
+}
+fn write_pam(pixels: &Pixels) -> Vec<u8> {
+    let tuple_type = match pixels.color_type {
+        ColorType::Grayscale => "GRAYSCALE",
+        ColorType::GrayscaleAlpha => "GRAYSCALE_ALPHA",
+        ColorType::Rgba => "RGB_ALPHA",
+        _ => "RGB",
+    };
+    let mut out = format!(
+        "P7\nWIDTH {}\nHEIGHT {}\nDEPTH {}\nMAXVAL {}\nTUPLTYPE {}\nENDHDR\n",
+        pixels.width, pixels.height, pixels.channels(), pixels.max_sample(), tuple_type,
+    ).into_bytes();
+    out.extend(netpbm_raster(pixels));
+    out
+}
+// "farbfeld", width and height, then 16-bit big-endian RGBA
+fn read_farbfeld(data: &[u8], limits: Limits) -> Result<Pixels, FormatError> {
+    let format = Format::Farbfeld;
+    if !data.starts_with(b"farbfeld") {
+        return Err(FormatError::Header(format, "no farbfeld magic"));
+    }
+    let header = raster(data, 8, 8, format)?;
+    let width = u32::from_be_bytes(header[..4].try_into().expect("4 bytes"));
+    let height = u32::from_be_bytes(header[4..].try_into().expect("4 bytes"));
+    check_dimensions(width, height, limits, format)?;
+    let count = width as u64 * height as u64 * 4;
+    let samples = unpack(raster(data, 16, count * 2, format)?, 16, count as usize);
+    Ok(Pixels { width, height, color_type: ColorType::Rgba, bit_depth: 16, samples })
+}
+fn write_farbfeld(pixels: &Pixels) -> Result<Vec<u8>, FormatError> {
+    let pixels = pixels.to_color_type(ColorType::Rgba, None)?.to_bit_depth(16)?;
+    let mut out = b"farbfeld".to_vec();
+    out.extend(pixels.width.to_be_bytes());
+    out.extend(pixels.height.to_be_bytes());
+    out.extend(netpbm_raster(&pixels));
+    Ok(out)
+}
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+const BI_ALPHABITFIELDS: u32 = 6;
+fn le_u16(data: &[u8], offset: usize) -> Result<u16, FormatError> {
+    Ok(u16::from_le_bytes(raster(data, offset, 2, Format::Bmp)?.try_into().expect("2 bytes")))
+}
+fn le_u32(data: &[u8], offset: usize) -> Result<u32, FormatError> {
+    Ok(u32::from_le_bytes(raster(data, offset, 4, Format::Bmp)?.try_into().expect("4 bytes")))
+}
+// a channel of a 16 or 32-bit pixel, scaled to 8 bits
+fn masked(value: u32, mask: u32) -> u16 {
+    if mask == 0 {
+        return 0;
+    }
+    scale(((value & mask) >> mask.trailing_zeros()) as u16, mask.count_ones() as u8, 8)
+}
+// Uncompressed BMPs with any of the usual headers, palettes of 1, 2, 4 or 8 bits,
+// and 16, 24 or 32-bit pixels, with bit field masks or the default layouts.
+// They come out as 8-bit RGB, or RGBA when there's an alpha mask.
+fn read_bmp(data: &[u8], limits: Limits) -> Result<Pixels, FormatError> {
+    let format = Format::Bmp;
+    if !data.starts_with(b"BM") {
+        return Err(FormatError::Header(format, "no BM magic"));
+    }
+    let offset = le_u32(data, 10)? as usize;
+    let header_size = le_u32(data, 14)?;
+    let (width, height, bpp, compression, colors_used, entry_size) = match header_size {
+        12 => (le_u16(data, 18)? as i32, le_u16(data, 20)? as i32, le_u16(data, 24)?, BI_RGB, 0, 3),
+        40.. => (le_u32(data, 18)? as i32, le_u32(data, 22)? as i32, le_u16(data, 28)?, le_u32(data, 30)?, le_u32(data, 46)?, 4),
+        _ => return Err(FormatError::Header(format, "unknown header size")),
+    };
+    if width < 0 || height == i32::MIN {
+        return Err(FormatError::Header(format, "negative width or height"));
+    }
+    let (width, top_down) = (width as u32, height < 0);
+    let height = height.unsigned_abs();
+    check_dimensions(width, height, limits, format)?;
+    let (mut masks, mut alpha_mask) = match bpp {
+        16 => ([0x7c00, 0x03e0, 0x001f], 0),
+        _ => ([0xff_0000, 0xff00, 0xff], 0),
+    };
+    match (compression, bpp) {
+        (BI_RGB, 1 | 2 | 4 | 8 | 16 | 24 | 32) => {}
+        (BI_BITFIELDS | BI_ALPHABITFIELDS, 16 | 32) => {
+            // after a 40-byte header, or in the larger ones at the same place
+            masks = [le_u32(data, 54)?, le_u32(data, 58)?, le_u32(data, 62)?];
+            if header_size >= 56 || compression == BI_ALPHABITFIELDS {
+                alpha_mask = le_u32(data, 66)?;
+            }
+            // one run of at most 16 bits each
+            let unusable = |mask: u32| {
+                let shifted = mask.checked_shr(mask.trailing_zeros()).unwrap_or(0);
+                shifted & shifted.wrapping_add(1) != 0 || shifted > 0xffff
+            };
+            if masks.iter().chain([&alpha_mask]).any(|&mask| unusable(mask)) {
+                return Err(FormatError::Unsupported(format, "bit field masks with gaps or over 16 bits".to_string()));
+            }
+        }
+        _ => return Err(FormatError::Unsupported(format, format!("{}-bit pixels with compression {}", bpp, compression))),
+    }
+    let palette: Vec<[u16; 3]> = if bpp <= 8 {
+        let count = if colors_used == 0 { 1 << bpp } else { colors_used.min(1 << bpp) };
+        let table = raster(data, 14 + header_size as usize, count as u64 * entry_size, format)?;
+        table.chunks(entry_size as usize).map(|bgr| [bgr[2] as u16, bgr[1] as u16, bgr[0] as u16]).collect()
+    } else {
+        Vec::new()
+    };
+    let row_bytes = (width as u64 * bpp as u64).div_ceil(32) * 4;
+    let rows = raster(data, offset, row_bytes * height as u64, format)?;
+    let color_type = if alpha_mask != 0 { ColorType::Rgba } else { ColorType::Rgb };
+    let mut samples = Vec::with_capacity(width as usize * height as usize * color_type.channels() as usize);
+    for y in 0..height as usize {
+        let y = if top_down { y } else { height as usize - 1 - y };
+        let row = &rows[y * row_bytes as usize..(y + 1) * row_bytes as usize];
+        match bpp {
+            1 | 2 | 4 | 8 => {
+                for index in unpack(row, bpp as u8, width as usize) {
+                    let color = palette.get(index as usize).ok_or(ConvertError::Index(index, palette.len()))?;
+                    samples.extend_from_slice(color);
+                }
+            }
+            24 => row.chunks_exact(3).take(width as usize).for_each(|bgr| samples.extend([bgr[2], bgr[1], bgr[0]].map(u16::from))),
+            _ => {
+                let bytes = bpp as usize / 8;
+                for pixel in row.chunks_exact(bytes).take(width as usize) {
+                    let value = pixel.iter().rev().fold(0u32, |value, &byte| value << 8 | byte as u32);
+                    samples.extend(masks.map(|mask| masked(value, mask)));
+                    if alpha_mask != 0 {
+                        samples.push(masked(value, alpha_mask));
+                    }
+                }
+            }
+        }
+    }
+    Ok(Pixels { width, height, color_type, bit_depth: 8, samples })
+}
+// Gray images get an 8-bit palette of grays, color ones 24-bit pixels and
+// ones with alpha 32-bit pixels with an alpha mask, which needs a V4 header
+fn write_bmp(pixels: &Pixels) -> Result<Vec<u8>, FormatError> {
+    let format = Format::Bmp;
+    if pixels.width > i32::MAX as u32 || pixels.height > i32::MAX as u32 {
+        return Err(FormatError::Unsupported(format, "images over 2^31 pixels across".to_string()));
+    }
+    let color_type = match pixels.color_type {
+        ColorType::Grayscale => ColorType::Grayscale,
+        ColorType::GrayscaleAlpha | ColorType::Rgba => ColorType::Rgba,
+        _ => ColorType::Rgb,
+    };
+    let pixels = pixels.to_color_type(color_type, None)?.to_bit_depth(8)?;
+    let (bpp, header_size, palette_size, compression) = match color_type {
+        ColorType::Grayscale => (8u16, 40u32, 256 * 4, BI_RGB),
+        ColorType::Rgb => (24, 40, 0, BI_RGB),
+        _ => (32, 108, 0, BI_BITFIELDS),
+    };
+    let row_bytes = (pixels.width as usize * bpp as usize).div_ceil(32) * 4;
+    let image_size = row_bytes * pixels.height as usize;
+    let offset = 14 + header_size + palette_size;
+    let mut out = Vec::with_capacity(offset as usize + image_size);
+    out.extend(b"BM");
+    out.extend(((offset as u64 + image_size as u64).min(u32::MAX as u64) as u32).to_le_bytes());
+    out.extend(0u32.to_le_bytes());
+    out.extend(offset.to_le_bytes());
+    out.extend(header_size.to_le_bytes());
+    out.extend(pixels.width.to_le_bytes());
+    // positive, so rows go bottom to top
+    out.extend(pixels.height.to_le_bytes());
+    out.extend(1u16.to_le_bytes());
+    out.extend(bpp.to_le_bytes());
+    out.extend(compression.to_le_bytes());
+    out.extend((image_size as u32).to_le_bytes());
+    out.extend([0; 8]);
+    out.extend((palette_size / 4).to_le_bytes());
+    out.extend(0u32.to_le_bytes());
+    if color_type == ColorType::Rgba {
+        for mask in [0xff_0000u32, 0xff00, 0xff, 0xff00_0000] {
+            out.extend(mask.to_le_bytes());
+        }
+        out.extend(b"BGRs");
+        out.extend([0; 36 + 12]);
+    }
+    for gray in 0..palette_size / 4 {
+        out.extend([gray as u8, gray as u8, gray as u8, 0]);
+    }
+    let channels = pixels.channels();
+    for row in pixels.samples.chunks(pixels.width as usize * channels).rev() {
+        let start = out.len();
+        for pixel in row.chunks(channels) {
+            match pixel {
+                [gray] => out.push(*gray as u8),
+                [red, green, blue] => out.extend([*blue as u8, *green as u8, *red as u8]),
+                [red, green, blue, alpha] => out.extend([*blue as u8, *green as u8, *red as u8, *alpha as u8]),
+                _ => unreachable!("gray, RGB or RGBA"),
+            }
+        }
+        out.resize(start + row_bytes, 0);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixels::tests::pattern;
+
+    fn pixels(color_type: ColorType, bit_depth: u8) -> Pixels {
+        pattern(5, 3, color_type, bit_depth)
+    }
+
+    fn round_trip(pixels: &Pixels, format: Format) -> Pixels {
+        let bytes = write(pixels, format, &EncodeOptions::default()).unwrap();
+        assert_eq!(Format::detect(&bytes), Some(format));
+        read(&bytes, format, Limits::default()).unwrap()
+    }
+
+    #[test]
+    fn test_formats() {
+        assert_eq!(Format::from_path(Path::new("a/b.PGM")), Some(Format::Pnm));
+        assert_eq!(Format::from_path(Path::new("b.ff")), Some(Format::Farbfeld));
+        assert_eq!(Format::from_path(Path::new("b.jpg")), None);
+        assert_eq!(Format::detect(b"GIF89a"), None);
+    }
+
+    #[test]
+    fn test_netpbm_round_trips() {
+        for (color_type, bit_depth) in [(ColorType::Grayscale, 1), (ColorType::Grayscale, 4), (ColorType::Rgb, 8), (ColorType::Rgb, 16)] {
+            let pixels = pixels(color_type, bit_depth);
+            assert_eq!(round_trip(&pixels, Format::Pnm), pixels);
+            assert_eq!(round_trip(&pixels, Format::Pam), pixels);
+        }
+        let alpha = pixels(ColorType::GrayscaleAlpha, 16);
+        assert_eq!(round_trip(&alpha, Format::Pam), alpha);
+        // PNM drops alpha
+        assert_eq!(round_trip(&alpha, Format::Pnm), alpha.to_color_type(ColorType::Grayscale, None).unwrap());
+    }
+
+    #[test]
+    fn test_read_pnm() {
+        // plain PBM, where 1 is black and digits can run together
+        let pbm = read(b"P1\n# a comment\n3 2\n100\n0 1 1\n", Format::Pnm, Limits::default()).unwrap();
+        assert_eq!((pbm.bit_depth, pbm.samples), (1, vec![0, 1, 1, 1, 0, 0]));
+        let pbm = read(b"P4 3 2\n\x80\x60", Format::Pnm, Limits::default()).unwrap();
+        assert_eq!(pbm.samples, [0, 1, 1, 1, 0, 0]);
+        let ppm = read(b"P3 1 1 255 10 20 30", Format::Pnm, Limits::default()).unwrap();
+        assert_eq!((ppm.color_type, ppm.samples), (ColorType::Rgb, vec![10, 20, 30]));
+        // a maxval that isn't a bit depth is rescaled
+        let pgm = read(b"P2 3 1 100 0 50 100", Format::Pnm, Limits::default()).unwrap();
+        assert_eq!((pgm.bit_depth, pgm.samples), (8, vec![0, 128, 255]));
+        assert!(matches!(read(b"P5 3 1 255\n\0\0", Format::Pnm, Limits::default()), Err(FormatError::Truncated(Format::Pnm))));
+        assert!(matches!(read(b"P5 3 1 0\n\0\0\0", Format::Pnm, Limits::default()), Err(FormatError::Header(..))));
+        assert!(matches!(read(b"P5 99999999 1 255\n", Format::Pnm, Limits::default()), Err(FormatError::Limit(_))));
+        assert!(matches!(read(b"P5 0 1 255\n", Format::Pnm, Limits::default()), Err(FormatError::Header(..))));
+    }
+
+    #[test]
+    fn test_farbfeld_round_trip() {
+        let rgba = pixels(ColorType::Rgba, 16);
+        assert_eq!(round_trip(&rgba, Format::Farbfeld), rgba);
+        let gray = pixels(ColorType::Grayscale, 8);
+        let converted = round_trip(&gray, Format::Farbfeld);
+        assert_eq!(converted, gray.to_color_type(ColorType::Rgba, None).unwrap().to_bit_depth(16).unwrap());
+    }
+
+    #[test]
+    fn test_bmp_round_trips() {
+        let rgb = pixels(ColorType::Rgb, 8);
+        assert_eq!(round_trip(&rgb, Format::Bmp), rgb);
+        let rgba = pixels(ColorType::Rgba, 8);
+        assert_eq!(round_trip(&rgba, Format::Bmp), rgba);
+        // gray goes through a palette of grays and comes back as RGB
+        let gray = pixels(ColorType::Grayscale, 8);
+        assert_eq!(round_trip(&gray, Format::Bmp), gray.to_color_type(ColorType::Rgb, None).unwrap());
+        let deep = pixels(ColorType::Rgb, 16);
+        assert_eq!(round_trip(&deep, Format::Bmp), deep.to_bit_depth(8).unwrap());
+    }
+
+    #[test]
+    fn test_read_bmp_variants() {
+        // a top-down 2x1 image of 16-bit 565 pixels given as bit fields
+        let mut bmp = b"BM".to_vec();
+        bmp.extend(0u32.to_le_bytes());
+        bmp.extend(0u32.to_le_bytes());
+        bmp.extend(66u32.to_le_bytes());
+        bmp.extend(40u32.to_le_bytes());
+        bmp.extend(2i32.to_le_bytes());
+        bmp.extend((-1i32).to_le_bytes());
+        bmp.extend(1u16.to_le_bytes());
+        bmp.extend(16u16.to_le_bytes());
+        bmp.extend(BI_BITFIELDS.to_le_bytes());
+        bmp.extend([0; 20]);
+        for mask in [0xf800u32, 0x07e0, 0x001f] {
+            bmp.extend(mask.to_le_bytes());
+        }
+        bmp.extend(0xf800u16.to_le_bytes());
+        bmp.extend(0x07ffu16.to_le_bytes());
+        let pixels = read(&bmp, Format::Bmp, Limits::default()).unwrap();
+        assert_eq!(pixels.samples, [255, 0, 0, 0, 255, 255]);
+        // nor are masks with gaps
+        bmp[54] = 0b1011;
+        assert!(matches!(read(&bmp, Format::Bmp, Limits::default()), Err(FormatError::Unsupported(..))));
+        bmp[54] = 0;
+        // RLE isn't supported
+        bmp[30] = 1;
+        assert!(matches!(read(&bmp, Format::Bmp, Limits::default()), Err(FormatError::Unsupported(..))));
+        bmp[30] = 3;
+        bmp.truncate(68);
+        assert!(matches!(read(&bmp, Format::Bmp, Limits::default()), Err(FormatError::Truncated(Format::Bmp))));
+    }
+
+    #[test]
+    fn test_png_round_trip() {
+        let indexed = Pixels { color_type: ColorType::Indexed, ..pixels(ColorType::Grayscale, 1) };
+        assert!(matches!(write(&indexed, Format::Pam, &EncodeOptions::default()), Err(FormatError::Convert(ConvertError::MissingPalette))));
+        let gray = pixels(ColorType::GrayscaleAlpha, 8);
+        assert_eq!(round_trip(&gray, Format::Png), gray);
+    }
+}
@@ -0,0 +1,199 @@
+use std::fmt;
+use thiserror::Error;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorType {
+    Grayscale = 0,
+    Rgb = 2,
+    Indexed = 3,
+    GrayscaleAlpha = 4,
+    Rgba = 6,
+}
+impl ColorType {
+    pub fn channels(self) -> u8 {
+        match self {
+            ColorType::Grayscale | ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+    pub fn allowed_bit_depths(self) -> &'static [u8] {
+        match self {
+            ColorType::Grayscale => &[1, 2, 4, 8, 16],
+            ColorType::Indexed => &[1, 2, 4, 8],
+            ColorType::Rgb | ColorType::GrayscaleAlpha | ColorType::Rgba => &[8, 16],
+        }
+    }
+    pub fn has_alpha(self) -> bool {
+        matches!(self, ColorType::GrayscaleAlpha | ColorType::Rgba)
+    }
+}
+impl TryFrom<u8> for ColorType {
+    type Error = IhdrError;
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ColorType::Grayscale),
+            2 => Ok(ColorType::Rgb),
+            3 => Ok(ColorType::Indexed),
+            4 => Ok(ColorType::GrayscaleAlpha),
+            6 => Ok(ColorType::Rgba),
+            _ => Err(IhdrError::ColorType(value)),
+        }
+    }
+}
+// the names pngcheck uses
+impl fmt::Display for ColorType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ColorType::Grayscale => "grayscale",
+            ColorType::Rgb => "RGB",
+            ColorType::Indexed => "palette",
+            ColorType::GrayscaleAlpha => "grayscale+alpha",
+            ColorType::Rgba => "RGB+alpha",
+        };
+        write!(f, "{}", name)
+    }
+}
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum IhdrError {
+    #[error("IHDR is {0} bytes long, expected 13")]
+    Length(usize),
+    #[error("Invalid image dimensions {0}x{1}")]
+    Dimensions(u32, u32),
+    #[error("Invalid color type {0}")]
+    ColorType(u8),
+    #[error("Bit depth {0} isn't allowed for {1} images")]
+    BitDepth(u8, ColorType),
+    #[error("Unknown compression method {0}")]
+    Compression(u8),
+    #[error("Unknown filter method {0}")]
+    Filter(u8),
+    #[error("Unknown interlace method {0}")]
+    Interlace(u8),
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ihdr {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: ColorType,
+    pub interlaced: bool,
+}
+impl Ihdr {
+    pub const LENGTH: usize = 13;
+    pub fn bits_per_pixel(&self) -> usize {
+        self.bit_depth as usize * self.color_type.channels() as usize
+    }
+    // bytes in one row of pixels, without the filter byte
+    pub fn row_bytes(&self) -> usize {
+        (self.width as usize * self.bits_per_pixel()).div_ceil(8)
+    }
+    pub fn image_bytes(&self) -> u64 {
+        self.height as u64 * self.row_bytes() as u64
+    }
+    // pngcheck style, e.g. "32-bit RGB+alpha" or "4-bit palette"
+    pub fn pixel_description(&self) -> String {
+        let bits = match self.color_type {
+            ColorType::Indexed => self.bit_depth as usize,
+            _ => self.bits_per_pixel(),
+        };
+        format!("{}-bit {}", bits, self.color_type)
+    }
+    pub fn interlace_description(&self) -> &'static str {
+        if self.interlaced { "interlaced" } else { "non-interlaced" }
+    }
+    pub fn to_chunk(&self) -> Chunk {
+        let data = self.width.to_be_bytes().into_iter()
+            .chain(self.height.to_be_bytes())
+            .chain([self.bit_depth, self.color_type as u8, 0, 0, self.interlaced as u8])
+            .collect();
+        Chunk::new(ChunkType::IHDR, data)
+    }
+}
+impl TryFrom<&[u8]> for Ihdr {
+    type Error = IhdrError;
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() != Ihdr::LENGTH {
+            return Err(IhdrError::Length(data.len()));
+        }
+        let width = u32::from_be_bytes(data[0..4].try_into().unwrap());
+        let height = u32::from_be_bytes(data[4..8].try_into().unwrap());
+        if width == 0 || height == 0 || width > Chunk::MAX_LENGTH || height > Chunk::MAX_LENGTH {
+            return Err(IhdrError::Dimensions(width, height));
+        }
+        let bit_depth = data[8];
+        let color_type = ColorType::try_from(data[9])?;
+        if !color_type.allowed_bit_depths().contains(&bit_depth) {
+            return Err(IhdrError::BitDepth(bit_depth, color_type));
+        }
+        if data[10] != 0 {
+            return Err(IhdrError::Compression(data[10]));
+        }
+        if data[11] != 0 {
+            return Err(IhdrError::Filter(data[11]));
+        }
+        let interlaced = match data[12] {
+            0 => false,
+            1 => true,
+            method => return Err(IhdrError::Interlace(method)),
+        };
+        Ok(Ihdr { width, height, bit_depth, color_type, interlaced })
+    }
+}
+// pngcheck style, e.g. "64x64, 32-bit RGB+alpha, non-interlaced"
+impl fmt::Display for Ihdr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}x{}, {}, {}",
+            self.width,
+            self.height,
+            self.pixel_description(),
+            self.interlace_description()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ihdr_bytes(color_type: u8, bit_depth: u8) -> Vec<u8> {
+        [&50u32.to_be_bytes()[..], &20u32.to_be_bytes(), &[bit_depth, color_type, 0, 0, 0]].concat()
+    }
+
+    #[test]
+    fn test_ihdr_from_bytes() {
+        let ihdr = Ihdr::try_from(ihdr_bytes(6, 8).as_slice()).unwrap();
+        assert_eq!(ihdr.width, 50);
+        assert_eq!(ihdr.height, 20);
+        assert_eq!(ihdr.color_type, ColorType::Rgba);
+        assert_eq!(ihdr.bits_per_pixel(), 32);
+        assert_eq!(ihdr.row_bytes(), 200);
+        assert_eq!(ihdr.to_string(), "50x20, 32-bit RGB+alpha, non-interlaced");
+        assert_eq!(ihdr.to_chunk().data(), ihdr_bytes(6, 8).as_slice());
+    }
+
+    #[test]
+    fn test_ihdr_sub_byte_rows() {
+        let ihdr = Ihdr::try_from(ihdr_bytes(3, 1).as_slice()).unwrap();
+        assert_eq!(ihdr.row_bytes(), 7);
+        assert_eq!(ihdr.to_string(), "50x20, 1-bit palette, non-interlaced");
+    }
+
+    #[test]
+    fn test_invalid_ihdr() {
+        assert_eq!(Ihdr::try_from(&[0u8; 4][..]), Err(IhdrError::Length(4)));
+        assert_eq!(Ihdr::try_from(ihdr_bytes(1, 8).as_slice()), Err(IhdrError::ColorType(1)));
+        assert_eq!(Ihdr::try_from(ihdr_bytes(2, 4).as_slice()), Err(IhdrError::BitDepth(4, ColorType::Rgb)));
+        let mut bytes = ihdr_bytes(0, 8);
+        bytes[12] = 2;
+        assert_eq!(Ihdr::try_from(bytes.as_slice()), Err(IhdrError::Interlace(2)));
+        let mut bytes = ihdr_bytes(0, 8);
+        bytes[0..4].copy_from_slice(&[0, 0, 0, 0]);
+        assert_eq!(Ihdr::try_from(bytes.as_slice()), Err(IhdrError::Dimensions(0, 20)));
+    }
+}
@@ -0,0 +1,476 @@
+use std::io::{self, Read};
+use thiserror::Error;
+use crate::limits::{LimitError, Limits};
+
+// zlib (RFC 1950) wrapped DEFLATE (RFC 1951) decoding. Input is pulled from an
+// iterator of slices, so the data of consecutive IDAT chunks can be fed in as is,
+// and output is produced on demand through `Read`.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum InflateError {
+    #[error("Compressed data ends early, at byte {0}")]
+    UnexpectedEof(usize),
+    #[error("Invalid zlib header {0:#04x} {1:#04x}")]
+    Header(u8, u8),
+    #[error("Unknown compression method {0}, only deflate (8) is supported")]
+    Method(u8),
+    #[error("zlib window size 2^{0} is over the maximum of 2^15")]
+    WindowSize(u8),
+    #[error("Compressed data needs a preset dictionary")]
+    PresetDictionary,
+    #[error("Invalid block type 3 at byte {0}")]
+    BlockType(usize),
+    #[error("Stored block length {0:#06x} doesn't match its complement {1:#06x} at byte {2}")]
+    StoredLength(u16, u16, usize),
+    #[error("Invalid Huffman code lengths at byte {0}")]
+    CodeLengths(usize),
+    #[error("Invalid Huffman code at byte {0}")]
+    Code(usize),
+    #[error("Invalid {0} symbol {1} at byte {2}")]
+    Symbol(&'static str, u16, usize),
+    #[error("Back reference {0} bytes back with only {1} bytes written, at byte {2}")]
+    Distance(usize, usize, usize),
+    #[error("Adler-32 checksum is {0:#010x}, computed {1:#010x}")]
+    Adler32(u32, u32),
+    #[error("{0}")]
+    Limit(#[from] LimitError),
+}
+pub fn adler32(adler: u32, bytes: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (adler & 0xffff, adler >> 16);
+    // 5552 bytes is the most that can be summed before b overflows a u32
+    for block in bytes.chunks(5552) {
+        for &byte in block {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+struct BitReader<'a, I> {
+    chunks: I,
+    current: &'a [u8],
+    bits: u64,
+    count: u32,
+    consumed: usize,
+}
+impl<'a, I: Iterator<Item = &'a [u8]>> BitReader<'a, I> {
+    fn byte(&mut self) -> Result<u8, InflateError> {
+        loop {
+            if let Some((&byte, rest)) = self.current.split_first() {
+                self.current = rest;
+                self.consumed += 1;
+                return Ok(byte);
+            }
+            self.current = self.chunks.next().ok_or(InflateError::UnexpectedEof(self.consumed))?;
+        }
+    }
+    // DEFLATE packs values starting from the least significant bit
+    fn bits(&mut self, n: u32) -> Result<u32, InflateError> {
+        while self.count < n {
+            self.bits |= (self.byte()? as u64) << self.count;
+            self.count += 8;
+        }
+        let value = (self.bits & ((1 << n) - 1)) as u32;
+        self.bits >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+    fn align(&mut self) {
+        let partial = self.count % 8;
+        self.bits >>= partial;
+        self.count -= partial;
+    }
+    // byte offset into the input, for error messages
+    fn position(&self) -> usize {
+        self.consumed - (self.count / 8) as usize
+    }
+}
+// Canonical Huffman code, decoded a bit at a time by counting codes of each length
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+impl Huffman {
+    // Incomplete codes are allowed, a single distance code is the usual case
+    fn new(lengths: &[u8]) -> Option<Huffman> {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return None;
+            }
+        }
+        let mut offsets = [0u16; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        counts[0] = 0;
+        Some(Huffman { counts, symbols })
+    }
+    fn decode<'a, I: Iterator<Item = &'a [u8]>>(&self, reader: &mut BitReader<'a, I>) -> Result<u16, InflateError> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= reader.bits(1)? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(InflateError::Code(reader.position()))
+    }
+}
+pub(crate) const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+pub(crate) const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+pub(crate) const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097,
+    6145, 8193, 12289, 16385, 24577,
+];
+pub(crate) const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+// order the code length code lengths are stored in
+pub(crate) const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+pub(crate) const WINDOW: usize = 1 << 15;
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Header,
+    Block,
+    Stored(usize),
+    Codes,
+    Trailer,
+    Done,
+}
+pub struct ZlibDecoder<'a, I> {
+    input: BitReader<'a, I>,
+    state: State,
+    last_block: bool,
+    literals: Huffman,
+    distances: Huffman,
+    window: Vec<u8>,
+    written: usize,
+    copy_length: usize,
+    copy_distance: usize,
+    adler: u32,
+    limits: Limits,
+}
+impl<'a, I: Iterator<Item = &'a [u8]>> ZlibDecoder<'a, I> {
+    pub fn new<C: IntoIterator<IntoIter = I>>(chunks: C, limits: Limits) -> Self {
+        ZlibDecoder {
+            input: BitReader { chunks: chunks.into_iter(), current: &[], bits: 0, count: 0, consumed: 0 },
+            state: State::Header,
+            last_block: false,
+            literals: Huffman { counts: [0; 16], symbols: Vec::new() },
+            distances: Huffman { counts: [0; 16], symbols: Vec::new() },
+            window: vec![0; WINDOW],
+            written: 0,
+            copy_length: 0,
+            copy_distance: 0,
+            adler: 1,
+            limits,
+        }
+    }
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+    // total bytes of output so far
+    pub fn total_out(&self) -> usize {
+        self.written
+    }
+    fn header(&mut self) -> Result<(), InflateError> {
+        let cmf = self.input.bits(8)? as u8;
+        let flg = self.input.bits(8)? as u8;
+        if !(cmf as u16 * 256 + flg as u16).is_multiple_of(31) {
+            return Err(InflateError::Header(cmf, flg));
+        }
+        if cmf & 0x0f != 8 {
+            return Err(InflateError::Method(cmf & 0x0f));
+        }
+        if cmf >> 4 > 7 {
+            return Err(InflateError::WindowSize((cmf >> 4) + 8));
+        }
+        if flg & 0x20 != 0 {
+            return Err(InflateError::PresetDictionary);
+        }
+        Ok(())
+    }
+    fn block_header(&mut self) -> Result<State, InflateError> {
+        if self.last_block {
+            return Ok(State::Trailer);
+        }
+        self.last_block = self.input.bits(1)? == 1;
+        match self.input.bits(2)? {
+            0 => {
+                self.input.align();
+                let length = self.input.bits(16)? as u16;
+                let complement = self.input.bits(16)? as u16;
+                if length != !complement {
+                    return Err(InflateError::StoredLength(length, complement, self.input.position()));
+                }
+                Ok(State::Stored(length as usize))
+            }
+            1 => {
+                let mut lengths = [8u8; 288];
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                self.literals = Huffman::new(&lengths).unwrap();
+                self.distances = Huffman::new(&[5; 30]).unwrap();
+                Ok(State::Codes)
+            }
+            2 => {
+                self.dynamic_codes()?;
+                Ok(State::Codes)
+            }
+            _ => Err(InflateError::BlockType(self.input.position())),
+        }
+    }
+    fn dynamic_codes(&mut self) -> Result<(), InflateError> {
+        let literal_count = self.input.bits(5)? as usize + 257;
+        let distance_count = self.input.bits(5)? as usize + 1;
+        let code_length_count = self.input.bits(4)? as usize + 4;
+        let bad_lengths = |input: &BitReader<'a, I>| InflateError::CodeLengths(input.position());
+        if literal_count > 286 || distance_count > 30 {
+            return Err(bad_lengths(&self.input));
+        }
+        let mut code_lengths = [0u8; 19];
+        for &index in &CODE_LENGTH_ORDER[..code_length_count] {
+            code_lengths[index] = self.input.bits(3)? as u8;
+        }
+        let code_length_code = Huffman::new(&code_lengths).ok_or_else(|| bad_lengths(&self.input))?;
+        let mut lengths = Vec::with_capacity(literal_count + distance_count);
+        while lengths.len() < literal_count + distance_count {
+            let symbol = code_length_code.decode(&mut self.input)?;
+            let (length, repeat) = match symbol {
+                0..=15 => (symbol as u8, 1),
+                16 => {
+                    let previous = *lengths.last().ok_or_else(|| bad_lengths(&self.input))?;
+                    (previous, 3 + self.input.bits(2)? as usize)
+                }
+                17 => (0, 3 + self.input.bits(3)? as usize),
+                _ => (0, 11 + self.input.bits(7)? as usize),
+            };
+            if lengths.len() + repeat > literal_count + distance_count {
+                return Err(bad_lengths(&self.input));
+            }
+            lengths.extend(std::iter::repeat_n(length, repeat));
+        }
+        // a block has to be able to end
+        if lengths[256] == 0 {
+            return Err(bad_lengths(&self.input));
+        }
+        self.literals = Huffman::new(&lengths[..literal_count]).ok_or_else(|| bad_lengths(&self.input))?;
+        self.distances = Huffman::new(&lengths[literal_count..]).ok_or_else(|| bad_lengths(&self.input))?;
+        Ok(())
+    }
+    // Decodes one literal/length symbol: a literal byte is returned, a length sets
+    // up a copy from the window and the end of the block moves on to the next one
+    fn codes(&mut self) -> Result<Option<u8>, InflateError> {
+        let symbol = self.literals.decode(&mut self.input)?;
+        match symbol {
+            0..=255 => return Ok(Some(symbol as u8)),
+            256 => {
+                self.state = State::Block;
+                return Ok(None);
+            }
+            _ => {}
+        }
+        let index = symbol as usize - 257;
+        if index >= LENGTH_BASE.len() {
+            return Err(InflateError::Symbol("length", symbol, self.input.position()));
+        }
+        let length = LENGTH_BASE[index] as usize + self.input.bits(LENGTH_EXTRA[index] as u32)? as usize;
+        let symbol = self.distances.decode(&mut self.input)?;
+        let index = symbol as usize;
+        if index >= DISTANCE_BASE.len() {
+            return Err(InflateError::Symbol("distance", symbol, self.input.position()));
+        }
+        let distance = DISTANCE_BASE[index] as usize + self.input.bits(DISTANCE_EXTRA[index] as u32)? as usize;
+        if distance > self.written {
+            return Err(InflateError::Distance(distance, self.written, self.input.position()));
+        }
+        self.copy_length = length;
+        self.copy_distance = distance;
+        Ok(None)
+    }
+    fn trailer(&mut self) -> Result<(), InflateError> {
+        self.input.align();
+        let mut stored = 0u32;
+        for _ in 0..4 {
+            stored = (stored << 8) | self.input.bits(8)?;
+        }
+        if stored != self.adler {
+            return Err(InflateError::Adler32(stored, self.adler));
+        }
+        Ok(())
+    }
+    fn emit(&mut self, byte: u8, buf: &mut [u8], n: &mut usize) {
+        self.window[self.written % WINDOW] = byte;
+        self.written += 1;
+        buf[*n] = byte;
+        *n += 1;
+    }
+    // Fills as much of `buf` as it can, returning 0 only once the stream has ended
+    pub fn decode(&mut self, buf: &mut [u8]) -> Result<usize, InflateError> {
+        let mut n = 0;
+        let mut checked = 0;
+        while n < buf.len() {
+            if self.copy_length > 0 {
+                let byte = self.window[(self.written - self.copy_distance) % WINDOW];
+                self.emit(byte, buf, &mut n);
+                self.copy_length -= 1;
+                continue;
+            }
+            match self.state {
+                State::Header => {
+                    self.header()?;
+                    self.state = State::Block;
+                }
+                State::Block => self.state = self.block_header()?,
+                State::Stored(0) => self.state = State::Block,
+                State::Stored(remaining) => {
+                    let byte = self.input.bits(8)? as u8;
+                    self.emit(byte, buf, &mut n);
+                    self.state = State::Stored(remaining - 1);
+                }
+                State::Codes => {
+                    if let Some(byte) = self.codes()? {
+                        self.emit(byte, buf, &mut n);
+                    }
+                }
+                State::Trailer => {
+                    self.adler = adler32(self.adler, &buf[checked..n]);
+                    checked = n;
+                    self.trailer()?;
+                    self.state = State::Done;
+                }
+                State::Done => break,
+            }
+        }
+        self.adler = adler32(self.adler, &buf[checked..n]);
+        self.limits.check_decompressed(self.written as u64)?;
+        Ok(n)
+    }
+    // Decodes the rest of the stream, checksum included
+    pub fn decode_to_vec(&mut self) -> Result<Vec<u8>, InflateError> {
+        let mut out = Vec::new();
+        let mut buf = vec![0; WINDOW];
+        loop {
+            let n = self.decode(&mut buf)?;
+            if n == 0 {
+                return Ok(out);
+            }
+            out.extend_from_slice(&buf[..n]);
+        }
+    }
+}
+impl<'a, I: Iterator<Item = &'a [u8]>> Read for ZlibDecoder<'a, I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.decode(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+pub fn inflate(data: &[u8], limits: Limits) -> Result<Vec<u8>, InflateError> {
+    ZlibDecoder::new([data], limits).decode_to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "Hello, hello, hello!" as zlib -9 makes it, with a back reference
+    const HELLO: [u8; 18] = [
+        0x78, 0xda, 0xf3, 0x48, 0xcd, 0xc9, 0xc9, 0xd7, 0x51, 0xc8, 0x40, 0xa2, 0x14, 0x01, 0x48, 0x9e, 0x06, 0xd6,
+    ];
+
+    // builds a zlib stream of stored blocks by hand
+    fn stored(data: &[u8], block: usize) -> Vec<u8> {
+        let mut out = vec![0x78, 0x01];
+        let blocks: Vec<&[u8]> = if data.is_empty() { vec![&[]] } else { data.chunks(block).collect() };
+        for (i, chunk) in blocks.iter().enumerate() {
+            out.push((i == blocks.len() - 1) as u8);
+            let length = chunk.len() as u16;
+            out.extend(length.to_le_bytes());
+            out.extend((!length).to_le_bytes());
+            out.extend_from_slice(chunk);
+        }
+        out.extend(adler32(1, data).to_be_bytes());
+        out
+    }
+
+    #[test]
+    fn test_adler32() {
+        assert_eq!(adler32(1, b"Wikipedia"), 0x11e60398);
+        assert_eq!(adler32(1, &[]), 1);
+    }
+
+    #[test]
+    fn test_inflate_stored() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        assert_eq!(inflate(&stored(&data, 65535), Limits::default()).unwrap(), data);
+        assert_eq!(inflate(&stored(&[], 10), Limits::default()).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_inflate_across_chunks() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i % 13) as u8).collect();
+        let stream = stored(&data, 300);
+        let pieces: Vec<&[u8]> = stream.chunks(7).collect();
+        let out = ZlibDecoder::new(pieces, Limits::default()).decode_to_vec().unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn test_inflate_read_in_small_pieces() {
+        let mut decoder = ZlibDecoder::new([&HELLO[..]], Limits::default());
+        let mut out = Vec::new();
+        let mut buf = [0u8; 3];
+        loop {
+            let n = decoder.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            out.extend_from_slice(&buf[..n]);
+        }
+        assert!(decoder.is_done());
+        assert_eq!(out, b"Hello, hello, hello!");
+    }
+
+    #[test]
+    fn test_inflate_errors() {
+        let mut bad = HELLO;
+        bad[17] ^= 1;
+        assert!(matches!(inflate(&bad, Limits::default()), Err(InflateError::Adler32(..))));
+        assert_eq!(inflate(&HELLO[..10], Limits::default()), Err(InflateError::UnexpectedEof(10)));
+        assert_eq!(inflate(&[0x78, 0x00], Limits::default()), Err(InflateError::Header(0x78, 0)));
+        assert_eq!(inflate(&[0x79, 0x18], Limits::default()), Err(InflateError::Method(9)));
+        assert_eq!(inflate(&[0x78, 0xbb], Limits::default()), Err(InflateError::PresetDictionary));
+        // block type 3
+        assert_eq!(inflate(&[0x78, 0x01, 0x07], Limits::default()), Err(InflateError::BlockType(3)));
+        let mut bad = stored(b"abc", 10);
+        bad[5] ^= 1;
+        assert!(matches!(inflate(&bad, Limits::default()), Err(InflateError::StoredLength(..))));
+    }
+
+    #[test]
+    fn test_inflate_limit() {
+        let data = vec![0u8; 1000];
+        let limits = Limits { max_decompressed: 999, ..Limits::default() };
+        assert_eq!(inflate(&stored(&data, 500), limits), Err(InflateError::Limit(LimitError::Decompressed(999))));
+    }
+}
@@ -0,0 +1,20 @@
+pub mod batch;
+pub mod chunk_type;
+pub mod chunk;
+pub mod decode;
+pub mod deflate;
+pub mod diff;
+pub mod encode;
+pub mod exif;
+pub mod formats;
+pub mod png;
+pub mod dump;
+pub mod ihdr;
+pub mod inflate;
+pub mod limits;
+pub mod ops;
+pub mod pixels;
+pub mod recover;
+pub mod render;
+pub mod signature;
+pub mod validate;
@@ -0,0 +1,75 @@
+use thiserror::Error;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+
+// Caps on what a png may make us allocate. Lengths and dimensions come straight
+// from the file, so without these a few bytes of hostile input can ask for gigabytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub max_chunk_length: u32,
+    pub max_chunks: usize,
+    pub max_decompressed: u64,
+    pub max_width: u32,
+    pub max_height: u32,
+}
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum LimitError {
+    #[error("{0} chunk is {1} bytes long, the limit is {2}")]
+    ChunkLength(ChunkType, u32, u32),
+    #[error("Png has more than {0} chunks")]
+    Chunks(usize),
+    #[error("Decompressed data is over the limit of {0} bytes")]
+    Decompressed(u64),
+    #[error("Image is {0}x{1}, the limit is {2}x{3}")]
+    Dimensions(u32, u32, u32, u32),
+}
+// libpng's default of a million pixels on each side; everything else is
+// generous enough for any real image
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_chunk_length: Chunk::MAX_LENGTH,
+            max_chunks: 1 << 20,
+            max_decompressed: 1 << 30,
+            max_width: 1_000_000,
+            max_height: 1_000_000,
+        }
+    }
+}
+impl Limits {
+    // Nothing beyond the 2^31-1 chunk length, which every chunk is held to anyway.
+    // Dimensions the spec doesn't allow are left to IHDR validation.
+    pub fn none() -> Self {
+        Limits {
+            max_chunk_length: Chunk::MAX_LENGTH,
+            max_chunks: usize::MAX,
+            max_decompressed: u64::MAX,
+            max_width: u32::MAX,
+            max_height: u32::MAX,
+        }
+    }
+    pub fn check_chunk_length(&self, ctype: ChunkType, length: u32) -> Result<(), LimitError> {
+        if length > self.max_chunk_length {
+            return Err(LimitError::ChunkLength(ctype, length, self.max_chunk_length));
+        }
+        Ok(())
+    }
+    pub fn check_chunks(&self, count: usize) -> Result<(), LimitError> {
+        if count > self.max_chunks {
+            return Err(LimitError::Chunks(self.max_chunks));
+        }
+        Ok(())
+    }
+    pub fn check_decompressed(&self, size: u64) -> Result<(), LimitError> {
+        if size > self.max_decompressed {
+            return Err(LimitError::Decompressed(self.max_decompressed));
+        }
+        Ok(())
+    }
+    pub fn check_dimensions(&self, width: u32, height: u32) -> Result<(), LimitError> {
+        if width > self.max_width || height > self.max_height {
+            return Err(LimitError::Dimensions(width, height, self.max_width, self.max_height));
+        }
+        Ok(())
+    }
+}
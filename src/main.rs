@@ -1,14 +1,28 @@
-use clap::{Parser,Subcommand,Args};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use clap::{Parser,Subcommand,Args,ValueEnum};
 
-use crate::chunk_type::ChunkType;
-use crate::chunk::Chunk;
-use crate::png::Png;
-pub mod chunk_type;
-pub mod chunk;
-pub mod png;
+use pngme::{batch, chunk, dump, recover};
+use pngme::chunk_type::ChunkType;
+use pngme::chunk::Chunk;
+use pngme::decode::data_size;
+use pngme::deflate::{BlockType, Compression, IdatWriter};
+use pngme::diff::{self, DiffError};
+use pngme::encode::EncodeOptions;
+use pngme::formats::{self, Format, FormatError};
+use pngme::limits::Limits;
+use pngme::ops::{self, ResizeFilter};
+use pngme::pixels::Pixels;
+use pngme::png::{Png, PngError, PngRef};
+use pngme::render::{Output, RenderOptions};
+use pngme::dump::{BinaryEncoding, PngDump};
+use pngme::batch::WriteOptions;
+use pngme::validate::Severity;
+use regex::Regex;
 use std::convert::TryFrom;
-use std::fs;
-use std::path::{PathBuf};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about=None)]
@@ -18,78 +32,719 @@ struct Cli {
 }
 #[derive(Subcommand)]
 enum Commands {
+    #[command(override_usage = "pngme encode [OPTIONS] <PATHS>... <CTYPE> <MESSAGE>")]
     Encode(EncodeArgs),
     Decode(DecodeArgs),
     Remove(RemoveArgs),
     Print(PrintArgs),
+    Dump(DumpArgs),
+    Build(BuildArgs),
+    Repair(RepairArgs),
+    Lint(LintArgs),
+    Optimize(OptimizeArgs),
+    Crop(CropArgs),
+    Flip(FlipArgs),
+    Rotate(RotateArgs),
+    Resize(ResizeArgs),
+    AutoOrient(TransformArgs),
+    Convert(ConvertArgs),
+    Diff(DiffArgs),
 }
+// Every command takes any mix of files, directories (searched recursively) and
+// glob patterns. With more than one file, output options name a directory.
+// `-` reads from stdin or writes to stdout.
 #[derive(Args)]
 struct EncodeArgs {
-    file_path: PathBuf,
-    ctype: ChunkType,
-    message: String,
+    /// Files, directories or glob patterns, followed by the chunk type and message
+    #[arg(required = true, num_args = 3.., value_names = ["PATHS", "CTYPE", "MESSAGE"])]
+    args: Vec<String>,
     #[arg(short, long)]
     output_file: Option<PathBuf>,
+    #[command(flatten)]
+    write: WriteOptions,
+}
+impl EncodeArgs {
+    fn split(&self) -> crate::Result<(Vec<PathBuf>, ChunkType, &str)> {
+        let [paths @ .., ctype, message] = self.args.as_slice() else {
+            unreachable!("clap requires at least 3 values")
+        };
+        Ok((paths.iter().map(PathBuf::from).collect(), ctype.parse()?, message))
+    }
 }
 #[derive(Args)]
 struct  DecodeArgs {
-    file_path: PathBuf,
+    #[arg(required = true)]
+    file_paths: Vec<PathBuf>,
     ctype: ChunkType,
+    /// Decode every chunk of this type instead of the first
+    #[arg(long)]
+    all: bool,
+    #[command(flatten)]
+    format: DecodeFormat,
+    /// Where --raw writes the data, defaults to stdout
+    #[arg(short, long, requires = "raw")]
+    output_file: Option<PathBuf>,
+}
+// Text data is printed as UTF-8 or Latin-1, anything else as hex
+#[derive(Args)]
+#[group(multiple = false)]
+struct DecodeFormat {
+    /// Write the chunk data byte for byte, with nothing around it
+    #[arg(long)]
+    raw: bool,
+    /// Print the chunk data as hex
+    #[arg(long)]
+    hex: bool,
+    /// Print the chunk data as base64
+    #[arg(long)]
+    base64: bool,
+}
+impl DecodeFormat {
+    fn format(&self, data: &[u8]) -> String {
+        if self.hex {
+            return dump::to_hex(data);
+        }
+        if self.base64 {
+            return BASE64.encode(data);
+        }
+        match chunk::data_as_text(data) {
+            Some(text) => text.into_owned(),
+            None => dump::to_hex(data),
+        }
+    }
 }
 #[derive(Args)]
 struct  RemoveArgs {
-    file_path: PathBuf,
+    #[arg(required = true)]
+    file_paths: Vec<PathBuf>,
     ctype: ChunkType,
+    /// Remove every chunk of this type instead of the first
+    #[arg(long, conflicts_with = "index")]
+    all: bool,
+    /// Remove the Nth chunk of this type, counting from 0
+    #[arg(long, value_name = "N")]
+    index: Option<usize>,
+    /// Only consider chunks whose text content matches this regex
+    #[arg(long, value_name = "REGEX")]
+    matching: Option<Regex>,
+    /// List the chunks that would be removed without changing any file
+    #[arg(long)]
+    dry_run: bool,
+    #[command(flatten)]
+    write: WriteOptions,
 }
 #[derive(Args)]
 struct  PrintArgs {
-    file_path: PathBuf,
+    #[arg(required = true)]
+    file_paths: Vec<PathBuf>,
 }
-fn encode(args: EncodeArgs) -> crate::Result<()> {
-    let output_file = args.output_file.unwrap_or_else(||  args.file_path.clone());
-    let png_bytes = fs::read(args.file_path)?;
-    let mut png: Png = TryFrom::try_from(png_bytes.as_slice())?;
-    let chunk = Chunk::new(args.ctype, args.message.as_bytes().to_vec());
-    png.append_chunk(chunk);
-    fs::write(output_file, png.as_bytes())?;
-    Ok(())
+#[derive(Args)]
+struct  DumpArgs {
+    #[arg(required = true)]
+    file_paths: Vec<PathBuf>,
+    #[arg(short, long)]
+    output_file: Option<PathBuf>,
+    #[arg(short, long, value_enum, default_value_t)]
+    encoding: BinaryEncoding,
 }
-fn decode(args: DecodeArgs) -> crate::Result<()> {
-    let png_bytes = fs::read(args.file_path)?;
-    let png: Png = TryFrom::try_from(png_bytes.as_slice())?;
-    let chunk = png.chunk_by_type(&args.ctype.to_string());
-    if let Some(c) = chunk {
-        println!("{}", c);
+#[derive(Args)]
+struct  BuildArgs {
+    #[arg(required = true)]
+    json_paths: Vec<PathBuf>,
+    /// Defaults to the json path with a .png extension
+    #[arg(short, long)]
+    output_file: Option<PathBuf>,
+    #[command(flatten)]
+    write: WriteOptions,
+}
+#[derive(Args)]
+struct  RepairArgs {
+    #[arg(required = true)]
+    file_paths: Vec<PathBuf>,
+    #[arg(short, long)]
+    output_file: Option<PathBuf>,
+    #[command(flatten)]
+    write: WriteOptions,
+}
+#[derive(Args)]
+struct  LintArgs {
+    #[arg(required = true)]
+    file_paths: Vec<PathBuf>,
+    /// Print every issue, not just the summary line
+    #[arg(short, long)]
+    verbose: bool,
+    /// Only print files with problems
+    #[arg(short, long)]
+    quiet: bool,
+}
+#[derive(Args)]
+struct  OptimizeArgs {
+    #[arg(required = true)]
+    file_paths: Vec<PathBuf>,
+    #[arg(short, long)]
+    output_file: Option<PathBuf>,
+    /// zlib compression level, 0 to 9
+    #[arg(short, long, default_value_t = 9, value_parser = clap::value_parser!(u8).range(0..=9))]
+    level: u8,
+    /// Search for the smallest encoding over this many rounds instead, which is slow
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "15", conflicts_with = "level")]
+    exhaustive: Option<u32>,
+    #[arg(long, value_enum, default_value_t)]
+    blocks: BlockType,
+    /// Largest IDAT chunk to write
+    #[arg(long, default_value_t = IdatWriter::DEFAULT_LENGTH)]
+    idat_size: usize,
+    #[command(flatten)]
+    write: WriteOptions,
+}
+// What every command that changes the pixels takes
+#[derive(Args)]
+struct TransformArgs {
+    #[arg(required = true)]
+    file_paths: Vec<PathBuf>,
+    #[arg(short, long)]
+    output_file: Option<PathBuf>,
+    #[command(flatten)]
+    encode: EncodeOptions,
+    #[command(flatten)]
+    write: WriteOptions,
+}
+#[derive(Args)]
+struct CropArgs {
+    #[command(flatten)]
+    transform: TransformArgs,
+    /// Left edge of the area to keep
+    #[arg(long, default_value_t = 0)]
+    x: u32,
+    /// Top edge of the area to keep
+    #[arg(long, default_value_t = 0)]
+    y: u32,
+    #[arg(long)]
+    width: u32,
+    #[arg(long)]
+    height: u32,
+}
+#[derive(Args)]
+struct FlipArgs {
+    #[command(flatten)]
+    transform: TransformArgs,
+    /// Flip top to bottom instead of left to right
+    #[arg(long)]
+    vertical: bool,
+}
+#[derive(ValueEnum, Clone, Copy)]
+enum Degrees {
+    #[value(name = "90")]
+    Quarter,
+    #[value(name = "180")]
+    Half,
+    #[value(name = "270")]
+    ThreeQuarters,
+}
+#[derive(Args)]
+struct RotateArgs {
+    #[command(flatten)]
+    transform: TransformArgs,
+    /// Clockwise
+    #[arg(short, long, value_enum)]
+    degrees: Degrees,
+}
+// With just one of width and height the other keeps the aspect ratio
+#[derive(Args)]
+#[group(id = "size", required = true, multiple = true, args = ["width", "height"])]
+struct ResizeArgs {
+    #[command(flatten)]
+    transform: TransformArgs,
+    #[arg(long)]
+    width: Option<u32>,
+    #[arg(long)]
+    height: Option<u32>,
+    /// Keep the aspect ratio, making the image as large as fits in width x height
+    #[arg(long, requires_all = ["width", "height"])]
+    fit: bool,
+    #[arg(long, value_enum, default_value_t)]
+    resample: ResizeFilter,
+}
+// Without an output file each input is written next to itself, with the
+// extension of the new format
+#[derive(Args)]
+struct ConvertArgs {
+    #[arg(required = true)]
+    file_paths: Vec<PathBuf>,
+    #[arg(short, long)]
+    output_file: Option<PathBuf>,
+    /// Format of the input, otherwise told from its first bytes
+    #[arg(long, value_enum)]
+    from: Option<Format>,
+    /// Format to write, otherwise taken from the output file's extension
+    #[arg(long, value_enum)]
+    to: Option<Format>,
+    /// Render a png's colors for display, applying gAMA, cHRM or sRGB, bKGD and sBIT
+    #[arg(long, value_enum, value_name = "OUTPUT")]
+    render: Option<Output>,
+    /// Composite over this sRGB color instead of bKGD when rendering, implies --render
+    #[arg(long, value_name = "RRGGBB", value_parser = parse_color)]
+    background: Option<[u8; 3]>,
+    #[command(flatten)]
+    encode: EncodeOptions,
+    #[command(flatten)]
+    write: WriteOptions,
+}
+#[derive(Args)]
+struct DiffArgs {
+    left: PathBuf,
+    right: PathBuf,
+    /// Channels differing by up to this many 8-bit levels still count as the same
+    #[arg(short, long, default_value_t = 0.0)]
+    tolerance: f64,
+    /// Write the left image faded, with the pixels that differ in red
+    #[arg(long, value_name = "FILE")]
+    highlight: Option<PathBuf>,
+}
+// Runs `f` over every input file in parallel and prints the output in input order,
+// prefixed with the file name in a batch. A failing file doesn't stop the rest, a
+// summary of failures goes to stderr at the end. In a batch `f` gets the path to
+// write the file's output to under an `output` directory.
+fn for_each_file<F>(inputs: &[PathBuf], extensions: &[&str], output: Option<&Path>, f: F) -> crate::Result<()>
+where
+    F: Fn(&Path, Option<&Path>) -> crate::Result<String> + Sync,
+{
+    let files = batch::expand(inputs, extensions)?;
+    let is_batch = batch::is_batch(inputs);
+    if is_batch && output.is_some_and(|output| !batch::is_stdio(output)) {
+        batch::check_outputs(&files)?;
+    }
+    let results = batch::run(&files, |input| f(&input.path, is_batch.then_some(input.relative.as_path())));
+    if !is_batch {
+        let output = results.into_iter().next().unwrap_or_else(|| Ok(String::new()))?;
+        print!("{}", output);
+        return Ok(());
+    }
+    let mut failed = 0;
+    for (input, result) in files.iter().zip(results) {
+        match result {
+            Ok(output) => output.lines().for_each(|line| println!("{}: {}", input.path.display(), line)),
+            Err(e) => {
+                failed += 1;
+                eprintln!("{}: {}", input.path.display(), e);
+            }
+        }
+    }
+    eprintln!("{} succeeded, {} failed", files.len() - failed, failed);
+    if failed > 0 {
+        return Err(format!("{} of {} files failed", failed, files.len()).into());
     }
     Ok(())
 }
-fn remove(args: RemoveArgs) -> crate::Result<()> {
-    let png_bytes = fs::read(&args.file_path)?;
-    let mut png: Png = TryFrom::try_from(png_bytes.as_slice())?;
-    match png.remove_first_chunk(&args.ctype.to_string()) {
-        Ok(chunk) => {
-            fs::write(args.file_path, png.as_bytes())?;
-            println!("Removed chunk {}", chunk);
-        }
-        Err(e) => return Err(Box::new(e)),
+// Reports go to stderr when stdout is carrying the png itself
+fn report(output_file: &Path, text: String) -> String {
+    if batch::is_stdio(output_file) {
+        eprint!("{}", text);
+        String::new()
+    } else {
+        text
     }
-    Ok(())
+}
+fn encode(args: EncodeArgs) -> crate::Result<()> {
+    let (file_paths, ctype, message) = args.split()?;
+    for_each_file(&file_paths, &["png"], args.output_file.as_deref(), |path, relative| {
+        let output_file = args.output_file.as_deref()
+            .map_or_else(|| path.to_path_buf(), |output| batch::output_path(output, relative));
+        let png_bytes = batch::read(path)?;
+        let mut png: Png = TryFrom::try_from(png_bytes.as_slice())?;
+        let chunk = Chunk::try_new(ctype, message.as_bytes().to_vec())?;
+        if ctype.is_critical() {
+            let index = png.position_of(ChunkType::IEND).unwrap_or(png.chunks().len());
+            png.insert_chunk_at(index, chunk)?;
+        } else {
+            png.insert_ancillary(chunk)?;
+        }
+        batch::write(&output_file, &png.as_bytes(), &args.write)?;
+        Ok(String::new())
+    })
+}
+fn decode(args: DecodeArgs) -> crate::Result<()> {
+    for_each_file(&args.file_paths, &["png"], args.output_file.as_deref().filter(|_| args.format.raw), |path, relative| {
+        let png_bytes = batch::read(path)?;
+        let png = PngRef::try_from(png_bytes.as_slice())?.verify_crc(true);
+        let chunks = if args.all {
+            png.chunks()
+                .filter(|chunk| chunk.as_ref().map_or(true, |chunk| *chunk.chunk_type() == args.ctype))
+                .collect::<std::result::Result<Vec<_>, PngError>>()?
+        } else {
+            png.chunk_by_type(&args.ctype)?.into_iter().collect()
+        };
+        if args.format.raw {
+            let data: Vec<u8> = chunks.iter().flat_map(|chunk| chunk.data()).copied().collect();
+            let output_file = batch::output_path(args.output_file.as_deref().unwrap_or(Path::new("-")), relative);
+            batch::write(&output_file, &data, &WriteOptions::default())?;
+            return Ok(String::new());
+        }
+        let mut output = String::new();
+        for chunk in &chunks {
+            writeln!(output, "{}\t{}", chunk.chunk_type(), args.format.format(chunk.data()))?;
+        }
+        Ok(output)
+    })
+}
+fn remove(args: RemoveArgs) -> crate::Result<()> {
+    for_each_file(&args.file_paths, &["png"], None, |path, _| {
+        let png_bytes = batch::read(path)?;
+        let mut png: Png = TryFrom::try_from(png_bytes.as_slice())?;
+        let selected = |chunk: &Chunk| {
+            chunk.ctype == args.ctype
+                && args.matching.as_ref().is_none_or(|regex| chunk.data_as_text().is_some_and(|text| regex.is_match(&text)))
+        };
+        let removed = if args.all {
+            png.remove_where(selected)
+        } else {
+            vec![png.remove_nth_where(args.index.unwrap_or(0), selected)?]
+        };
+        if removed.is_empty() {
+            return Err(PngError::NoChunk.into());
+        }
+        let verb = if args.dry_run { "Would remove" } else { "Removed" };
+        let mut output = String::new();
+        for chunk in &removed {
+            writeln!(output, "{} chunk {}", verb, chunk)?;
+        }
+        if args.dry_run {
+            return Ok(output);
+        }
+        batch::write(path, &png.as_bytes(), &args.write)?;
+        Ok(report(path, output))
+    })
 }
 fn print(args: PrintArgs) -> crate::Result<()> {
-    let png_bytes = fs::read(args.file_path)?;
-    let png: Png = TryFrom::try_from(png_bytes.as_slice())?;
-    println!("{}", png);
-    Ok(())
+    for_each_file(&args.file_paths, &["png"], None, |path, _| {
+        let png_bytes = batch::read(path)?;
+        let png: Png = TryFrom::try_from(png_bytes.as_slice())?;
+        Ok(format!("{}\n", png))
+    })
+}
+fn dump(args: DumpArgs) -> crate::Result<()> {
+    for_each_file(&args.file_paths, &["png"], args.output_file.as_deref(), |path, relative| {
+        let png_bytes = batch::read(path)?;
+        let png: Png = TryFrom::try_from(png_bytes.as_slice())?;
+        let json = serde_json::to_string_pretty(&PngDump::new(&png, args.encoding))? + "\n";
+        match &args.output_file {
+            Some(output) if batch::is_stdio(output) => return Ok(json),
+            Some(output) if relative.is_some() => {
+                let output_file = batch::output_path(output, relative).with_extension("json");
+                batch::write(&output_file, json.as_bytes(), &WriteOptions::default())?
+            }
+            Some(output) => batch::write(output, json.as_bytes(), &WriteOptions::default())?,
+            None => return Ok(json),
+        }
+        Ok(String::new())
+    })
+}
+fn build(args: BuildArgs) -> crate::Result<()> {
+    for_each_file(&args.json_paths, &["json"], args.output_file.as_deref(), |path, relative| {
+        let output_file = match &args.output_file {
+            Some(output) if relative.is_some() => batch::output_path(output, relative).with_extension("png"),
+            Some(output) => output.clone(),
+            None if batch::is_stdio(path) => path.to_path_buf(),
+            None => path.with_extension("png"),
+        };
+        let dump: PngDump = serde_json::from_slice(&batch::read(path)?)?;
+        let png = dump.to_png()?;
+        batch::write(&output_file, &png.as_bytes(), &args.write)?;
+        Ok(String::new())
+    })
+}
+fn repair(args: RepairArgs) -> crate::Result<()> {
+    for_each_file(&args.file_paths, &["png"], args.output_file.as_deref(), |path, relative| {
+        let output_file = args.output_file.as_deref()
+            .map_or_else(|| path.to_path_buf(), |output| batch::output_path(output, relative));
+        let png_bytes = batch::read(path)?;
+        let recovery = recover::recover(&png_bytes);
+        if recovery.is_clean() {
+            return Ok(report(&output_file, "No problems found\n".to_string()));
+        }
+        let mut output = String::new();
+        for diagnostic in &recovery.diagnostics {
+            writeln!(output, "{}", diagnostic)?;
+        }
+        batch::write(&output_file, &recovery.png.as_bytes(), &args.write)?;
+        writeln!(output, "Wrote repaired png to {}", output_file.display())?;
+        Ok(report(&output_file, output))
+    })
+}
+// Recompresses the image data, keeping whichever of the two files is smaller
+fn optimize(args: OptimizeArgs) -> crate::Result<()> {
+    let compression = args.exhaustive
+        .map_or_else(|| Compression::level(args.level), Compression::exhaustive)
+        .blocks(args.blocks);
+    for_each_file(&args.file_paths, &["png"], args.output_file.as_deref(), |path, relative| {
+        let output_file = args.output_file.as_deref()
+            .map_or_else(|| path.to_path_buf(), |output| batch::output_path(output, relative));
+        let png_bytes = batch::read(path)?;
+        let mut png: Png = TryFrom::try_from(png_bytes.as_slice())?;
+        png.recompress(compression, args.idat_size, Limits::default())?;
+        let optimized = png.as_bytes();
+        let (before, after) = (png_bytes.len(), optimized.len());
+        if after >= before {
+            if output_file != path {
+                batch::write(&output_file, &png_bytes, &args.write)?;
+            }
+            return Ok(report(&output_file, format!("{} bytes, recompressing saved nothing\n", before)));
+        }
+        batch::write(&output_file, &optimized, &args.write)?;
+        let saved = 100.0 * (before - after) as f64 / before as f64;
+        Ok(report(&output_file, format!("{} -> {} bytes ({:.1}% smaller)\n", before, after, saved)))
+    })
+}
+// Decodes each file, replaces its pixels with what `f` makes of it and encodes it
+// again, keeping the chunks that still apply to the new pixels
+fn transform<F>(args: &TransformArgs, f: F) -> crate::Result<()>
+where
+    F: Fn(&mut Png) -> crate::Result<Pixels> + Sync,
+{
+    for_each_file(&args.file_paths, &["png"], args.output_file.as_deref(), |path, relative| {
+        let output_file = args.output_file.as_deref()
+            .map_or_else(|| path.to_path_buf(), |output| batch::output_path(output, relative));
+        let png_bytes = batch::read(path)?;
+        let mut png: Png = TryFrom::try_from(png_bytes.as_slice())?;
+        let before = png.ihdr()?;
+        let pixels = f(&mut png)?;
+        png.set_pixels(&pixels, &args.encode)?;
+        batch::write(&output_file, &png.as_bytes(), &args.write)?;
+        Ok(report(&output_file, format!("{}x{} -> {}x{}\n", before.width, before.height, pixels.width, pixels.height)))
+    })
+}
+fn crop(args: CropArgs) -> crate::Result<()> {
+    transform(&args.transform, |png| Ok(png.pixels()?.crop(args.x, args.y, args.width, args.height)?))
 }
-pub type Error = Box<dyn std::error::Error>;
+fn flip(args: FlipArgs) -> crate::Result<()> {
+    transform(&args.transform, |png| {
+        let pixels = png.pixels()?;
+        Ok(if args.vertical { pixels.flip_vertical() } else { pixels.flip_horizontal() })
+    })
+}
+fn rotate(args: RotateArgs) -> crate::Result<()> {
+    transform(&args.transform, |png| {
+        let pixels = png.pixels()?;
+        Ok(match args.degrees {
+            Degrees::Quarter => {
+                png.swap_physical_axes();
+                pixels.rotate_90()
+            }
+            Degrees::Half => pixels.rotate_180(),
+            Degrees::ThreeQuarters => {
+                png.swap_physical_axes();
+                pixels.rotate_270()
+            }
+        })
+    })
+}
+fn resize(args: ResizeArgs) -> crate::Result<()> {
+    transform(&args.transform, |png| {
+        let ihdr = png.ihdr()?;
+        let (width, height) = ops::scaled_size(ihdr.width, ihdr.height, args.width, args.height, args.fit);
+        Limits::default().check_dimensions(width, height)?;
+        let pixels = match args.resample {
+            ResizeFilter::Nearest => png.pixels()?,
+            _ => png.continuous_pixels()?,
+        };
+        Ok(pixels.resize(width, height, args.resample)?)
+    })
+}
+// Turns the pixels the way the EXIF orientation says and resets it, leaving
+// files without one as they are
+fn auto_orient(args: TransformArgs) -> crate::Result<()> {
+    for_each_file(&args.file_paths, &["png"], args.output_file.as_deref(), |path, relative| {
+        let output_file = args.output_file.as_deref()
+            .map_or_else(|| path.to_path_buf(), |output| batch::output_path(output, relative));
+        let png_bytes = batch::read(path)?;
+        let mut png: Png = TryFrom::try_from(png_bytes.as_slice())?;
+        let Some(orientation) = png.apply_orientation(&args.encode)? else {
+            if output_file != path {
+                batch::write(&output_file, &png_bytes, &args.write)?;
+            }
+            return Ok(report(&output_file, "No orientation to apply\n".to_string()));
+        };
+        batch::write(&output_file, &png.as_bytes(), &args.write)?;
+        Ok(report(&output_file, format!("Applied orientation {}\n", orientation)))
+    })
+}
+fn convert(args: ConvertArgs) -> crate::Result<()> {
+    let to = args.to
+        .or_else(|| args.output_file.as_deref().and_then(Format::from_path))
+        .ok_or("No format to convert to, give --to or an output file with a known extension")?;
+    let render = (args.render.is_some() || args.background.is_some()).then(|| RenderOptions {
+        output: args.render.unwrap_or_default(),
+        file_background: args.background.is_none(),
+        background: args.background,
+        ..RenderOptions::default()
+    });
+    let extensions = args.from.map_or(Format::Png.extensions(), Format::extensions);
+    for_each_file(&args.file_paths, extensions, args.output_file.as_deref(), |path, relative| {
+        let output_file = match args.output_file.as_deref() {
+            Some(output) if relative.is_some() => batch::output_path(output, relative).with_extension(to.extension()),
+            Some(output) => output.to_path_buf(),
+            None => path.with_extension(to.extension()),
+        };
+        if output_file == path {
+            return Err(format!("Converting {} would overwrite it", path.display()).into());
+        }
+        let bytes = batch::read(path)?;
+        let from = args.from.or_else(|| Format::detect(&bytes)).ok_or(FormatError::Unrecognized)?;
+        let pixels = match render {
+            Some(options) if from == Format::Png => Png::from_bytes_with_limits(&bytes, Limits::default())?.render(&options)?,
+            Some(_) => return Err(format!("Only png input can be rendered, not {}", from).into()),
+            None => formats::read(&bytes, from, Limits::default())?,
+        };
+        let output = match render {
+            Some(options) if to == Format::Png => {
+                let mut png = Png::from_pixels(&pixels, None, &args.encode)?;
+                png.insert_ancillary(options.output.to_chunk())?;
+                png.as_bytes()
+            }
+            _ => formats::write(&pixels, to, &args.encode)?,
+        };
+        batch::write(&output_file, &output, &args.write)?;
+        Ok(report(&output_file, format!("{} -> {}, {}x{}\n", from, to, pixels.width, pixels.height)))
+    })
+}
+fn parse_color(s: &str) -> std::result::Result<[u8; 3], String> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    let channel = |i: usize| hex.get(i..i + 2).and_then(|digits| u8::from_str_radix(digits, 16).ok());
+    match (hex.len(), channel(0), channel(2), channel(4)) {
+        (6, Some(red), Some(green), Some(blue)) => Ok([red, green, blue]),
+        _ => Err(format!("{} isn't a RRGGBB hex color", s)),
+    }
+}
+// diff-style exit codes: 0 if the images look the same, 1 if they don't, 2 if
+// they couldn't be compared
+fn diff(args: DiffArgs) -> crate::Result<ExitCode> {
+    let read = |path: &Path| -> crate::Result<(Png, Pixels)> {
+        let png_bytes = batch::read(path)?;
+        let png: Png = TryFrom::try_from(png_bytes.as_slice())?;
+        let pixels = png.continuous_pixels()?;
+        Ok((png, pixels))
+    };
+    let (left_png, left) = read(&args.left)?;
+    let (right_png, right) = read(&args.right)?;
+    let (left_ihdr, right_ihdr) = (left_png.ihdr()?, right_png.ihdr()?);
+    if left_ihdr != right_ihdr {
+        println!("Headers differ: {} vs {}", left_ihdr, right_ihdr);
+    }
+    let difference = match diff::compare(&left, &right, args.tolerance) {
+        Err(e @ DiffError::Dimensions(..)) => {
+            println!("{}", e);
+            return Ok(ExitCode::from(1));
+        }
+        result => result?,
+    };
+    let pixels = difference.width as u64 * difference.height as u64;
+    let percent = 100.0 * difference.differing as f64 / pixels as f64;
+    println!("{} of {} pixels differ ({:.3}%)", difference.differing, pixels, percent);
+    println!("Max error {:.2}, mean error {:.4}", difference.max_error, difference.mean_error);
+    if difference.psnr.is_infinite() {
+        println!("PSNR infinite, the images are identical");
+    } else {
+        println!("PSNR {:.2} dB", difference.psnr);
+    }
+    if let Some(path) = &args.highlight {
+        let highlight = Png::from_pixels(&difference.highlight(&left)?, None, &EncodeOptions::default())?;
+        batch::write(path, &highlight.as_bytes(), &WriteOptions::default())?;
+    }
+    Ok(ExitCode::from((difference.differing > 0) as u8))
+}
+// pngcheck-style exit codes: 0 if every file is clean, 1 for warnings, 2 for errors
+fn lint(args: LintArgs) -> crate::Result<ExitCode> {
+    let files = batch::expand(&args.file_paths, &["png"])?;
+    let results = batch::run(&files, |input| lint_file(&input.path, &args));
+    let mut worst = None;
+    for result in results {
+        let (output, severity) = result?;
+        print!("{}", output);
+        worst = worst.max(severity);
+    }
+    Ok(match worst {
+        None => ExitCode::SUCCESS,
+        Some(Severity::Warning) => ExitCode::from(1),
+        Some(Severity::Error) => ExitCode::from(2),
+    })
+}
+fn lint_file(path: &Path, args: &LintArgs) -> std::result::Result<(String, Option<Severity>), std::fmt::Error> {
+    let mut output = String::new();
+    let parsed = batch::read(path).map_err(Error::from).and_then(|png_bytes| {
+        let png: Png = TryFrom::try_from(png_bytes.as_slice())?;
+        Ok((png, png_bytes.len()))
+    });
+    let (png, file_size) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            writeln!(output, "ERROR: {}: {}", path.display(), e)?;
+            return Ok((output, Some(Severity::Error)));
+        }
+    };
+    let issues = png.validate();
+    let worst = issues.iter().map(|issue| issue.severity).max();
+    if args.verbose {
+        for issue in &issues {
+            writeln!(output, "  {}  {}", path.display(), issue)?;
+        }
+    }
+    if args.quiet && worst.is_none() {
+        return Ok((output, None));
+    }
+    let status = match worst {
+        None => "OK",
+        Some(Severity::Warning) => "WARNING",
+        Some(Severity::Error) => "ERROR",
+    };
+    match png.ihdr() {
+        Ok(ihdr) => {
+            let trns = if png.position_of(ChunkType::TRNS).is_some() { "+trns" } else { "" };
+            let ratio = 100.0 * (1.0 - file_size as f64 / data_size(&ihdr) as f64);
+            writeln!(
+                output,
+                "{}: {} ({}x{}, {}{}, {}, {:.1}%)",
+                status, path.display(), ihdr.width, ihdr.height,
+                ihdr.pixel_description(), trns, ihdr.interlace_description(), ratio
+            )?;
+        }
+        Err(_) => writeln!(output, "{}: {}", status, path.display())?,
+    }
+    Ok((output, worst))
+}
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
 pub type Result<T> = std::result::Result<T, Error>;
-fn main() -> Result<()>{
+fn run() -> Result<ExitCode>{
     let cli = Cli::parse();
     match cli.command {
         Commands::Encode(args) => encode(args),
         Commands::Decode(args) => decode(args),
         Commands::Remove(args) => remove(args),
         Commands::Print(args) => print(args),
+        Commands::Dump(args) => dump(args),
+        Commands::Build(args) => build(args),
+        Commands::Repair(args) => repair(args),
+        Commands::Lint(args) => return lint(args),
+        Commands::Optimize(args) => optimize(args),
+        Commands::Crop(args) => crop(args),
+        Commands::Flip(args) => flip(args),
+        Commands::Rotate(args) => rotate(args),
+        Commands::Resize(args) => resize(args),
+        Commands::AutoOrient(args) => auto_orient(args),
+        Commands::Convert(args) => convert(args),
+        Commands::Diff(args) => {
+            return diff(args).or_else(|e| {
+                eprintln!("Error: {}", e);
+                Ok(ExitCode::from(2))
+            })
+        }
     }?;
-    Ok(())
-}
\ No newline at end of file
+    Ok(ExitCode::SUCCESS)
+}
+// Errors are shown with their Display message rather than the Debug output
+// returning them from main would give
+fn main() -> ExitCode {
+    run().unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        ExitCode::FAILURE
+    })
+}
@@ -0,0 +1,331 @@
+use clap::ValueEnum;
+use std::f64::consts::PI;
+use thiserror::Error;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::decode::DecodeError;
+use crate::ihdr::ColorType;
+use crate::pixels::{ConvertError, Pixels};
+use crate::png::Png;
+
+// Geometric operations on unpacked pixels. They work on samples whatever the
+// color type, so indexed images keep their palette, except resampling, which
+// mixes colors and needs real ones: see `Png::continuous_pixels`.
+#[derive(Error, Debug)]
+pub enum OpsError {
+    #[error("Crop of {2}x{3} at {0},{1} doesn't fit in the {4}x{5} image")]
+    Crop(u32, u32, u32, u32, u32, u32),
+    #[error("Can't resize to {0}x{1}, both sides need at least one pixel")]
+    Size(u32, u32),
+    #[error("Only nearest neighbour resizing works on palette indices")]
+    Indexed,
+    #[error("{0}")]
+    Decode(#[from] DecodeError),
+    #[error("{0}")]
+    Convert(#[from] ConvertError),
+}
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResizeFilter {
+    /// Copies the closest pixel, the only one that keeps colors exact
+    Nearest,
+    Bilinear,
+    /// Lanczos with 3 lobes, the sharpest
+    #[default]
+    Lanczos,
+}
+impl ResizeFilter {
+    fn radius(self) -> f64 {
+        match self {
+            ResizeFilter::Nearest => 0.5,
+            ResizeFilter::Bilinear => 1.0,
+            ResizeFilter::Lanczos => 3.0,
+        }
+    }
+    fn weight(self, x: f64) -> f64 {
+        let sinc = |x: f64| if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+        match self {
+            ResizeFilter::Nearest => 1.0,
+            ResizeFilter::Bilinear => (1.0 - x.abs()).max(0.0),
+            ResizeFilter::Lanczos if x.abs() < 3.0 => sinc(x) * sinc(x / 3.0),
+            ResizeFilter::Lanczos => 0.0,
+        }
+    }
+}
+// For each of the `to` pixels along an axis, the first of the `from` pixels it's
+// made from and their weights, which add up to 1. Shrinking widens the filter
+// so every source pixel counts.
+fn weights(from: u32, to: u32, filter: ResizeFilter) -> Vec<(usize, Vec<f64>)> {
+    let scale = from as f64 / to as f64;
+    let stretch = scale.max(1.0);
+    let radius = filter.radius() * stretch;
+    (0..to)
+        .map(|i| {
+            let center = (i as f64 + 0.5) * scale;
+            let start = (center - radius).floor().max(0.0) as usize;
+            let end = ((center + radius).ceil() as usize).clamp(start + 1, from as usize);
+            let mut weights: Vec<f64> = (start..end).map(|j| filter.weight((j as f64 + 0.5 - center) / stretch)).collect();
+            let total: f64 = weights.iter().sum();
+            if total != 0.0 {
+                weights.iter_mut().for_each(|weight| *weight /= total);
+            }
+            (start, weights)
+        })
+        .collect()
+}
+// The size to resize a `width` by `height` image to given a target width and/or
+// height. With only one, or with `fit`, the aspect ratio is kept and the image
+// is made as large as fits in the target.
+pub fn scaled_size(width: u32, height: u32, target_width: Option<u32>, target_height: Option<u32>, fit: bool) -> (u32, u32) {
+    if let (Some(target_width), Some(target_height), false) = (target_width, target_height, fit) {
+        return (target_width, target_height);
+    }
+    let scale = [(target_width, width), (target_height, height)].into_iter()
+        .filter_map(|(target, size)| target.map(|target| target as f64 / size as f64))
+        .fold(f64::INFINITY, f64::min);
+    if scale.is_infinite() {
+        return (width, height);
+    }
+    let scaled = |size: u32| ((size as f64 * scale).round() as u32).max(1);
+    (scaled(width), scaled(height))
+}
+impl Pixels {
+    // A `width` by `height` image whose pixel at x, y is this one's at `source(x, y)`
+    fn remap<F: Fn(u32, u32) -> (u32, u32)>(&self, width: u32, height: u32, source: F) -> Pixels {
+        let mut samples = Vec::with_capacity(width as usize * height as usize * self.channels());
+        for y in 0..height {
+            for x in 0..width {
+                let (from_x, from_y) = source(x, y);
+                samples.extend_from_slice(self.pixel(from_x, from_y));
+            }
+        }
+        Pixels { width, height, samples, ..self.clone() }
+    }
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Result<Pixels, OpsError> {
+        if width == 0 || height == 0 || x as u64 + width as u64 > self.width as u64 || y as u64 + height as u64 > self.height as u64 {
+            return Err(OpsError::Crop(x, y, width, height, self.width, self.height));
+        }
+        Ok(self.remap(width, height, |dx, dy| (x + dx, y + dy)))
+    }
+    pub fn flip_horizontal(&self) -> Pixels {
+        self.remap(self.width, self.height, |x, y| (self.width - 1 - x, y))
+    }
+    pub fn flip_vertical(&self) -> Pixels {
+        self.remap(self.width, self.height, |x, y| (x, self.height - 1 - y))
+    }
+    // rotations are clockwise
+    pub fn rotate_90(&self) -> Pixels {
+        self.remap(self.height, self.width, |x, y| (y, self.height - 1 - x))
+    }
+    pub fn rotate_180(&self) -> Pixels {
+        self.remap(self.width, self.height, |x, y| (self.width - 1 - x, self.height - 1 - y))
+    }
+    pub fn rotate_270(&self) -> Pixels {
+        self.remap(self.height, self.width, |x, y| (self.width - 1 - y, x))
+    }
+    // mirrored along the diagonal from the top left, a quarter turn and a flip
+    pub fn transpose(&self) -> Pixels {
+        self.remap(self.height, self.width, |x, y| (y, x))
+    }
+    // mirrored along the other diagonal
+    pub fn transverse(&self) -> Pixels {
+        self.remap(self.height, self.width, |x, y| (self.width - 1 - y, self.height - 1 - x))
+    }
+    // Resizes in the image's own sample values, like most tools, with color
+    // weighted by alpha so transparent pixels don't bleed their color into the
+    // ones around them. Sub-byte depths come out as 8-bit.
+    pub fn resize(&self, width: u32, height: u32, filter: ResizeFilter) -> Result<Pixels, OpsError> {
+        if width == 0 || height == 0 {
+            return Err(OpsError::Size(width, height));
+        }
+        if filter == ResizeFilter::Nearest {
+            let (from_width, from_height) = (self.width as u64, self.height as u64);
+            return Ok(self.remap(width, height, |x, y| {
+                (((2 * x as u64 + 1) * from_width / (2 * width as u64)) as u32, ((2 * y as u64 + 1) * from_height / (2 * height as u64)) as u32)
+            }));
+        }
+        if self.color_type == ColorType::Indexed {
+            return Err(OpsError::Indexed);
+        }
+        let source = if self.bit_depth < 8 { self.to_bit_depth(8)? } else { self.clone() };
+        let channels = source.channels();
+        let max = source.max_sample() as f64;
+        let alpha = source.color_type.has_alpha().then_some(channels - 1);
+        // premultiplied, so each color counts as much as it's visible
+        let mut values: Vec<f64> = source.samples.iter().map(|&sample| sample as f64).collect();
+        if let Some(alpha) = alpha {
+            for pixel in values.chunks_mut(channels) {
+                let opacity = pixel[alpha] / max;
+                pixel[..alpha].iter_mut().for_each(|sample| *sample *= opacity);
+            }
+        }
+        let (from_width, from_height) = (source.width as usize, source.height as usize);
+        let mut wide = vec![0.0; width as usize * from_height * channels];
+        let columns = weights(source.width, width, filter);
+        for y in 0..from_height {
+            let row = &values[y * from_width * channels..(y + 1) * from_width * channels];
+            for (x, (start, weights)) in columns.iter().enumerate() {
+                let out = &mut wide[(y * width as usize + x) * channels..][..channels];
+                for (i, weight) in weights.iter().enumerate() {
+                    let pixel = &row[(start + i) * channels..][..channels];
+                    out.iter_mut().zip(pixel).for_each(|(out, sample)| *out += weight * sample);
+                }
+            }
+        }
+        let mut resized = vec![0.0; width as usize * height as usize * channels];
+        let row_length = width as usize * channels;
+        for (y, (start, weights)) in weights(source.height, height, filter).into_iter().enumerate() {
+            let out = &mut resized[y * row_length..(y + 1) * row_length];
+            for (i, weight) in weights.iter().enumerate() {
+                let row = &wide[(start + i) * row_length..][..row_length];
+                out.iter_mut().zip(row).for_each(|(out, sample)| *out += weight * sample);
+            }
+        }
+        let mut samples = Vec::with_capacity(resized.len());
+        for pixel in resized.chunks(channels) {
+            let opacity = alpha.map_or(1.0, |alpha| pixel[alpha].clamp(0.0, max) / max);
+            for (channel, &value) in pixel.iter().enumerate() {
+                let value = if Some(channel) == alpha || opacity == 1.0 {
+                    value
+                } else if opacity > 0.0 {
+                    value / opacity
+                } else {
+                    0.0
+                };
+                samples.push(value.round().clamp(0.0, max) as u16);
+            }
+        }
+        Ok(Pixels { width, height, samples, ..source })
+    }
+}
+impl Png {
+    // The pixels with real colors, for operations that mix neighbouring pixels
+    // or leave the palette behind, like resizing, `formats::write` and
+    // `diff::compare`: palette entries are looked up and a tRNS color becomes an
+    // alpha channel
+    pub fn continuous_pixels(&self) -> Result<Pixels, OpsError> {
+        self.to_continuous(self.pixels()?)
+    }
+    // the same for `pixels` already decoded from this png
+    pub fn to_continuous(&self, pixels: Pixels) -> Result<Pixels, OpsError> {
+        if pixels.color_type == ColorType::Indexed {
+            let palette = self.palette().ok_or(ConvertError::MissingPalette)?;
+            let color_type = if palette.alpha.is_empty() { ColorType::Rgb } else { ColorType::Rgba };
+            return Ok(pixels.to_color_type(color_type, Some(&palette))?);
+        }
+        Ok(match self.transparent_color() {
+            Some(color) => pixels.with_transparent_color(&color),
+            None => pixels,
+        })
+    }
+    // pHYs after a quarter turn, which swaps what counts as horizontal
+    pub fn swap_physical_axes(&mut self) {
+        let Some(index) = self.position_of(ChunkType::PHYS) else {
+            return;
+        };
+        let data = self.chunks()[index].data();
+        if data.len() != 9 {
+            return;
+        }
+        let mut swapped = data[4..8].to_vec();
+        swapped.extend_from_slice(&data[..4]);
+        swapped.push(data[8]);
+        self.remove_all(ChunkType::PHYS);
+        self.insert_chunk_at(index, Chunk::new(ChunkType::PHYS, swapped)).expect("index is within the chunks");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a 3x2 image, each pixel's value its index
+    fn pixels() -> Pixels {
+        Pixels { width: 3, height: 2, color_type: ColorType::Grayscale, bit_depth: 8, samples: vec![0, 1, 2, 3, 4, 5] }
+    }
+
+    #[test]
+    fn test_crop() {
+        assert_eq!(pixels().crop(1, 0, 2, 2).unwrap().samples, [1, 2, 4, 5]);
+        assert_eq!(pixels().crop(2, 1, 1, 1).unwrap().samples, [5]);
+        assert!(matches!(pixels().crop(2, 1, 2, 1), Err(OpsError::Crop(2, 1, 2, 1, 3, 2))));
+        assert!(matches!(pixels().crop(0, 0, 0, 1), Err(OpsError::Crop(..))));
+        assert!(pixels().crop(u32::MAX, 0, 1, 1).is_err());
+    }
+
+    #[test]
+    fn test_flips_and_rotations() {
+        assert_eq!(pixels().flip_horizontal().samples, [2, 1, 0, 5, 4, 3]);
+        assert_eq!(pixels().flip_vertical().samples, [3, 4, 5, 0, 1, 2]);
+        let rotated = pixels().rotate_90();
+        assert_eq!((rotated.width, rotated.height), (2, 3));
+        assert_eq!(rotated.samples, [3, 0, 4, 1, 5, 2]);
+        assert_eq!(pixels().rotate_180().samples, [5, 4, 3, 2, 1, 0]);
+        assert_eq!(pixels().rotate_270().samples, [2, 5, 1, 4, 0, 3]);
+        assert_eq!(pixels().rotate_90().rotate_270(), pixels());
+        assert_eq!(pixels().rotate_90().rotate_90(), pixels().rotate_180());
+        assert_eq!(pixels().transpose().samples, [0, 3, 1, 4, 2, 5]);
+        assert_eq!(pixels().transpose(), pixels().rotate_90().flip_horizontal());
+        assert_eq!(pixels().transverse(), pixels().rotate_270().flip_horizontal());
+    }
+
+    #[test]
+    fn test_resize_nearest() {
+        let doubled = pixels().resize(6, 4, ResizeFilter::Nearest).unwrap();
+        assert_eq!(doubled.samples[..6], [0, 0, 1, 1, 2, 2]);
+        assert_eq!(doubled.resize(3, 2, ResizeFilter::Nearest).unwrap(), pixels());
+        let indexed = Pixels { color_type: ColorType::Indexed, ..pixels() };
+        assert_eq!(indexed.resize(1, 1, ResizeFilter::Nearest).unwrap().samples, [4]);
+        assert!(matches!(indexed.resize(1, 1, ResizeFilter::Bilinear), Err(OpsError::Indexed)));
+        assert!(matches!(pixels().resize(0, 1, ResizeFilter::Nearest), Err(OpsError::Size(0, 1))));
+    }
+
+    #[test]
+    fn test_resize_filters() {
+        for filter in [ResizeFilter::Bilinear, ResizeFilter::Lanczos] {
+            // the same size is the same image, and a flat one stays flat
+            assert_eq!(pixels().resize(3, 2, filter).unwrap(), pixels());
+            let flat = Pixels { samples: vec![77; 6], ..pixels() };
+            assert!(flat.resize(7, 5, filter).unwrap().samples.iter().all(|&sample| sample == 77));
+            assert!(flat.resize(2, 1, filter).unwrap().samples.iter().all(|&sample| sample == 77));
+        }
+        let ramp = Pixels { width: 2, height: 1, samples: vec![0, 100], ..pixels() };
+        assert_eq!(ramp.resize(4, 1, ResizeFilter::Bilinear).unwrap().samples, [0, 25, 75, 100]);
+        // sub-byte samples come out as 8-bit
+        let bits = Pixels { width: 2, height: 1, bit_depth: 1, samples: vec![1, 1], ..pixels() };
+        let resized = bits.resize(3, 1, ResizeFilter::Bilinear).unwrap();
+        assert_eq!((resized.bit_depth, resized.samples), (8, vec![255; 3]));
+    }
+
+    #[test]
+    fn test_resize_weights_color_by_alpha() {
+        // an invisible red pixel doesn't tint the opaque blue one it's averaged with
+        let pixels = Pixels { width: 2, height: 1, color_type: ColorType::Rgba, bit_depth: 8, samples: vec![255, 0, 0, 0, 0, 0, 255, 255] };
+        let resized = pixels.resize(1, 1, ResizeFilter::Bilinear).unwrap();
+        assert_eq!(resized.samples, [0, 0, 255, 128]);
+    }
+
+    #[test]
+    fn test_scaled_size() {
+        assert_eq!(scaled_size(330, 247, Some(100), Some(100), false), (100, 100));
+        assert_eq!(scaled_size(330, 247, Some(100), Some(100), true), (100, 75));
+        assert_eq!(scaled_size(330, 247, None, Some(494), false), (660, 494));
+        assert_eq!(scaled_size(1000, 1, Some(10), None, false), (10, 1));
+        assert_eq!(scaled_size(330, 247, None, None, true), (330, 247));
+    }
+
+    #[test]
+    fn test_continuous_pixels_and_physical_axes() {
+        let palette = Chunk::new(ChunkType::PLTE, vec![255, 0, 0, 0, 0, 255]);
+        let mut png = crate::png::Png::from_chunks(vec![
+            crate::ihdr::Ihdr { width: 2, height: 1, bit_depth: 1, color_type: ColorType::Indexed, interlaced: false }.to_chunk(),
+            palette,
+            Chunk::new(ChunkType::PHYS, vec![0, 0, 0, 1, 0, 0, 0, 2, 1]),
+            Chunk::new(ChunkType::IDAT, crate::deflate::deflate(&[0, 0b0100_0000], Default::default())),
+            Chunk::new(ChunkType::IEND, Vec::new()),
+        ]);
+        let pixels = png.continuous_pixels().unwrap();
+        assert_eq!((pixels.color_type, pixels.samples), (ColorType::Rgb, vec![255, 0, 0, 0, 0, 255]));
+        png.swap_physical_axes();
+        assert_eq!(png.chunks()[2].data(), [0, 0, 0, 2, 0, 0, 0, 1, 1]);
+    }
+}
@@ -0,0 +1,435 @@
+use thiserror::Error;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::decode::{DecodeError, Image};
+use crate::ihdr::{ColorType, Ihdr, IhdrError};
+use crate::png::Png;
+
+// Unpacked pixels, one u16 per sample whatever the bit depth, and conversions
+// between bit depths and color types. Samples are scaled with rounding, never
+// truncated, so white stays white and black stays black at every depth.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ConvertError {
+    #[error("{0}")]
+    Ihdr(#[from] IhdrError),
+    #[error("Indexed pixels need a palette")]
+    MissingPalette,
+    #[error("Palette index {0} is out of range, the palette has {1} entries")]
+    Index(u16, usize),
+    #[error("Color {0:?} isn't in the palette")]
+    NotInPalette([u8; 4]),
+    #[error("Image has more than 256 colors, too many for a palette")]
+    TooManyColors,
+    #[error("sBIT has {0} values, {1} images need {2}")]
+    SbitLength(usize, ColorType, usize),
+    #[error("sBIT value {0} is outside 1 to {1}")]
+    SbitValue(u8, u8),
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pixels {
+    pub width: u32,
+    pub height: u32,
+    pub color_type: ColorType,
+    pub bit_depth: u8,
+    // row by row, `color_type.channels()` samples per pixel
+    pub samples: Vec<u16>,
+}
+// PLTE colors and their tRNS alpha, which may cover fewer entries: the rest are opaque
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Palette {
+    pub colors: Vec<[u8; 3]>,
+    pub alpha: Vec<u8>,
+}
+// Reads `count` samples from a packed row, sub-byte ones starting from the high
+// bits and 16-bit ones big-endian
+pub fn unpack(row: &[u8], bit_depth: u8, count: usize) -> Vec<u16> {
+    match bit_depth {
+        16 => row.chunks_exact(2).take(count).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect(),
+        8 => row.iter().take(count).map(|&byte| byte as u16).collect(),
+        _ => {
+            let bits = bit_depth as usize;
+            let mask = (1u16 << bits) - 1;
+            (0..count).map(|i| (row[i * bits / 8] as u16 >> (8 - bits - i * bits % 8)) & mask).collect()
+        }
+    }
+}
+// The reverse of `unpack`, padding the last byte with zeros
+pub fn pack(samples: &[u16], bit_depth: u8) -> Vec<u8> {
+    match bit_depth {
+        16 => samples.iter().flat_map(|sample| sample.to_be_bytes()).collect(),
+        8 => samples.iter().map(|&sample| sample as u8).collect(),
+        _ => {
+            let bits = bit_depth as usize;
+            let mut out = vec![0u8; (samples.len() * bits).div_ceil(8)];
+            for (i, &sample) in samples.iter().enumerate() {
+                out[i * bits / 8] |= (sample as u8) << (8 - bits - i * bits % 8);
+            }
+            out
+        }
+    }
+}
+// Rescales a sample from one bit depth to another, rounding to the nearest value
+pub fn scale(sample: u16, from: u8, to: u8) -> u16 {
+    if from == to {
+        return sample;
+    }
+    let (from_max, to_max) = ((1u32 << from) - 1, (1u32 << to) - 1);
+    ((sample as u32 * to_max + from_max / 2) / from_max) as u16
+}
+// Rec. 709 luma weights out of 2^15, the ones libpng uses for RGB to gray
+fn luminance(red: u16, green: u16, blue: u16) -> u16 {
+    ((6966 * red as u64 + 23436 * green as u64 + 2366 * blue as u64 + (1 << 14)) >> 15) as u16
+}
+impl Pixels {
+    pub fn channels(&self) -> usize {
+        self.color_type.channels() as usize
+    }
+    pub fn max_sample(&self) -> u16 {
+        ((1u32 << self.bit_depth) - 1) as u16
+    }
+    pub fn pixel(&self, x: u32, y: u32) -> &[u16] {
+        let start = (y as usize * self.width as usize + x as usize) * self.channels();
+        &self.samples[start..start + self.channels()]
+    }
+    // Errors if the bit depth isn't one PNG allows for the color type
+    pub fn ihdr(&self) -> Result<Ihdr, ConvertError> {
+        if !self.color_type.allowed_bit_depths().contains(&self.bit_depth) {
+            return Err(IhdrError::BitDepth(self.bit_depth, self.color_type).into());
+        }
+        Ok(Ihdr { width: self.width, height: self.height, bit_depth: self.bit_depth, color_type: self.color_type, interlaced: false })
+    }
+    // packs the samples back into non-interlaced scanlines
+    pub fn to_image(&self) -> Result<Image, ConvertError> {
+        let ihdr = self.ihdr()?;
+        let row_samples = self.width as usize * self.channels();
+        let data = self.samples.chunks(row_samples.max(1)).flat_map(|row| pack(row, self.bit_depth)).collect();
+        Ok(Image { ihdr, data })
+    }
+    fn map_samples<F: Fn(&[u16], &mut Vec<u16>)>(&self, color_type: ColorType, bit_depth: u8, f: F) -> Pixels {
+        let mut samples = Vec::with_capacity(self.width as usize * self.height as usize * color_type.channels() as usize);
+        for pixel in self.samples.chunks(self.channels()) {
+            f(pixel, &mut samples);
+        }
+        Pixels { width: self.width, height: self.height, color_type, bit_depth, samples }
+    }
+    // Indexed pixels keep their values, which have to fit the new depth
+    pub fn to_bit_depth(&self, bit_depth: u8) -> Result<Pixels, ConvertError> {
+        if !(1..=16).contains(&bit_depth) {
+            return Err(IhdrError::BitDepth(bit_depth, self.color_type).into());
+        }
+        if self.color_type == ColorType::Indexed {
+            let max = ((1u32 << bit_depth) - 1) as u16;
+            if let Some(&index) = self.samples.iter().find(|&&index| index > max) {
+                return Err(ConvertError::Index(index, max as usize + 1));
+            }
+            return Ok(Pixels { bit_depth, ..self.clone() });
+        }
+        let samples = self.samples.iter().map(|&sample| scale(sample, self.bit_depth, bit_depth)).collect();
+        Ok(Pixels { bit_depth, samples, ..self.clone() })
+    }
+    // Converts between color types: gray becomes RGB by copying, RGB becomes gray
+    // by its luminance, alpha is dropped or added as opaque. Indexed pixels are
+    // looked up in `palette` and become 8-bit; to go to indexed every color has
+    // to be in `palette` and the smallest bit depth that fits it is used. Depths
+    // the new color type doesn't allow are raised to 8.
+    pub fn to_color_type(&self, color_type: ColorType, palette: Option<&Palette>) -> Result<Pixels, ConvertError> {
+        if self.color_type == color_type {
+            return Ok(self.clone());
+        }
+        if self.color_type == ColorType::Indexed {
+            let palette = palette.ok_or(ConvertError::MissingPalette)?;
+            return palette.expand(self)?.to_color_type(color_type, None);
+        }
+        if color_type == ColorType::Indexed {
+            let palette = palette.ok_or(ConvertError::MissingPalette)?;
+            return palette.index(self);
+        }
+        let bit_depth = if color_type.allowed_bit_depths().contains(&self.bit_depth) { self.bit_depth } else { 8 };
+        let source = self.to_bit_depth(bit_depth)?;
+        let opaque = source.max_sample();
+        let (from_color, from_alpha) = (self.color_type, self.color_type.has_alpha());
+        let to_alpha = color_type.has_alpha();
+        Ok(source.map_samples(color_type, bit_depth, |pixel, out| {
+            let alpha = if from_alpha { pixel[pixel.len() - 1] } else { opaque };
+            match (from_color, color_type) {
+                (ColorType::Grayscale | ColorType::GrayscaleAlpha, ColorType::Rgb | ColorType::Rgba) => {
+                    out.extend([pixel[0]; 3])
+                }
+                (ColorType::Rgb | ColorType::Rgba, ColorType::Grayscale | ColorType::GrayscaleAlpha) => {
+                    out.push(luminance(pixel[0], pixel[1], pixel[2]))
+                }
+                (ColorType::Grayscale | ColorType::GrayscaleAlpha, _) => out.push(pixel[0]),
+                _ => out.extend_from_slice(&pixel[..3]),
+            }
+            if to_alpha {
+                out.push(alpha);
+            }
+        }))
+    }
+    // Adds alpha from a tRNS color: pixels of exactly that color become fully
+    // transparent, everything else opaque. Gray with alpha is at least 8-bit,
+    // so sub-byte gray is rescaled to 8 bits.
+    pub fn with_transparent_color(&self, color: &[u16]) -> Pixels {
+        let color_type = match self.color_type {
+            ColorType::Grayscale => ColorType::GrayscaleAlpha,
+            ColorType::Rgb => ColorType::Rgba,
+            _ => return self.clone(),
+        };
+        let bit_depth = self.bit_depth.max(8);
+        let opaque = ((1u32 << bit_depth) - 1) as u16;
+        self.map_samples(color_type, bit_depth, |pixel, out| {
+            out.extend(pixel.iter().map(|&sample| scale(sample, self.bit_depth, bit_depth)));
+            out.push(if pixel == color { 0 } else { opaque });
+        })
+    }
+    // Rescales samples that only have `sbit` significant bits per channel, as
+    // an sBIT chunk declares, to use the whole range of the bit depth. Indexed
+    // pixels are left alone, their sBIT is about the palette.
+    pub fn expand_significant_bits(&self, sbit: &[u8]) -> Result<Pixels, ConvertError> {
+        if self.color_type == ColorType::Indexed {
+            return Ok(self.clone());
+        }
+        if sbit.len() != self.channels() {
+            return Err(ConvertError::SbitLength(sbit.len(), self.color_type, self.channels()));
+        }
+        if let Some(&bits) = sbit.iter().find(|&&bits| bits == 0 || bits > self.bit_depth) {
+            return Err(ConvertError::SbitValue(bits, self.bit_depth));
+        }
+        let depth = self.bit_depth;
+        Ok(self.map_samples(self.color_type, depth, |pixel, out| {
+            out.extend(pixel.iter().zip(sbit).map(|(&sample, &bits)| scale(sample >> (depth - bits), bits, depth)));
+        }))
+    }
+}
+impl Palette {
+    pub fn rgba(&self, index: usize) -> Option<[u8; 4]> {
+        let [red, green, blue] = *self.colors.get(index)?;
+        Some([red, green, blue, self.alpha.get(index).copied().unwrap_or(u8::MAX)])
+    }
+    // The distinct colors of `pixels` at 8 bits, translucent ones first so tRNS
+    // stays short
+    pub fn from_pixels(pixels: &Pixels) -> Result<Palette, ConvertError> {
+        let rgba = pixels.to_color_type(ColorType::Rgba, None)?.to_bit_depth(8)?;
+        let mut colors: Vec<[u8; 4]> = Vec::new();
+        let mut seen = std::collections::HashSet::new();
+        for pixel in rgba.samples.chunks(4) {
+            let color = [pixel[0] as u8, pixel[1] as u8, pixel[2] as u8, pixel[3] as u8];
+            if seen.insert(color) {
+                if colors.len() == 256 {
+                    return Err(ConvertError::TooManyColors);
+                }
+                colors.push(color);
+            }
+        }
+        colors.sort_by_key(|color| color[3] == u8::MAX);
+        let translucent = colors.iter().take_while(|color| color[3] < u8::MAX).count();
+        Ok(Palette {
+            colors: colors.iter().map(|&[red, green, blue, _]| [red, green, blue]).collect(),
+            alpha: colors[..translucent].iter().map(|color| color[3]).collect(),
+        })
+    }
+    // RGBA at 8 bits, or RGB when the palette has no transparency
+    fn expand(&self, pixels: &Pixels) -> Result<Pixels, ConvertError> {
+        let color_type = if self.alpha.is_empty() { ColorType::Rgb } else { ColorType::Rgba };
+        let mut samples = Vec::with_capacity(pixels.samples.len() * color_type.channels() as usize);
+        for &index in &pixels.samples {
+            let rgba = self.rgba(index as usize).ok_or(ConvertError::Index(index, self.colors.len()))?;
+            samples.extend(rgba[..color_type.channels() as usize].iter().map(|&sample| sample as u16));
+        }
+        Ok(Pixels { width: pixels.width, height: pixels.height, color_type, bit_depth: 8, samples })
+    }
+    fn index(&self, pixels: &Pixels) -> Result<Pixels, ConvertError> {
+        let lookup: std::collections::HashMap<[u8; 4], u16> =
+            (0..self.colors.len()).rev().map(|index| (self.rgba(index).unwrap(), index as u16)).collect();
+        let rgba = pixels.to_color_type(ColorType::Rgba, None)?.to_bit_depth(8)?;
+        let samples = rgba.samples
+            .chunks(4)
+            .map(|pixel| {
+                let color = [pixel[0] as u8, pixel[1] as u8, pixel[2] as u8, pixel[3] as u8];
+                lookup.get(&color).copied().ok_or(ConvertError::NotInPalette(color))
+            })
+            .collect::<Result<_, _>>()?;
+        let bit_depth = [1, 2, 4, 8].into_iter().find(|&depth| self.colors.len() <= 1 << depth).unwrap_or(8);
+        Ok(Pixels { width: pixels.width, height: pixels.height, color_type: ColorType::Indexed, bit_depth, samples })
+    }
+    // PLTE, followed by tRNS if any entry isn't opaque
+    pub fn to_chunks(&self) -> Vec<Chunk> {
+        let mut chunks = vec![Chunk::new(ChunkType::PLTE, self.colors.concat())];
+        if self.alpha.iter().any(|&alpha| alpha < u8::MAX) {
+            chunks.push(Chunk::new(ChunkType::TRNS, self.alpha.clone()));
+        }
+        chunks
+    }
+}
+impl Image {
+    pub fn to_pixels(&self) -> Pixels {
+        let row_samples = self.ihdr.width as usize * self.ihdr.color_type.channels() as usize;
+        let row_bytes = self.ihdr.row_bytes();
+        let samples = self.data.chunks(row_bytes.max(1)).flat_map(|row| unpack(row, self.ihdr.bit_depth, row_samples)).collect();
+        Pixels {
+            width: self.ihdr.width,
+            height: self.ihdr.height,
+            color_type: self.ihdr.color_type,
+            bit_depth: self.ihdr.bit_depth,
+            samples,
+        }
+    }
+}
+impl Png {
+    pub fn pixels(&self) -> Result<Pixels, DecodeError> {
+        Ok(self.decode()?.to_pixels())
+    }
+    // None without a PLTE chunk
+    pub fn palette(&self) -> Option<Palette> {
+        let plte = self.chunks_by_type(ChunkType::PLTE).next()?;
+        let colors = plte.data().chunks_exact(3).map(|rgb| [rgb[0], rgb[1], rgb[2]]).collect();
+        let alpha = match self.ihdr() {
+            Ok(ihdr) if ihdr.color_type == ColorType::Indexed => {
+                self.chunks_by_type(ChunkType::TRNS).next().map_or_else(Vec::new, |trns| trns.data().to_vec())
+            }
+            _ => Vec::new(),
+        };
+        Some(Palette { colors, alpha })
+    }
+    // The tRNS color of a gray or RGB image, as samples
+    pub fn transparent_color(&self) -> Option<Vec<u16>> {
+        let ihdr = self.ihdr().ok()?;
+        let trns = self.chunks_by_type(ChunkType::TRNS).next()?;
+        let channels = match ihdr.color_type {
+            ColorType::Grayscale | ColorType::Rgb => ihdr.color_type.channels() as usize,
+            _ => return None,
+        };
+        if trns.data().len() != 2 * channels {
+            return None;
+        }
+        Some(unpack(trns.data(), 16, channels))
+    }
+    // The sBIT values, one per channel and three for indexed images. None without
+    // sBIT, or without a valid IHDR to make sense of it.
+    pub fn significant_bits(&self) -> Result<Option<Vec<u8>>, ConvertError> {
+        let (Some(sbit), Ok(ihdr)) = (self.chunks_by_type(ChunkType::SBIT).next(), self.ihdr()) else {
+            return Ok(None);
+        };
+        let (channels, depth) = match ihdr.color_type {
+            ColorType::Indexed => (3, 8),
+            color_type => (color_type.channels() as usize, ihdr.bit_depth),
+        };
+        if sbit.data().len() != channels {
+            return Err(ConvertError::SbitLength(sbit.data().len(), ihdr.color_type, channels));
+        }
+        if let Some(&bits) = sbit.data().iter().find(|&&bits| bits == 0 || bits > depth) {
+            return Err(ConvertError::SbitValue(bits, depth));
+        }
+        Ok(Some(sbit.data().to_vec()))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn pixels(color_type: ColorType, bit_depth: u8, samples: Vec<u16>) -> Pixels {
+        let width = (samples.len() / color_type.channels() as usize) as u32;
+        Pixels { width, height: 1, color_type, bit_depth, samples }
+    }
+
+    // samples spread over the whole range of the bit depth, no two neighbours alike
+    pub(crate) fn pattern(width: u32, height: u32, color_type: ColorType, bit_depth: u8) -> Pixels {
+        let count = width as usize * height as usize * color_type.channels() as usize;
+        let max = (1u32 << bit_depth) - 1;
+        let samples = (0..count).map(|i| (i as u32 * 7919 % (max + 1)) as u16).collect();
+        Pixels { width, height, color_type, bit_depth, samples }
+    }
+
+    #[test]
+    fn test_pack_unpack() {
+        assert_eq!(unpack(&[0b1011_0001], 2, 4), [2, 3, 0, 1]);
+        assert_eq!(unpack(&[0b1010_0000], 1, 3), [1, 0, 1]);
+        assert_eq!(unpack(&[0x12, 0x34, 0xff, 0xfe], 16, 2), [0x1234, 0xfffe]);
+        assert_eq!(pack(&[2, 3, 0, 1], 2), [0b1011_0001]);
+        assert_eq!(pack(&[0xf, 0x1, 0x7], 4), [0xf1, 0x70]);
+        assert_eq!(pack(&[0x1234], 16), [0x12, 0x34]);
+    }
+
+    #[test]
+    fn test_scale() {
+        assert_eq!(scale(1, 1, 8), 255);
+        assert_eq!(scale(2, 2, 8), 170);
+        assert_eq!(scale(0xab, 8, 16), 0xabab);
+        assert_eq!(scale(0xabab, 16, 8), 0xab);
+        // rounded, where truncating would give 0x12
+        assert_eq!(scale(0x12ff, 16, 8), 0x13);
+        assert_eq!(scale(31, 5, 8), 255);
+        assert_eq!(scale(255, 8, 1), 1);
+        assert_eq!(scale(127, 8, 1), 0);
+    }
+
+    #[test]
+    fn test_to_bit_depth() {
+        let gray = pixels(ColorType::Grayscale, 2, vec![0, 1, 2, 3]);
+        assert_eq!(gray.to_bit_depth(8).unwrap().samples, [0, 85, 170, 255]);
+        assert_eq!(gray.to_bit_depth(16).unwrap().to_bit_depth(2).unwrap(), gray);
+        let indexed = pixels(ColorType::Indexed, 8, vec![0, 3, 5]);
+        assert_eq!(indexed.to_bit_depth(4).unwrap().samples, [0, 3, 5]);
+        assert_eq!(indexed.to_bit_depth(2), Err(ConvertError::Index(5, 4)));
+    }
+
+    #[test]
+    fn test_to_color_type() {
+        let gray = pixels(ColorType::Grayscale, 4, vec![0, 15]);
+        let rgba = gray.to_color_type(ColorType::Rgba, None).unwrap();
+        assert_eq!((rgba.bit_depth, rgba.samples.as_slice()), (8, &[0, 0, 0, 255, 255, 255, 255, 255][..]));
+        let rgb = pixels(ColorType::Rgb, 16, vec![65535, 0, 0, 0, 65535, 0]);
+        let gray = rgb.to_color_type(ColorType::GrayscaleAlpha, None).unwrap();
+        assert_eq!(gray.samples, [13932, 65535, 46871, 65535]);
+        let rgba = pixels(ColorType::Rgba, 8, vec![1, 2, 3, 4]);
+        assert_eq!(rgba.to_color_type(ColorType::Rgb, None).unwrap().samples, [1, 2, 3]);
+        assert_eq!(rgba.to_color_type(ColorType::Indexed, None), Err(ConvertError::MissingPalette));
+    }
+
+    #[test]
+    fn test_palette_round_trip() {
+        let rgba = pixels(ColorType::Rgba, 8, vec![10, 20, 30, 255, 1, 2, 3, 0, 10, 20, 30, 255]);
+        let palette = Palette::from_pixels(&rgba).unwrap();
+        assert_eq!(palette.colors, [[1, 2, 3], [10, 20, 30]]);
+        assert_eq!(palette.alpha, [0]);
+        let indexed = rgba.to_color_type(ColorType::Indexed, Some(&palette)).unwrap();
+        assert_eq!((indexed.bit_depth, indexed.samples.as_slice()), (1, &[1, 0, 1][..]));
+        assert_eq!(indexed.to_color_type(ColorType::Rgba, Some(&palette)).unwrap(), rgba);
+        let chunks = palette.to_chunks();
+        assert_eq!(chunks[1].data(), &[0]);
+        let other = Palette { colors: vec![[0, 0, 0]], alpha: Vec::new() };
+        assert_eq!(rgba.to_color_type(ColorType::Indexed, Some(&other)), Err(ConvertError::NotInPalette([10, 20, 30, 255])));
+        let many = pixels(ColorType::Rgb, 8, (0..300).flat_map(|i| [i % 256, i / 256, 0]).collect());
+        assert_eq!(Palette::from_pixels(&many), Err(ConvertError::TooManyColors));
+    }
+
+    #[test]
+    fn test_transparent_color() {
+        let rgb = pixels(ColorType::Rgb, 8, vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(rgb.with_transparent_color(&[4, 5, 6]).samples, [1, 2, 3, 255, 4, 5, 6, 0]);
+        // gray with alpha can't be 1-bit
+        let gray = pixels(ColorType::Grayscale, 1, vec![0, 1]).with_transparent_color(&[1]);
+        assert_eq!((gray.color_type, gray.bit_depth, gray.samples.clone()), (ColorType::GrayscaleAlpha, 8, vec![0, 255, 255, 0]));
+        assert!(gray.to_image().is_ok());
+    }
+
+    #[test]
+    fn test_expand_significant_bits() {
+        // 3 significant bits in 8, replicated the way the spec suggests
+        let gray = pixels(ColorType::GrayscaleAlpha, 8, vec![0b1111_1111, 0b1001_0010]);
+        let expanded = gray.expand_significant_bits(&[3, 3]).unwrap();
+        assert_eq!(expanded.samples, [255, scale(0b100, 3, 8)]);
+        assert!(matches!(gray.expand_significant_bits(&[3]), Err(ConvertError::SbitLength(1, _, 2))));
+        assert_eq!(gray.expand_significant_bits(&[9, 1]), Err(ConvertError::SbitValue(9, 8)));
+    }
+
+    #[test]
+    fn test_image_round_trip() {
+        let png = Png::try_from(&include_bytes!("../dice.png")[..]).unwrap();
+        let image = png.decode().unwrap();
+        let pixels = image.to_pixels();
+        assert_eq!(pixels.pixel(0, 0).len(), 4);
+        assert_eq!(pixels.to_image().unwrap(), image);
+    }
+}
@@ -1,14 +1,20 @@
 #![allow(unused_variables)]
 use core::{fmt, iter::Iterator, result::Result};
-use std::io::{BufReader, Read};
-use crate::chunk::{Chunk, ChunkError};
+use std::collections::HashMap;
+use crate::chunk::{Chunk, ChunkError, ChunkRef};
+use crate::chunk_type::ChunkType;
+use crate::ihdr::{Ihdr, IhdrError};
+use crate::limits::{LimitError, Limits};
+use crate::signature::Mangling;
+use crate::validate::constraint;
 use thiserror::Error;
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Png {
     signature: [u8;8],
     chunks: Vec<Chunk>
 }
 impl Png {
-    const STANDARD_SIGNATURE: [u8;8] = [137, 80, 78, 71, 13, 10, 26, 10];
+    pub const STANDARD_SIGNATURE: [u8;8] = [137, 80, 78, 71, 13, 10, 26, 10];
     // even though IHDR is important, the tests don't cover it
     // Maybe after I finish the book I'll make it spec compliant
     // const IHDR_LEN: u32 = 13;
@@ -17,46 +23,134 @@ impl Png {
 pub enum PngError {
     #[error("Bad Png Signature")]
     PngSignature,
-    // #[error("IHDR chunk either not present or invalid")]
-    // IHDR,
-    #[error("Couldn't get {0} bytes of data from index {1}")]
-    Buffer(usize, usize),
+    #[error("Bad Png Signature: {0}")]
+    MangledSignature(Mangling),
+    #[error("Invalid IHDR chunk: {0}")]
+    Ihdr(#[from] IhdrError),
     #[error("Invalid chunk: {0}")]
     ChunkType(#[from] ChunkError),
     #[error("No chunk to delete")]
     NoChunk,
+    #[error("No {0} chunk in png")]
+    MissingChunk(ChunkType),
+    #[error("Can't insert at index {0}, png only has {1} chunks")]
+    Position(usize, usize),
+    #[error("{0} is a critical chunk")]
+    Critical(ChunkType),
+    #[error("{0}")]
+    Limit(#[from] LimitError),
 }
-fn read_exact(reader: &mut BufReader<&[u8]>, buf: &mut [u8], index: &mut usize) -> Result<(), PngError> {
-    let res: Result<(), PngError> = reader
-        .read_exact(buf)
-        .map_err(|_| PngError::Buffer(buf.len(), *index));
-    *index += buf.len();
-    res
+// The earliest critical chunk the spec requires an ancillary chunk to come before
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MustPrecede {
+    Plte,
+    Idat,
+    Iend,
+}
+impl MustPrecede {
+    // after-PLTE chunks also have to come before IDAT, which is where they end up
+    fn of(ctype: ChunkType) -> MustPrecede {
+        match constraint(ctype) {
+            Some(constraint) if constraint.before_plte => MustPrecede::Plte,
+            Some(_) if ctype == ChunkType::TIME => MustPrecede::Iend,
+            _ => MustPrecede::Idat,
+        }
+    }
 }
 impl TryFrom<&[u8]> for Png {
     type Error = PngError;
     fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
-        let mut reader = BufReader::new(bytes);
-        // there is probably a better way to do this. cursor crate or sth
-        let mut index: usize = 0;
-        let mut signature = [0u8; 8];
-        read_exact(&mut reader, &mut signature, &mut index)?;
-        if signature != Png::STANDARD_SIGNATURE {
-            return Err(PngError::PngSignature);
+        Png::from_bytes_with_limits(bytes, Limits::default())
+    }
+}
+// Borrowed counterpart to `Png` for scanning: chunks are parsed lazily out of the
+// input slice, and crcs are only checked when `verify_crc` is turned on.
+pub struct PngRef<'a> {
+    signature: &'a [u8; 8],
+    body: &'a [u8],
+    verify_crc: bool,
+    limits: Limits,
+}
+impl<'a> TryFrom<&'a [u8]> for PngRef<'a> {
+    type Error = PngError;
+    fn try_from(bytes: &'a [u8]) -> Result<Self, Self::Error> {
+        if !bytes.starts_with(&Png::STANDARD_SIGNATURE) {
+            return Err(Mangling::diagnose(bytes).map_or(PngError::PngSignature, PngError::MangledSignature));
+        }
+        let (signature, body) = bytes.split_at(8);
+        Ok(PngRef { signature: signature.try_into().unwrap(), body, verify_crc: false, limits: Limits::default() })
+    }
+}
+impl<'a> PngRef<'a> {
+    pub fn verify_crc(self, verify_crc: bool) -> Self {
+        PngRef { verify_crc, ..self }
+    }
+    pub fn limits(self, limits: Limits) -> Self {
+        PngRef { limits, ..self }
+    }
+    pub fn header(&self) -> &'a [u8; 8] {
+        self.signature
+    }
+    pub fn chunks(&self) -> ChunkRefs<'a> {
+        ChunkRefs { rest: self.body, verify_crc: self.verify_crc, limits: self.limits, count: 0 }
+    }
+    pub fn chunk_by_type(&self, ctype: &ChunkType) -> Result<Option<ChunkRef<'a>>, PngError> {
+        for chunk in self.chunks() {
+            let chunk = chunk?;
+            if chunk.chunk_type() == ctype {
+                return Ok(Some(chunk));
+            }
+        }
+        Ok(None)
+    }
+    pub fn to_png(&self) -> Result<Png, PngError> {
+        let chunks = self.chunks()
+            .map(|chunk| chunk.map(|chunk| chunk.to_chunk()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Png { signature: *self.signature, chunks })
+    }
+}
+// Stops after the first error, since there's no telling where the next chunk starts
+pub struct ChunkRefs<'a> {
+    rest: &'a [u8],
+    verify_crc: bool,
+    limits: Limits,
+    count: usize,
+}
+impl ChunkRefs<'_> {
+    fn check_limits(&mut self, chunk: &ChunkRef) -> Result<(), LimitError> {
+        self.count += 1;
+        self.limits.check_chunks(self.count)?;
+        self.limits.check_chunk_length(*chunk.chunk_type(), chunk.length())?;
+        if *chunk.chunk_type() == ChunkType::IHDR && chunk.data().len() >= 8 {
+            let width = u32::from_be_bytes(chunk.data()[0..4].try_into().unwrap());
+            let height = u32::from_be_bytes(chunk.data()[4..8].try_into().unwrap());
+            self.limits.check_dimensions(width, height)?;
         }
-        let mut chunks = Vec::new();
-        let mut length_buf = [0u8;4];
-        while let Ok(()) = read_exact(&mut reader, &mut length_buf, &mut index) {
-            let rest_len = u32::from_be_bytes(length_buf) as usize + 8;
-            let mut rest_buf = vec![0; rest_len];
-            read_exact(&mut reader, &mut rest_buf, &mut index)?;
-            let chunk_buffer: Vec<u8> = [&length_buf[..], &rest_buf[..]].concat();
-            let chunk = Chunk::try_from(chunk_buffer.as_slice())?;
-            chunks.push(chunk);
+        Ok(())
+    }
+}
+impl<'a> Iterator for ChunkRefs<'a> {
+    type Item = Result<ChunkRef<'a>, PngError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        let parsed = ChunkRef::parse(self.rest).map_err(PngError::from).and_then(|(chunk, rest)| {
+            let chunk = if self.verify_crc { chunk.verify_crc()? } else { chunk };
+            self.check_limits(&chunk)?;
+            Ok((chunk, rest))
+        });
+        match parsed {
+            Ok((chunk, rest)) => {
+                self.rest = rest;
+                Some(Ok(chunk))
+            }
+            Err(e) => {
+                self.rest = &[];
+                Some(Err(e))
+            }
         }
-        // TODO: Add a check for critiical chunks. See https://www.libpng.org/pub/png/spec/1.2/PNG-Chunks.html
-        // currently can't do that because of the tests
-        Ok(Png{signature, chunks})
     }
 }
 impl fmt::Display for Png {
@@ -64,18 +158,58 @@ impl fmt::Display for Png {
         write!(f, "[{}]", self.chunks.iter().map(|chunk| format!("{}", chunk)).collect::<Vec<_>>().join(",\n"))
     }
 }
-#[allow(dead_code)]
 impl Png {
-    fn from_chunks(chunks: Vec<Chunk>) -> Png {
+    pub fn from_bytes_with_limits(bytes: &[u8], limits: Limits) -> Result<Png, PngError> {
+        PngRef::try_from(bytes)?.verify_crc(true).limits(limits).to_png()
+    }
+    pub fn from_chunks(chunks: Vec<Chunk>) -> Png {
         Png{signature: Png::STANDARD_SIGNATURE, chunks}
     }
     pub fn append_chunk(&mut self, chunk: Chunk) {
         self.chunks.push(chunk);
     }
+    pub fn insert_chunk_at(&mut self, index: usize, chunk: Chunk) -> Result<(), PngError> {
+        if index > self.chunks.len() {
+            return Err(PngError::Position(index, self.chunks.len()));
+        }
+        self.chunks.insert(index, chunk);
+        Ok(())
+    }
+    // before the first chunk of `ctype`
+    pub fn insert_before(&mut self, ctype: ChunkType, chunk: Chunk) -> Result<usize, PngError> {
+        let index = self.position_of(ctype).ok_or(PngError::MissingChunk(ctype))?;
+        self.chunks.insert(index, chunk);
+        Ok(index)
+    }
+    // after the last chunk of `ctype`, so consecutive IDATs stay together
+    pub fn insert_after(&mut self, ctype: ChunkType, chunk: Chunk) -> Result<usize, PngError> {
+        let index = self.chunks.iter()
+            .rposition(|ch| ch.ctype == ctype)
+            .ok_or(PngError::MissingChunk(ctype))? + 1;
+        self.chunks.insert(index, chunk);
+        Ok(index)
+    }
+    // Inserts at the end of the region the spec allows for the chunk type, which
+    // is never after IEND. Chunks with no placement rule, text included, go before IDAT.
+    pub fn insert_ancillary(&mut self, chunk: Chunk) -> Result<usize, PngError> {
+        if chunk.ctype.is_critical() {
+            return Err(PngError::Critical(chunk.ctype));
+        }
+        let before = match MustPrecede::of(chunk.ctype) {
+            MustPrecede::Plte => [ChunkType::PLTE, ChunkType::IDAT, ChunkType::IEND].as_slice(),
+            MustPrecede::Idat => &[ChunkType::IDAT, ChunkType::IEND],
+            MustPrecede::Iend => &[ChunkType::IEND],
+        };
+        let index = before.iter()
+            .find_map(|&ctype| self.position_of(ctype))
+            .unwrap_or(self.chunks.len());
+        self.chunks.insert(index, chunk);
+        Ok(index)
+    }
     pub fn remove_first_chunk(&mut self, chunk_type: &str) -> Result<Chunk, PngError> {
-        let pos = self.chunks.iter()
-            .position(|ch| format!("{}", ch.ctype) == chunk_type);
-    
+        let pos = chunk_type.parse().ok()
+            .and_then(|ctype| self.position_of(ctype));
+
         if let Some(pos) = pos {
             // Remove and return the chunk at this position
             Ok(self.chunks.remove(pos))
@@ -83,15 +217,64 @@ impl Png {
             Err(PngError::NoChunk)
         }
     }
-    fn header(&self) -> &[u8; 8] {
+    // Removes the `n`th chunk, counting from 0, for which `f` returns true
+    pub fn remove_nth_where<F: FnMut(&Chunk) -> bool>(&mut self, n: usize, mut f: F) -> Result<Chunk, PngError> {
+        let pos = self.chunks.iter()
+            .enumerate()
+            .filter(|(_, chunk)| f(chunk))
+            .nth(n)
+            .map(|(pos, _)| pos)
+            .ok_or(PngError::NoChunk)?;
+        Ok(self.chunks.remove(pos))
+    }
+    pub fn remove_nth(&mut self, ctype: ChunkType, n: usize) -> Result<Chunk, PngError> {
+        self.remove_nth_where(n, |chunk| chunk.ctype == ctype)
+    }
+    // Removes every chunk for which `f` returns true, keeping the order of the rest
+    pub fn remove_where<F: FnMut(&Chunk) -> bool>(&mut self, f: F) -> Vec<Chunk> {
+        let (removed, kept) = std::mem::take(&mut self.chunks)
+            .into_iter()
+            .partition(f);
+        self.chunks = kept;
+        removed
+    }
+    pub fn remove_all(&mut self, ctype: ChunkType) -> Vec<Chunk> {
+        self.remove_where(|chunk| chunk.ctype == ctype)
+    }
+    pub fn retain<F: FnMut(&Chunk) -> bool>(&mut self, f: F) {
+        self.chunks.retain(f);
+    }
+    // Swaps every IDAT for `idat`, put where the first one was or else before IEND
+    pub fn replace_image_data(&mut self, idat: Vec<Chunk>) -> Result<(), PngError> {
+        let index = self.position_of(ChunkType::IDAT)
+            .or_else(|| self.position_of(ChunkType::IEND))
+            .ok_or(PngError::MissingChunk(ChunkType::IEND))?;
+        self.remove_all(ChunkType::IDAT);
+        self.chunks.splice(index..index, idat);
+        Ok(())
+    }
+    pub fn header(&self) -> &[u8; 8] {
         &self.signature
     }
-    fn chunks(&self) -> &[Chunk] {
+    pub fn chunks(&self) -> &[Chunk] {
         self.chunks.as_slice()
     }
     pub fn chunk_by_type(&self, ctype: &str) -> Option<&Chunk> {
-        self.chunks.iter()
-        .find(|&chunk| format!("{}", chunk.ctype) == ctype)
+        self.chunks_by_type(ctype.parse().ok()?).next()
+    }
+    pub fn chunks_by_type(&self, ctype: ChunkType) -> impl Iterator<Item = &Chunk> {
+        self.chunks.iter().filter(move |chunk| chunk.ctype == ctype)
+    }
+    pub fn position_of(&self, ctype: ChunkType) -> Option<usize> {
+        self.chunks.iter().position(|chunk| chunk.ctype == ctype)
+    }
+    pub fn ihdr(&self) -> Result<Ihdr, PngError> {
+        let chunk = self.chunks_by_type(ChunkType::IHDR).next()
+            .ok_or(PngError::MissingChunk(ChunkType::IHDR))?;
+        Ok(Ihdr::try_from(chunk.data())?)
+    }
+    pub fn index(&self) -> ChunkIndex<'_> {
+        ChunkIndex::new(self)
     }
     pub fn as_bytes(&self) -> Vec<u8> {
         let bytes_it = self.chunks.iter().flat_map(|chunk| chunk.as_bytes());
@@ -103,11 +286,40 @@ impl Png {
         result
     }
 }
+// Lookup table from chunk type to positions, for pngs with many chunks.
+// It borrows the png, so it can't go stale under a mutation.
+pub struct ChunkIndex<'a> {
+    png: &'a Png,
+    positions: HashMap<ChunkType, Vec<usize>>,
+}
+impl<'a> ChunkIndex<'a> {
+    pub fn new(png: &'a Png) -> ChunkIndex<'a> {
+        let mut positions: HashMap<ChunkType, Vec<usize>> = HashMap::new();
+        for (i, chunk) in png.chunks.iter().enumerate() {
+            positions.entry(chunk.ctype).or_default().push(i);
+        }
+        ChunkIndex { png, positions }
+    }
+    pub fn positions(&self, ctype: ChunkType) -> &[usize] {
+        self.positions.get(&ctype).map_or(&[], |positions| positions.as_slice())
+    }
+    pub fn first(&self, ctype: ChunkType) -> Option<&'a Chunk> {
+        self.positions(ctype).first().map(|&i| &self.png.chunks[i])
+    }
+    pub fn all(&self, ctype: ChunkType) -> impl Iterator<Item = &'a Chunk> + '_ {
+        self.positions(ctype).iter().map(|&i| &self.png.chunks[i])
+    }
+    pub fn contains(&self, ctype: ChunkType) -> bool {
+        self.positions.contains_key(&ctype)
+    }
+}
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::chunk_type::{ChunkType, ChunkTypeError};
     use crate::chunk::Chunk;
+    use crate::chunk::tests::arb_chunk;
+    use proptest::prelude::*;
     use std::convert::TryFrom;
 
     fn testing_chunks() -> Vec<Chunk> {
@@ -233,6 +445,130 @@ mod tests {
         assert!(chunk.is_none());
     }
 
+    #[test]
+    fn test_chunks_by_type() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("FrSt", "I am the second first chunk").unwrap());
+        let ctype = ChunkType::try_from(*b"FrSt").unwrap();
+        let data: Vec<_> = png.chunks_by_type(ctype).map(|chunk| chunk.data_as_string().unwrap()).collect();
+        assert_eq!(data, vec!["I am the first chunk", "I am the second first chunk"]);
+        assert_eq!(png.position_of(ctype), Some(0));
+        assert_eq!(png.position_of(ChunkType::IEND), None);
+    }
+
+    #[test]
+    fn test_remove_all() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("FrSt", "I am the second first chunk").unwrap());
+        let removed = png.remove_all(ChunkType::try_from(*b"FrSt").unwrap());
+        assert_eq!(removed.len(), 2);
+        assert_eq!(png.chunks().len(), 2);
+        assert!(png.chunk_by_type("FrSt").is_none());
+    }
+
+
+    #[test]
+    fn test_remove_nth() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("FrSt", "I am the second first chunk").unwrap());
+        let ctype = ChunkType::try_from(*b"FrSt").unwrap();
+        let removed = png.remove_nth(ctype, 1).unwrap();
+        assert_eq!(removed.data_as_string().unwrap(), "I am the second first chunk");
+        assert_eq!(png.chunks().len(), 3);
+        assert!(matches!(png.remove_nth(ctype, 1), Err(PngError::NoChunk)));
+    }
+
+    #[test]
+    fn test_remove_where() {
+        let mut png = testing_png();
+        let removed = png.remove_where(|chunk| chunk.data_as_string().unwrap().contains("first"));
+        assert_eq!(removed.len(), 1);
+        assert_eq!(png.chunks().len(), 2);
+        assert_eq!(png.chunks()[0].chunk_type().to_string(), "miDl");
+    }
+    #[test]
+    fn test_retain() {
+        let mut png = testing_png();
+        png.retain(|chunk| chunk.chunk_type().is_critical());
+        let types: Vec<_> = png.chunks().iter().map(|chunk| chunk.chunk_type().to_string()).collect();
+        assert_eq!(types, vec!["FrSt", "LASt"]);
+    }
+
+    #[test]
+    fn test_chunk_index() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let index = png.index();
+        assert_eq!(index.positions(ChunkType::IHDR), &[0]);
+        assert_eq!(index.first(ChunkType::IEND).unwrap().length(), 0);
+        assert_eq!(index.all(ChunkType::IDAT).count(), png.chunks_by_type(ChunkType::IDAT).count());
+        assert!(!index.contains(ChunkType::PLTE));
+    }
+
+    fn critical_png() -> Png {
+        let chunks = ["IHDR", "PLTE", "IDAT", "IDAT", "IEND"]
+            .iter()
+            .map(|ctype| chunk_from_strings(ctype, "").unwrap())
+            .collect();
+        Png::from_chunks(chunks)
+    }
+
+    fn chunk_types(png: &Png) -> Vec<String> {
+        png.chunks().iter().map(|chunk| chunk.chunk_type().to_string()).collect()
+    }
+
+    #[test]
+    fn test_insert_chunk_at() {
+        let mut png = testing_png();
+        png.insert_chunk_at(1, chunk_from_strings("TeSt", "Message").unwrap()).unwrap();
+        assert_eq!(chunk_types(&png), vec!["FrSt", "TeSt", "miDl", "LASt"]);
+        assert!(png.insert_chunk_at(9, chunk_from_strings("TeSt", "Message").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_insert_before_and_after() {
+        let mut png = critical_png();
+        assert_eq!(png.insert_before(ChunkType::IDAT, chunk_from_strings("tEXt", "a").unwrap()).unwrap(), 2);
+        assert_eq!(png.insert_after(ChunkType::IDAT, chunk_from_strings("tIME", "b").unwrap()).unwrap(), 5);
+        assert_eq!(chunk_types(&png), vec!["IHDR", "PLTE", "tEXt", "IDAT", "IDAT", "tIME", "IEND"]);
+        assert!(png.insert_before(ChunkType::SRGB, chunk_from_strings("tEXt", "c").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_replace_image_data() {
+        let mut png = critical_png();
+        let idat = vec![chunk_from_strings("IDAT", "a").unwrap(); 3];
+        png.replace_image_data(idat.clone()).unwrap();
+        assert_eq!(chunk_types(&png), vec!["IHDR", "PLTE", "IDAT", "IDAT", "IDAT", "IEND"]);
+        png.remove_all(ChunkType::IDAT);
+        png.replace_image_data(idat).unwrap();
+        assert_eq!(chunk_types(&png), vec!["IHDR", "PLTE", "IDAT", "IDAT", "IDAT", "IEND"]);
+        png.remove_all(ChunkType::IEND);
+        png.remove_all(ChunkType::IDAT);
+        assert!(png.replace_image_data(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_insert_ancillary() {
+        let mut png = critical_png();
+        png.insert_ancillary(chunk_from_strings("gAMA", "").unwrap()).unwrap();
+        png.insert_ancillary(chunk_from_strings("tRNS", "").unwrap()).unwrap();
+        png.insert_ancillary(chunk_from_strings("tEXt", "").unwrap()).unwrap();
+        png.insert_ancillary(chunk_from_strings("tIME", "").unwrap()).unwrap();
+        png.insert_ancillary(chunk_from_strings("ruSt", "").unwrap()).unwrap();
+        assert_eq!(
+            chunk_types(&png),
+            vec!["IHDR", "gAMA", "PLTE", "tRNS", "tEXt", "ruSt", "IDAT", "IDAT", "tIME", "IEND"]
+        );
+        assert!(png.insert_ancillary(chunk_from_strings("RuSt", "").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_insert_ancillary_without_critical_chunks() {
+        let mut png = testing_png();
+        png.insert_ancillary(chunk_from_strings("tEXt", "").unwrap()).unwrap();
+        assert_eq!(chunk_types(&png), vec!["FrSt", "miDl", "LASt", "tEXt"]);
+    }
+
     #[test]
     fn test_png_from_image_file() {
         let png = Png::try_from(&PNG_FILE[..]);
@@ -247,6 +583,55 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_png_ref_chunks() {
+        let png_ref = PngRef::try_from(&PNG_FILE[..]).unwrap().verify_crc(true);
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let types: Vec<_> = png_ref.chunks().map(|chunk| chunk.unwrap().chunk_type().to_string()).collect();
+        let expected: Vec<_> = png.chunks().iter().map(|chunk| chunk.chunk_type().to_string()).collect();
+        assert_eq!(types, expected);
+        assert_eq!(png_ref.to_png().unwrap().as_bytes(), PNG_FILE.to_vec());
+    }
+
+    #[test]
+    fn test_png_ref_chunk_by_type() {
+        let bytes = testing_png().as_bytes();
+        let png_ref = PngRef::try_from(bytes.as_slice()).unwrap();
+        let ctype = ChunkType::try_from(*b"miDl").unwrap();
+        let chunk = png_ref.chunk_by_type(&ctype).unwrap().unwrap();
+        assert_eq!(chunk.data_as_str().unwrap(), "I am another chunk");
+    }
+
+    #[test]
+    fn test_png_ref_crc_only_when_asked() {
+        let mut bytes = testing_png().as_bytes();
+        bytes[8 + 8] ^= 1;
+        let png_ref = PngRef::try_from(bytes.as_slice()).unwrap();
+        assert!(png_ref.chunks().all(|chunk| chunk.is_ok()));
+        let png_ref = png_ref.verify_crc(true);
+        let results: Vec<_> = png_ref.chunks().collect();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_err());
+    }
+
+    #[test]
+    fn test_limits() {
+        let bytes = testing_png().as_bytes();
+        let limits = Limits { max_chunks: 2, ..Limits::default() };
+        assert!(matches!(Png::from_bytes_with_limits(&bytes, limits), Err(PngError::Limit(LimitError::Chunks(2)))));
+        let limits = Limits { max_chunk_length: 19, ..Limits::default() };
+        assert!(matches!(
+            Png::from_bytes_with_limits(&bytes, limits),
+            Err(PngError::Limit(LimitError::ChunkLength(_, 20, 19)))
+        ));
+        let limits = Limits { max_width: 49, ..Limits::default() };
+        assert!(matches!(
+            Png::from_bytes_with_limits(&PNG_FILE, limits),
+            Err(PngError::Limit(LimitError::Dimensions(..)))
+        ));
+        assert!(Png::from_bytes_with_limits(&bytes, Limits::default()).is_ok());
+    }
+
     #[test]
     fn test_png_trait_impls() {
         let chunk_bytes: Vec<u8> = testing_chunks()
@@ -264,6 +649,28 @@ mod tests {
         let _png_string = format!("{}", png);
     }
 
+    proptest! {
+        #[test]
+        fn prop_png_round_trip(chunks in prop::collection::vec(arb_chunk(), 0..8)) {
+            let png = Png::from_chunks(chunks);
+            prop_assert_eq!(Png::try_from(png.as_bytes().as_slice()).unwrap(), png);
+        }
+
+        // flipping a byte or cutting the file short must give an error, not a panic
+        #[test]
+        fn prop_damaged_png_never_panics(index in any::<prop::sample::Index>(), byte in any::<u8>(), cut in any::<prop::sample::Index>()) {
+            let mut bytes = PNG_FILE.to_vec();
+            let i = index.index(bytes.len());
+            bytes[i] = byte;
+            bytes.truncate(cut.index(bytes.len() + 1));
+            if let Ok(png) = Png::try_from(bytes.as_slice()) {
+                png.validate();
+                let _ = png.ihdr();
+            }
+            let _ = PngRef::try_from(bytes.as_slice()).map(|png| png.chunks().count());
+        }
+    }
+
     // This is the raw bytes for a shrunken version of the `dice.png` image on Wikipedia
     const PNG_FILE: [u8; 4803] = [
         137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0, 50, 0, 0, 0, 50, 8,
@@ -0,0 +1,280 @@
+use thiserror::Error;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::signature::Mangling;
+
+// Lenient counterpart to `Png::try_from`: instead of failing on the first
+// problem it records what was wrong, fixes what it can and keeps going.
+#[derive(Error, Debug, PartialEq)]
+pub enum Diagnostic {
+    #[error("Bad png signature {0:?}, replaced with the standard one")]
+    PngSignature([u8; 8]),
+    #[error("Missing png signature, inserted the standard one")]
+    MissingSignature,
+    #[error("Png signature shows {mangling}, {}", if *whole_file { "reversed it over the whole file" } else { "but only the signature was changed; replaced it" })]
+    Unmangled { mangling: Mangling, whole_file: bool },
+    #[error("Png signature shows {0}, which can't be reversed unambiguously; replaced the signature only")]
+    Mangled(Mangling),
+    #[error("{ctype} chunk at offset {offset} has crc {stored:#010x}, expected {computed:#010x}")]
+    Crc { ctype: ChunkType, offset: usize, stored: u32, computed: u32 },
+    #[error("{ctype} chunk at offset {offset} says it is of length {stored}, but it's {actual}")]
+    Length { ctype: ChunkType, offset: usize, stored: u32, actual: usize },
+    #[error("{ctype} chunk at offset {offset} is truncated, kept {kept} bytes of data")]
+    Truncated { ctype: ChunkType, offset: usize, kept: usize },
+    #[error("Skipped {0} unreadable bytes at offset {1}")]
+    Skipped(usize, usize),
+    #[error("Missing IEND chunk, appended one")]
+    MissingIend,
+}
+pub struct Recovery {
+    pub png: Png,
+    pub diagnostics: Vec<Diagnostic>,
+}
+impl Recovery {
+    pub fn is_clean(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+}
+// A chunk header is plausible if its type is alphabetic and its data fits in the buffer
+fn header_at(bytes: &[u8], pos: usize) -> Option<(u32, ChunkType)> {
+    let header = bytes.get(pos..pos + 8)?;
+    let length = u32::from_be_bytes(header[..4].try_into().unwrap());
+    let ctype = ChunkType::try_from(<[u8; 4]>::try_from(&header[4..]).unwrap()).ok()?;
+    Some((length, ctype))
+}
+fn plausible_at(bytes: &[u8], pos: usize) -> bool {
+    match header_at(bytes, pos) {
+        Some((length, _)) => length <= Chunk::MAX_LENGTH && pos + 12 + length as usize <= bytes.len(),
+        None => false,
+    }
+}
+fn next_plausible(bytes: &[u8], from: usize) -> Option<usize> {
+    (from..bytes.len()).find(|&pos| plausible_at(bytes, pos))
+}
+fn crc_at(bytes: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap())
+}
+pub fn recover(bytes: &[u8]) -> Recovery {
+    let mangling = Mangling::diagnose(bytes);
+    if let Some(mangling) = mangling
+        && let Some(fixed) = mangling.unmangle(bytes)
+    {
+        // the reversal that parsed might have been the signature alone
+        let whole_file = fixed[Png::STANDARD_SIGNATURE.len()..] != bytes[mangling.signature().len()..];
+        let mut recovery = recover(&fixed);
+        recovery.diagnostics.insert(0, Diagnostic::Unmangled { mangling, whole_file });
+        return recovery;
+    }
+    let mut diagnostics = Vec::new();
+    let mut pos = match (bytes.get(..8), mangling) {
+        (Some(signature), _) if signature == Png::STANDARD_SIGNATURE => 8,
+        (_, Some(mangling)) => {
+            diagnostics.push(Diagnostic::Mangled(mangling));
+            mangling.signature().len()
+        }
+        _ if plausible_at(bytes, 0) => {
+            diagnostics.push(Diagnostic::MissingSignature);
+            0
+        }
+        (Some(signature), None) => {
+            diagnostics.push(Diagnostic::PngSignature(signature.try_into().unwrap()));
+            8
+        }
+        (None, None) => {
+            diagnostics.push(Diagnostic::MissingSignature);
+            bytes.len()
+        }
+    };
+    let mut chunks = Vec::new();
+    while pos < bytes.len() {
+        let Some((length, ctype)) = header_at(bytes, pos) else {
+            let next = next_plausible(bytes, pos + 1).unwrap_or(bytes.len());
+            diagnostics.push(Diagnostic::Skipped(next - pos, pos));
+            pos = next;
+            continue;
+        };
+        let data_start = pos + 8;
+        let mut end = data_start + length as usize;
+        let fits = length <= Chunk::MAX_LENGTH && end + 4 <= bytes.len();
+        let crc_ok = fits && Chunk::checksum(&ctype, &bytes[data_start..end]) == crc_at(bytes, end);
+        // a bad length shows up as a crc mismatch followed by garbage; trust the
+        // next plausible header over the length field in that case
+        if !crc_ok && (!fits || (end + 4 < bytes.len() && !plausible_at(bytes, end + 4))) {
+            match next_plausible(bytes, data_start) {
+                Some(next) if next >= data_start + 4 => {
+                    end = next - 4;
+                    diagnostics.push(Diagnostic::Length {
+                        ctype, offset: pos, stored: length, actual: end - data_start,
+                    });
+                }
+                _ if !fits => {
+                    let kept = bytes.len() - data_start;
+                    diagnostics.push(Diagnostic::Truncated { ctype, offset: pos, kept });
+                    chunks.push(Chunk::new(ctype, bytes[data_start..].to_vec()));
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let chunk = Chunk::new(ctype, bytes[data_start..end].to_vec());
+        let stored = crc_at(bytes, end);
+        if stored != chunk.crc() {
+            diagnostics.push(Diagnostic::Crc { ctype, offset: pos, stored, computed: chunk.crc() });
+        }
+        chunks.push(chunk);
+        pos = end + 4;
+    }
+    if !chunks.iter().any(|chunk| *chunk.chunk_type() == ChunkType::IEND) {
+        diagnostics.push(Diagnostic::MissingIend);
+        chunks.push(Chunk::new(ChunkType::IEND, Vec::new()));
+    }
+    Recovery { png: Png::from_chunks(chunks), diagnostics }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::tests::arb_chunk;
+    use crate::ihdr::{ColorType, Ihdr};
+    use crate::limits::Limits;
+    use crate::png::PngError;
+    use proptest::prelude::*;
+    use std::str::FromStr;
+
+    fn chunk(ctype: &str, data: &str) -> Chunk {
+        Chunk::new(ChunkType::from_str(ctype).unwrap(), data.as_bytes().to_vec())
+    }
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            chunk("FrSt", "I am the first chunk"),
+            chunk("miDl", "I am another chunk"),
+            chunk("IEND", ""),
+        ])
+    }
+
+    #[test]
+    fn test_recover_clean() {
+        let png = testing_png();
+        let recovery = recover(&png.as_bytes());
+        assert!(recovery.is_clean());
+        assert_eq!(recovery.png.as_bytes(), png.as_bytes());
+    }
+
+    #[test]
+    fn test_recover_crc() {
+        let png = testing_png();
+        let mut bytes = png.as_bytes();
+        bytes[8 + 8] ^= 0x01;
+        let recovery = recover(&bytes);
+        assert_eq!(recovery.diagnostics.len(), 1);
+        assert!(matches!(recovery.diagnostics[0], Diagnostic::Crc { offset: 8, .. }));
+        assert_eq!(recovery.png.chunks().len(), 3);
+        assert_eq!(recovery.png.chunks()[0].data(), b"H am the first chunk");
+    }
+
+    #[test]
+    fn test_recover_bad_length() {
+        let png = testing_png();
+        let mut bytes = png.as_bytes();
+        bytes[8 + 3] = 200;
+        let recovery = recover(&bytes);
+        assert_eq!(
+            recovery.diagnostics,
+            vec![Diagnostic::Length { ctype: ChunkType::from_str("FrSt").unwrap(), offset: 8, stored: 200, actual: 20 }]
+        );
+        assert_eq!(recovery.png.as_bytes(), png.as_bytes());
+    }
+
+    #[test]
+    fn test_recover_garbage_between_chunks() {
+        let png = testing_png();
+        let mut bytes = png.as_bytes();
+        let second = 8 + 12 + 20;
+        bytes.splice(second..second, [0u8, 1, 2, 3, 4]);
+        let recovery = recover(&bytes);
+        assert_eq!(recovery.diagnostics, vec![Diagnostic::Skipped(5, second)]);
+        assert_eq!(recovery.png.as_bytes(), png.as_bytes());
+    }
+
+    #[test]
+    fn test_recover_truncated() {
+        let png = testing_png();
+        let bytes = png.as_bytes();
+        let recovery = recover(&bytes[..8 + 12 + 20 + 10]);
+        assert_eq!(
+            recovery.diagnostics,
+            vec![
+                Diagnostic::Truncated { ctype: ChunkType::from_str("miDl").unwrap(), offset: 40, kept: 2 },
+                Diagnostic::MissingIend,
+            ]
+        );
+        assert_eq!(recovery.png.chunks().len(), 3);
+        assert_eq!(recovery.png.chunks()[1].data(), b"I ");
+    }
+
+    #[test]
+    fn test_recover_mangled_signature() {
+        let png = testing_png();
+        let bytes = png.as_bytes();
+        let stripped = [&bytes[..6], &bytes[7..]].concat();
+        let recovery = recover(&stripped);
+        assert_eq!(recovery.diagnostics, vec![Diagnostic::Unmangled { mangling: Mangling::EofStripped, whole_file: false }]);
+        assert_eq!(recovery.png.as_bytes(), bytes);
+        assert!(recovery.diagnostics[0].to_string().ends_with("only the signature was changed; replaced it"));
+
+        let png = Png::from_chunks(vec![chunk("teXt", "two\nlines"), chunk("IEND", "")]);
+        let rest: Vec<u8> = png.as_bytes()[8..].iter().flat_map(|&byte| if byte == b'\n' { vec![b'\r', b'\n'] } else { vec![byte] }).collect();
+        let crlf = [Mangling::LfToCrlf.signature(), &rest].concat();
+        let recovery = recover(&crlf);
+        assert_eq!(recovery.diagnostics, vec![Diagnostic::Unmangled { mangling: Mangling::LfToCrlf, whole_file: true }]);
+        assert_eq!(recovery.png.as_bytes(), png.as_bytes());
+
+        let mut cleared = bytes.clone();
+        cleared[0] &= 0x7f;
+        cleared[8 + 8] ^= 0x01;
+        let recovery = recover(&cleared);
+        assert_eq!(recovery.diagnostics[0], Diagnostic::Mangled(Mangling::HighBitCleared));
+        assert!(matches!(recovery.diagnostics[1], Diagnostic::Crc { offset: 8, .. }));
+    }
+
+    #[test]
+    fn test_recover_missing_signature() {
+        let png = testing_png();
+        let bytes = png.as_bytes();
+        let recovery = recover(&bytes[8..]);
+        assert_eq!(recovery.diagnostics, vec![Diagnostic::MissingSignature]);
+        assert_eq!(recovery.png.as_bytes(), png.as_bytes());
+    }
+
+    // found by fuzzing: recovery has no limits, so its output can be over the default ones
+    #[test]
+    fn test_recover_over_limits() {
+        let ihdr = Ihdr { width: 7_995_722, height: 247, bit_depth: 8, color_type: ColorType::Rgba, interlaced: false };
+        let bytes = Png::from_chunks(vec![ihdr.to_chunk()]).as_bytes();
+        let recovery = recover(&bytes);
+        assert_eq!(recovery.diagnostics, vec![Diagnostic::MissingIend]);
+        let bytes = recovery.png.as_bytes();
+        assert!(matches!(Png::try_from(bytes.as_slice()), Err(PngError::Limit(_))));
+        assert!(Png::from_bytes_with_limits(&bytes, Limits::none()).is_ok());
+    }
+
+    proptest! {
+        // whatever goes in, what comes out is a png the strict parser accepts
+        #[test]
+        fn prop_recover_output_parses(bytes in prop::collection::vec(any::<u8>(), 0..256)) {
+            let recovery = recover(&bytes);
+            prop_assert!(Png::from_bytes_with_limits(&recovery.png.as_bytes(), Limits::none()).is_ok());
+        }
+
+        #[test]
+        fn prop_recover_damaged_png(chunks in prop::collection::vec(arb_chunk(), 1..6), index in any::<prop::sample::Index>(), byte in any::<u8>()) {
+            let mut bytes = Png::from_chunks(chunks).as_bytes();
+            let i = index.index(bytes.len());
+            bytes[i] = byte;
+            let recovery = recover(&bytes);
+            prop_assert!(Png::from_bytes_with_limits(&recovery.png.as_bytes(), Limits::none()).is_ok());
+        }
+    }
+}
@@ -0,0 +1,386 @@
+use clap::ValueEnum;
+use thiserror::Error;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::decode::DecodeError;
+use crate::ihdr::ColorType;
+use crate::pixels::{unpack, ConvertError, Pixels};
+use crate::png::{Png, PngError};
+
+// Color-correct pixels, in the order libpng's read transformations go: palette
+// and tRNS to alpha, sBIT, gAMA/cHRM (or sRGB) to linear light, compositing
+// over a background, and finally encoding for the output. Images without any
+// color information are taken to be sRGB, as browsers do.
+#[derive(Error, Debug)]
+pub enum RenderError {
+    #[error("{0}")]
+    Decode(#[from] DecodeError),
+    #[error("{0}")]
+    Convert(#[from] ConvertError),
+    #[error("{0}")]
+    Png(#[from] PngError),
+    #[error("Rendered pixels are 8 or 16-bit, not {0}")]
+    BitDepth(u8),
+}
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Output {
+    /// sRGB encoded
+    #[default]
+    Srgb,
+    /// Linear light, with sRGB's primaries
+    Linear,
+}
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderOptions {
+    pub output: Output,
+    // composite over the bKGD color when the file has one
+    pub file_background: bool,
+    // otherwise over this sRGB color; with neither, alpha is kept
+    pub background: Option<[u8; 3]>,
+    // undo sBIT scaling, the way libpng's png_set_shift does
+    pub significant_bits: bool,
+    // 8 or 16, by default 16 only for 16-bit images
+    pub bit_depth: Option<u8>,
+}
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions { output: Output::Srgb, file_background: true, background: None, significant_bits: true, bit_depth: None }
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transfer {
+    Srgb,
+    // gAMA's value: samples are linear light raised to this
+    Gamma(f64),
+}
+// cHRM's white point and primaries as (x, y)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Chromaticities {
+    pub white: (f64, f64),
+    pub red: (f64, f64),
+    pub green: (f64, f64),
+    pub blue: (f64, f64),
+}
+type Matrix = [[f64; 3]; 3];
+// How a file's samples relate to light: the transfer function and, if its
+// primaries aren't sRGB's, the matrix taking linear RGB to sRGB's
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorSpace {
+    pub transfer: Transfer,
+    pub to_srgb: Option<Matrix>,
+}
+pub fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+}
+pub fn linear_to_srgb(value: f64) -> f64 {
+    if value <= 0.003_130_8 { value * 12.92 } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 }
+}
+impl Transfer {
+    pub fn to_linear(self, value: f64) -> f64 {
+        match self {
+            Transfer::Srgb => srgb_to_linear(value),
+            Transfer::Gamma(gamma) => value.powf(1.0 / gamma),
+        }
+    }
+}
+impl Output {
+    fn encode(self, linear: f64) -> f64 {
+        match self {
+            Output::Srgb => linear_to_srgb(linear),
+            Output::Linear => linear,
+        }
+    }
+    // tags a png of rendered pixels: sRGB with the perceptual intent, or a gamma of 1
+    pub fn to_chunk(self) -> Chunk {
+        match self {
+            Output::Srgb => Chunk::new(ChunkType::SRGB, vec![0]),
+            Output::Linear => Chunk::new(ChunkType::GAMA, 100_000u32.to_be_bytes().to_vec()),
+        }
+    }
+}
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    std::array::from_fn(|row| std::array::from_fn(|column| (0..3).map(|k| a[row][k] * b[k][column]).sum()))
+}
+fn apply(m: &Matrix, v: [f64; 3]) -> [f64; 3] {
+    std::array::from_fn(|row| (0..3).map(|k| m[row][k] * v[k]).sum())
+}
+fn invert(m: &Matrix) -> Option<Matrix> {
+    let cofactor = |row: usize, column: usize| {
+        let (r1, r2) = ((row + 1) % 3, (row + 2) % 3);
+        let (c1, c2) = ((column + 1) % 3, (column + 2) % 3);
+        m[r1][c1] * m[r2][c2] - m[r1][c2] * m[r2][c1]
+    };
+    let determinant: f64 = (0..3).map(|column| m[0][column] * cofactor(0, column)).sum();
+    if determinant.abs() < 1e-12 {
+        return None;
+    }
+    Some(std::array::from_fn(|row| std::array::from_fn(|column| cofactor(column, row) / determinant)))
+}
+fn xyz((x, y): (f64, f64)) -> [f64; 3] {
+    [x / y, 1.0, (1.0 - x - y) / y]
+}
+// Bradford chromatic adaptation, for a cHRM white point that isn't D65
+const BRADFORD: Matrix = [[0.8951, 0.2664, -0.1614], [-0.7502, 1.7135, 0.0367], [0.0389, -0.0685, 1.0296]];
+impl Chromaticities {
+    pub const SRGB: Chromaticities =
+        Chromaticities { white: (0.3127, 0.3290), red: (0.64, 0.33), green: (0.30, 0.60), blue: (0.15, 0.06) };
+    fn to_xyz(self) -> Option<Matrix> {
+        if [self.white, self.red, self.green, self.blue].iter().any(|&(_, y)| y <= 0.0) {
+            return None;
+        }
+        let [red, green, blue] = [xyz(self.red), xyz(self.green), xyz(self.blue)];
+        let primaries: Matrix = std::array::from_fn(|row| [red[row], green[row], blue[row]]);
+        let scale = apply(&invert(&primaries)?, xyz(self.white));
+        Some(std::array::from_fn(|row| std::array::from_fn(|column| primaries[row][column] * scale[column])))
+    }
+    // linear RGB in these primaries to linear sRGB, adapted to D65
+    fn to_srgb(self) -> Option<Matrix> {
+        let (from_white, to_white) = (apply(&BRADFORD, xyz(self.white)), apply(&BRADFORD, xyz(Self::SRGB.white)));
+        let gains: Matrix = std::array::from_fn(|row| std::array::from_fn(|column| {
+            if row == column { to_white[row] / from_white[row] } else { 0.0 }
+        }));
+        let adapt = multiply(&invert(&BRADFORD)?, &multiply(&gains, &BRADFORD));
+        let from_srgb = invert(&Self::SRGB.to_xyz()?)?;
+        Some(multiply(&from_srgb, &multiply(&adapt, &self.to_xyz()?)))
+    }
+}
+fn is_identity(m: &Matrix) -> bool {
+    (0..3).all(|row| (0..3).all(|column| (m[row][column] - (row == column) as u8 as f64).abs() < 1e-4))
+}
+impl Png {
+    pub fn gamma(&self) -> Option<f64> {
+        let data = self.chunks_by_type(ChunkType::GAMA).next()?.data();
+        let gamma = u32::from_be_bytes(data.try_into().ok()?);
+        (gamma > 0).then(|| gamma as f64 / 100_000.0)
+    }
+    pub fn chromaticities(&self) -> Option<Chromaticities> {
+        let data = self.chunks_by_type(ChunkType::CHRM).next()?.data();
+        if data.len() != 32 {
+            return None;
+        }
+        let values: Vec<f64> = data.chunks(4).map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()) as f64 / 100_000.0).collect();
+        Some(Chromaticities {
+            white: (values[0], values[1]),
+            red: (values[2], values[3]),
+            green: (values[4], values[5]),
+            blue: (values[6], values[7]),
+        })
+    }
+    // sRGB wins over gAMA and cHRM, as the spec says. iCCP profiles aren't read,
+    // for those gAMA and cHRM are the fallback the spec intends.
+    pub fn color_space(&self) -> ColorSpace {
+        if self.position_of(ChunkType::SRGB).is_some() {
+            return ColorSpace { transfer: Transfer::Srgb, to_srgb: None };
+        }
+        let transfer = self.gamma().map_or(Transfer::Srgb, Transfer::Gamma);
+        let to_srgb = self.chromaticities().and_then(|chromaticities| chromaticities.to_srgb()).filter(|m| !is_identity(m));
+        ColorSpace { transfer, to_srgb }
+    }
+    // bKGD as linear light
+    fn background(&self, space: &ColorSpace) -> Option<[f64; 3]> {
+        let ihdr = self.ihdr().ok()?;
+        let data = self.chunks_by_type(ChunkType::BKGD).next()?.data();
+        let (rgb, max) = match ihdr.color_type {
+            ColorType::Indexed => {
+                let [red, green, blue, _] = self.palette()?.rgba(*data.first()? as usize)?;
+                ([red as u16, green as u16, blue as u16], 255)
+            }
+            ColorType::Grayscale | ColorType::GrayscaleAlpha if data.len() == 2 => {
+                ([unpack(data, 16, 1)[0]; 3], (1u32 << ihdr.bit_depth) - 1)
+            }
+            ColorType::Rgb | ColorType::Rgba if data.len() == 6 => {
+                let samples = unpack(data, 16, 3);
+                ([samples[0], samples[1], samples[2]], (1u32 << ihdr.bit_depth) - 1)
+            }
+            _ => return None,
+        };
+        let linear = rgb.map(|sample| space.transfer.to_linear((sample as f64 / max as f64).min(1.0)));
+        Some(space.to_srgb.map_or(linear, |m| apply(&m, linear).map(|v| v.clamp(0.0, 1.0))))
+    }
+    pub fn render(&self, options: &RenderOptions) -> Result<Pixels, RenderError> {
+        let ihdr = self.ihdr()?;
+        let bit_depth = options.bit_depth.unwrap_or(if ihdr.bit_depth == 16 { 16 } else { 8 });
+        if bit_depth != 8 && bit_depth != 16 {
+            return Err(RenderError::BitDepth(bit_depth));
+        }
+        let mut pixels = self.pixels()?;
+        if ihdr.color_type == ColorType::Indexed {
+            let palette = self.palette();
+            let color_type = match &palette {
+                Some(palette) if !palette.alpha.is_empty() => ColorType::Rgba,
+                _ => ColorType::Rgb,
+            };
+            pixels = pixels.to_color_type(color_type, palette.as_ref())?;
+        } else if let Some(color) = self.transparent_color() {
+            pixels = pixels.with_transparent_color(&color);
+        }
+        if options.significant_bits
+            && ihdr.color_type != ColorType::Indexed
+            && let Some(mut sbit) = self.significant_bits()?
+        {
+            // alpha that came from tRNS has every bit
+            sbit.resize(pixels.channels(), pixels.bit_depth);
+            pixels = pixels.expand_significant_bits(&sbit)?;
+        }
+        let space = self.color_space();
+        let file_background = options.file_background.then(|| self.background(&space)).flatten();
+        let background = match (file_background, options.background) {
+            (Some(color), _) => Some(color),
+            (None, Some(color)) => Some(color.map(|sample| srgb_to_linear(sample as f64 / 255.0))),
+            (None, None) => None,
+        };
+        let has_alpha = pixels.color_type.has_alpha();
+        let gray = pixels.channels() < 3 && background.is_none_or(|[red, green, blue]| red == green && green == blue);
+        let color_type = match (gray, has_alpha && background.is_none()) {
+            (true, false) => ColorType::Grayscale,
+            (true, true) => ColorType::GrayscaleAlpha,
+            (false, false) => ColorType::Rgb,
+            (false, true) => ColorType::Rgba,
+        };
+        let max_in = pixels.max_sample() as f64;
+        let max_out = ((1u32 << bit_depth) - 1) as f64;
+        let to_linear: Vec<f64> = (0..=pixels.max_sample()).map(|sample| space.transfer.to_linear(sample as f64 / max_in)).collect();
+        let quantize = |value: f64| (value.clamp(0.0, 1.0) * max_out).round() as u16;
+        let channels = pixels.channels();
+        let mut samples = Vec::with_capacity(pixels.samples.len() / channels * color_type.channels() as usize);
+        for pixel in pixels.samples.chunks(channels) {
+            let mut color = if channels >= 3 {
+                let linear = [to_linear[pixel[0] as usize], to_linear[pixel[1] as usize], to_linear[pixel[2] as usize]];
+                space.to_srgb.map_or(linear, |m| apply(&m, linear))
+            } else {
+                [to_linear[pixel[0] as usize]; 3]
+            };
+            let alpha = if has_alpha { pixel[channels - 1] as f64 / max_in } else { 1.0 };
+            if let Some(background) = background {
+                color = std::array::from_fn(|c| color[c] * alpha + background[c] * (1.0 - alpha));
+            }
+            let encoded = color.map(|value| quantize(options.output.encode(value.clamp(0.0, 1.0))));
+            samples.extend_from_slice(if gray { &encoded[..1] } else { &encoded });
+            if color_type.has_alpha() {
+                samples.push(quantize(alpha));
+            }
+        }
+        Ok(Pixels { width: pixels.width, height: pixels.height, color_type, bit_depth, samples })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deflate::{deflate_to_idat, Compression};
+    use crate::ihdr::Ihdr;
+    use crate::pixels::pack;
+
+    // a one row image of `samples`, with `chunks` before the image data
+    fn png(color_type: ColorType, bit_depth: u8, samples: &[u16], chunks: Vec<Chunk>) -> Png {
+        let width = (samples.len() / color_type.channels() as usize) as u32;
+        let ihdr = Ihdr { width, height: 1, bit_depth, color_type, interlaced: false };
+        let mut data = vec![0];
+        data.extend(pack(samples, bit_depth));
+        let mut all = vec![ihdr.to_chunk()];
+        all.extend(chunks);
+        all.extend(deflate_to_idat(&data, Compression::default(), 1 << 16));
+        all.push(Chunk::new(ChunkType::IEND, Vec::new()));
+        Png::from_chunks(all)
+    }
+
+    fn chunk(ctype: ChunkType, data: &[u8]) -> Chunk {
+        Chunk::new(ctype, data.to_vec())
+    }
+
+    fn linear() -> RenderOptions {
+        RenderOptions { output: Output::Linear, ..Default::default() }
+    }
+
+    #[test]
+    fn test_transfer_functions() {
+        for i in 0..=255 {
+            let value = i as f64 / 255.0;
+            assert!((linear_to_srgb(srgb_to_linear(value)) - value).abs() < 1e-9);
+        }
+        assert!((Transfer::Gamma(0.5).to_linear(0.5) - 0.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_untagged_is_srgb() {
+        let samples: Vec<u16> = (0..=255).collect();
+        let png = png(ColorType::Grayscale, 8, &samples, Vec::new());
+        assert_eq!(png.render(&RenderOptions::default()).unwrap().samples, samples);
+        let rendered = png.render(&linear()).unwrap();
+        assert_eq!(rendered.samples[128], (srgb_to_linear(128.0 / 255.0) * 255.0).round() as u16);
+    }
+
+    #[test]
+    fn test_gamma() {
+        // linear samples, tagged with a gamma of 1
+        let png = png(ColorType::Grayscale, 8, &[0, 55, 255], vec![chunk(ChunkType::GAMA, &100_000u32.to_be_bytes())]);
+        assert_eq!(png.gamma(), Some(1.0));
+        assert_eq!(png.render(&linear()).unwrap().samples, [0, 55, 255]);
+        assert_eq!(png.render(&RenderOptions::default()).unwrap().samples, [0, 128, 255]);
+        // sRGB overrides gAMA
+        let mut tagged = png.clone();
+        tagged.insert_after(ChunkType::IHDR, chunk(ChunkType::SRGB, &[0])).unwrap();
+        assert_eq!(tagged.render(&RenderOptions::default()).unwrap().samples, [0, 55, 255]);
+    }
+
+    #[test]
+    fn test_chromaticities() {
+        assert!(is_identity(&Chromaticities::SRGB.to_srgb().unwrap()));
+        // swapped red and green primaries swap the channels back
+        let swapped = Chromaticities { red: Chromaticities::SRGB.green, green: Chromaticities::SRGB.red, ..Chromaticities::SRGB };
+        let data: Vec<u8> = [swapped.white, swapped.red, swapped.green, swapped.blue]
+            .iter()
+            .flat_map(|&(x, y)| [x, y])
+            .flat_map(|value| ((value * 100_000.0).round() as u32).to_be_bytes())
+            .collect();
+        let png = png(ColorType::Rgb, 8, &[200, 10, 30], vec![chunk(ChunkType::CHRM, &data)]);
+        assert_eq!(png.chromaticities().unwrap().red, (0.30, 0.60));
+        let rendered = png.render(&RenderOptions::default()).unwrap().samples;
+        assert!(rendered.iter().zip([10, 200, 30]).all(|(&a, b)| a.abs_diff(b) <= 1), "{:?}", rendered);
+    }
+
+    #[test]
+    fn test_backgrounds() {
+        let samples = [255, 0, 0, 255, 255, 0, 0, 0, 255, 0, 0, 128];
+        let plain = png(ColorType::Rgba, 8, &samples, Vec::new());
+        let kept = plain.render(&RenderOptions::default()).unwrap();
+        assert_eq!((kept.color_type, kept.samples.as_slice()), (ColorType::Rgba, &samples[..]));
+        let white = RenderOptions { background: Some([255, 255, 255]), ..Default::default() };
+        let over_white = plain.render(&white).unwrap();
+        assert_eq!(over_white.color_type, ColorType::Rgb);
+        assert_eq!(&over_white.samples[..6], &[255, 0, 0, 255, 255, 255]);
+        // half covered, mixed in linear light rather than sRGB values
+        let half = linear_to_srgb(1.0 - 128.0 / 255.0);
+        assert_eq!(&over_white.samples[6..], &[255, (half * 255.0).round() as u16, (half * 255.0).round() as u16]);
+        // bKGD comes first
+        let bkgd = [0, 0, 0, 0, 0, 255].to_vec();
+        let tagged = png(ColorType::Rgba, 8, &samples, vec![chunk(ChunkType::BKGD, &bkgd)]);
+        assert_eq!(&tagged.render(&white).unwrap().samples[3..6], &[0, 0, 255]);
+        let ignored = RenderOptions { file_background: false, ..white };
+        assert_eq!(&tagged.render(&ignored).unwrap().samples[3..6], &[255, 255, 255]);
+    }
+
+    #[test]
+    fn test_transparent_color_and_gray_background() {
+        let trns = chunk(ChunkType::TRNS, &[0, 7]);
+        let png = png(ColorType::Grayscale, 4, &[7, 15], vec![trns]);
+        let kept = png.render(&RenderOptions::default()).unwrap();
+        assert_eq!((kept.color_type, kept.samples.as_slice()), (ColorType::GrayscaleAlpha, &[119, 0, 255, 255][..]));
+        let gray = RenderOptions { background: Some([9, 9, 9]), ..Default::default() };
+        assert_eq!(png.render(&gray).unwrap().samples, [9, 255]);
+        let red = RenderOptions { background: Some([255, 0, 0]), ..Default::default() };
+        let rendered = png.render(&red).unwrap();
+        assert_eq!((rendered.color_type, rendered.samples.as_slice()), (ColorType::Rgb, &[255, 0, 0, 255, 255, 255][..]));
+    }
+
+    #[test]
+    fn test_significant_bits_and_depth() {
+        let sbit = chunk(ChunkType::SBIT, &[4]);
+        let png = png(ColorType::Grayscale, 8, &[0xf0, 0x80], vec![sbit]);
+        assert_eq!(png.render(&RenderOptions::default()).unwrap().samples, [255, 136]);
+        let unshifted = RenderOptions { significant_bits: false, bit_depth: Some(16), ..Default::default() };
+        assert_eq!(png.render(&unshifted).unwrap().samples, [0xf0f0, 0x8080]);
+        let bad = RenderOptions { bit_depth: Some(4), ..Default::default() };
+        assert!(matches!(png.render(&bad), Err(RenderError::BitDepth(4))));
+    }
+}
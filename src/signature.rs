@@ -0,0 +1,167 @@
+use std::fmt;
+use crate::png::Png;
+
+// The png signature is built to catch the usual ways a transfer mangles binary
+// files. Each variant is recognised by what it does to the signature bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mangling {
+    CrlfToLf,
+    LfToCrlf,
+    HighBitCleared,
+    EofStripped,
+}
+impl Mangling {
+    pub const ALL: [Mangling; 4] = [
+        Mangling::CrlfToLf,
+        Mangling::LfToCrlf,
+        Mangling::HighBitCleared,
+        Mangling::EofStripped,
+    ];
+    // the signature as it looks after the mangling
+    pub fn signature(self) -> &'static [u8] {
+        match self {
+            Mangling::CrlfToLf => &[137, 80, 78, 71, 10, 26, 10],
+            Mangling::LfToCrlf => &[137, 80, 78, 71, 13, 13, 10, 26, 13, 10],
+            Mangling::HighBitCleared => &[9, 80, 78, 71, 13, 10, 26, 10],
+            Mangling::EofStripped => &[137, 80, 78, 71, 13, 10, 10],
+        }
+    }
+    // A mangling only counts if what follows it could be a chunk header, so a
+    // file that is corrupt for some other reason isn't reported as mangled
+    pub fn diagnose(bytes: &[u8]) -> Option<Mangling> {
+        Mangling::ALL.into_iter().find(|m| {
+            let n = m.signature().len();
+            bytes.starts_with(m.signature())
+                && bytes.get(n + 4..n + 8).is_none_or(|ctype| ctype.iter().all(u8::is_ascii_alphabetic))
+        })
+    }
+    // Whole-file reversals first, then just swapping the signature back in.
+    // Cleared high bits can't be restored, so only the signature is fixed for those.
+    fn candidates(self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        let rest = &bytes[self.signature().len()..];
+        let signature_only = [&Png::STANDARD_SIGNATURE[..], rest].concat();
+        match self {
+            Mangling::CrlfToLf => vec![
+                [&Png::STANDARD_SIGNATURE[..], &replace(rest, b"\n", b"\r\n")].concat(),
+                signature_only,
+            ],
+            Mangling::LfToCrlf => vec![
+                [&Png::STANDARD_SIGNATURE[..], &replace(rest, b"\r\n", b"\n")].concat(),
+                signature_only,
+            ],
+            Mangling::HighBitCleared | Mangling::EofStripped => vec![signature_only],
+        }
+    }
+    // Only returns a reversal that parses with every crc intact, so a
+    // conversion that could have gone more than one way is never guessed at
+    pub fn unmangle(self, bytes: &[u8]) -> Option<Vec<u8>> {
+        self.candidates(bytes)
+            .into_iter()
+            .find(|candidate| Png::try_from(candidate.as_slice()).is_ok())
+    }
+}
+impl fmt::Display for Mangling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            Mangling::CrlfToLf => "CRLF converted to LF",
+            Mangling::LfToCrlf => "LF converted to CRLF",
+            Mangling::HighBitCleared => "high bit cleared by a 7-bit transfer",
+            Mangling::EofStripped => "DOS EOF byte (0x1a) stripped",
+        };
+        write!(f, "{}", description)
+    }
+}
+fn replace(bytes: &[u8], from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i..].starts_with(from) {
+            result.extend_from_slice(to);
+            i += from.len();
+        } else {
+            result.push(bytes[i]);
+            i += 1;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn testing_bytes() -> Vec<u8> {
+        let chunks = vec![
+            Chunk::new(ChunkType::from_str("FrSt").unwrap(), b"line one\nline two\r\n".to_vec()),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]),
+        ];
+        Png::from_chunks(chunks).as_bytes()
+    }
+
+    #[test]
+    fn test_diagnose_standard() {
+        assert_eq!(Mangling::diagnose(&testing_bytes()), None);
+    }
+
+    #[test]
+    fn test_diagnose_each() {
+        for mangling in Mangling::ALL {
+            let bytes = [mangling.signature(), &[0, 0, 0, 0]].concat();
+            assert_eq!(Mangling::diagnose(&bytes), Some(mangling));
+        }
+    }
+
+    #[test]
+    fn test_diagnose_needs_chunk_after_signature() {
+        // CR converted to CRLF starts like a stripped EOF byte, but no chunk follows
+        let mangled = replace(&testing_bytes(), b"\r", b"\r\n");
+        assert_eq!(Mangling::diagnose(&mangled), None);
+    }
+
+    #[test]
+    fn test_unmangle_crlf_to_lf() {
+        let bytes = testing_bytes();
+        let mangled = replace(&bytes, b"\r\n", b"\n");
+        assert_eq!(Mangling::diagnose(&mangled), Some(Mangling::CrlfToLf));
+        // the data had a lone \n before the conversion, so there's no unambiguous reversal
+        assert_eq!(Mangling::CrlfToLf.unmangle(&mangled), None);
+    }
+
+    #[test]
+    fn test_unmangle_crlf_to_lf_unambiguous() {
+        let chunks = vec![
+            Chunk::new(ChunkType::from_str("FrSt").unwrap(), b"line one\r\nline two".to_vec()),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]),
+        ];
+        let bytes = Png::from_chunks(chunks).as_bytes();
+        let mangled = replace(&bytes, b"\r\n", b"\n");
+        assert_eq!(Mangling::CrlfToLf.unmangle(&mangled), Some(bytes));
+    }
+
+    #[test]
+    fn test_unmangle_lf_to_crlf() {
+        let bytes = testing_bytes();
+        let mangled = replace(&bytes, b"\n", b"\r\n");
+        assert_eq!(Mangling::diagnose(&mangled), Some(Mangling::LfToCrlf));
+        assert_eq!(Mangling::LfToCrlf.unmangle(&mangled), Some(bytes));
+    }
+
+    #[test]
+    fn test_unmangle_eof_stripped() {
+        let bytes = testing_bytes();
+        let mangled = [&bytes[..6], &bytes[7..]].concat();
+        assert_eq!(Mangling::diagnose(&mangled), Some(Mangling::EofStripped));
+        assert_eq!(Mangling::EofStripped.unmangle(&mangled), Some(bytes));
+    }
+
+    #[test]
+    fn test_png_error_names_mangling() {
+        let mut bytes = testing_bytes();
+        bytes[0] &= 0x7f;
+        let err = Png::try_from(bytes.as_slice()).err().unwrap();
+        assert_eq!(err.to_string(), "Bad Png Signature: high bit cleared by a 7-bit transfer");
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::HashSet;
+use std::fmt;
+use thiserror::Error;
+use crate::chunk_type::ChunkType;
+use crate::ihdr::{ColorType, Ihdr, IhdrError};
+use crate::png::Png;
+
+// Ordering and multiplicity rules from the chunk ordering table in the spec.
+// See https://www.w3.org/TR/png/#5ChunkOrdering
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Constraint {
+    pub multiple: bool,
+    pub before_plte: bool,
+    pub after_plte: bool,
+    pub before_idat: bool,
+    pub requires: Option<ChunkType>,
+    pub excludes: Option<ChunkType>,
+}
+impl Constraint {
+    const ANYWHERE: Constraint = Constraint {
+        multiple: false,
+        before_plte: false,
+        after_plte: false,
+        before_idat: false,
+        requires: None,
+        excludes: None,
+    };
+    const BEFORE_PLTE: Constraint = Constraint { before_plte: true, before_idat: true, ..Constraint::ANYWHERE };
+    const AFTER_PLTE: Constraint = Constraint { after_plte: true, before_idat: true, ..Constraint::ANYWHERE };
+    const BEFORE_IDAT: Constraint = Constraint { before_idat: true, ..Constraint::ANYWHERE };
+}
+pub const CONSTRAINTS: &[(ChunkType, Constraint)] = &[
+    (ChunkType::IHDR, Constraint::ANYWHERE),
+    (ChunkType::PLTE, Constraint::BEFORE_IDAT),
+    (ChunkType::IDAT, Constraint { multiple: true, ..Constraint::ANYWHERE }),
+    (ChunkType::IEND, Constraint::ANYWHERE),
+    (ChunkType::CHRM, Constraint::BEFORE_PLTE),
+    (ChunkType::GAMA, Constraint::BEFORE_PLTE),
+    (ChunkType::ICCP, Constraint { excludes: Some(ChunkType::SRGB), ..Constraint::BEFORE_PLTE }),
+    (ChunkType::SBIT, Constraint::BEFORE_PLTE),
+    (ChunkType::SRGB, Constraint { excludes: Some(ChunkType::ICCP), ..Constraint::BEFORE_PLTE }),
+    (ChunkType::BKGD, Constraint::AFTER_PLTE),
+    (ChunkType::HIST, Constraint { requires: Some(ChunkType::PLTE), ..Constraint::AFTER_PLTE }),
+    (ChunkType::TRNS, Constraint::AFTER_PLTE),
+    (ChunkType::PHYS, Constraint::BEFORE_IDAT),
+    (ChunkType::SPLT, Constraint { multiple: true, ..Constraint::BEFORE_IDAT }),
+    (ChunkType::EXIF, Constraint::BEFORE_IDAT),
+    (ChunkType::TIME, Constraint::ANYWHERE),
+    (ChunkType::ITXT, Constraint { multiple: true, ..Constraint::ANYWHERE }),
+    (ChunkType::TEXT, Constraint { multiple: true, ..Constraint::ANYWHERE }),
+    (ChunkType::ZTXT, Constraint { multiple: true, ..Constraint::ANYWHERE }),
+];
+pub fn constraint(ctype: ChunkType) -> Option<&'static Constraint> {
+    CONSTRAINTS.iter()
+        .find(|(known, _)| *known == ctype)
+        .map(|(_, constraint)| constraint)
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum Violation {
+    #[error("Missing {0} chunk")]
+    Missing(ChunkType),
+    #[error("IHDR is not the first chunk")]
+    IhdrNotFirst,
+    #[error("Invalid IHDR: {0}")]
+    InvalidIhdr(IhdrError),
+    #[error("PLTE isn't allowed in {0} images")]
+    PlteNotAllowed(ColorType),
+    #[error("{0} chunks after IEND")]
+    AfterIend(usize),
+    #[error("IDAT chunks are not consecutive")]
+    IdatNotConsecutive,
+    #[error("Multiple {0} chunks")]
+    Duplicate(ChunkType),
+    #[error("{0} must come before {1}")]
+    NotBefore(ChunkType, ChunkType),
+    #[error("{0} must come after {1}")]
+    NotAfter(ChunkType, ChunkType),
+    #[error("{0} requires a {1} chunk")]
+    Requires(ChunkType, ChunkType),
+    #[error("{0} and {1} must not both be present")]
+    Conflict(ChunkType, ChunkType),
+    #[error("Unknown critical chunk {0}")]
+    UnknownCritical(ChunkType),
+    #[error("{0} has the reserved bit set")]
+    ReservedBit(ChunkType),
+}
+#[derive(Debug, Clone, PartialEq)]
+pub struct Issue {
+    pub severity: Severity,
+    // index of the offending chunk, if the issue is about a single chunk
+    pub chunk: Option<usize>,
+    pub violation: Violation,
+}
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.chunk {
+            Some(index) => write!(f, "{}: chunk {}: {}", self.severity, index, self.violation),
+            None => write!(f, "{}: {}", self.severity, self.violation),
+        }
+    }
+}
+// Problems with critical chunks stop a decoder, misplaced ancillary chunks just get ignored
+fn severity_of(ctype: ChunkType) -> Severity {
+    if ctype.is_critical() { Severity::Error } else { Severity::Warning }
+}
+impl Png {
+    pub fn validate(&self) -> Vec<Issue> {
+        let chunks = self.chunks();
+        let mut issues = Vec::new();
+        let mut push = |severity, chunk, violation| issues.push(Issue { severity, chunk, violation });
+
+        match self.position_of(ChunkType::IHDR) {
+            None => push(Severity::Error, None, Violation::Missing(ChunkType::IHDR)),
+            Some(0) => {}
+            Some(i) => push(Severity::Error, Some(i), Violation::IhdrNotFirst),
+        }
+        let plte = self.position_of(ChunkType::PLTE);
+        if let Some(i) = self.position_of(ChunkType::IHDR) {
+            match Ihdr::try_from(chunks[i].data()) {
+                Ok(ihdr) => match (ihdr.color_type, plte) {
+                    (ColorType::Indexed, None) => push(Severity::Error, None, Violation::Missing(ChunkType::PLTE)),
+                    (ColorType::Grayscale | ColorType::GrayscaleAlpha, Some(p)) => {
+                        push(Severity::Error, Some(p), Violation::PlteNotAllowed(ihdr.color_type))
+                    }
+                    _ => {}
+                },
+                Err(e) => push(Severity::Error, Some(i), Violation::InvalidIhdr(e)),
+            }
+        }
+        let idats: Vec<usize> = (0..chunks.len()).filter(|&i| *chunks[i].chunk_type() == ChunkType::IDAT).collect();
+        match (idats.first(), idats.last()) {
+            (Some(first), Some(last)) if last - first + 1 != idats.len() => {
+                push(Severity::Error, None, Violation::IdatNotConsecutive)
+            }
+            (None, _) => push(Severity::Error, None, Violation::Missing(ChunkType::IDAT)),
+            _ => {}
+        }
+        match self.position_of(ChunkType::IEND) {
+            None => push(Severity::Error, None, Violation::Missing(ChunkType::IEND)),
+            Some(i) if i + 1 < chunks.len() => {
+                push(Severity::Error, Some(i + 1), Violation::AfterIend(chunks.len() - i - 1))
+            }
+            _ => {}
+        }
+
+        let first_idat = idats.first().copied();
+        let mut seen = HashSet::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let ctype = *chunk.chunk_type();
+            let severity = severity_of(ctype);
+            let first = seen.insert(ctype);
+            if !ctype.is_reserved_bit_valid() {
+                push(Severity::Error, Some(i), Violation::ReservedBit(ctype));
+            }
+            let Some(constraint) = constraint(ctype) else {
+                if ctype.is_critical() {
+                    push(Severity::Error, Some(i), Violation::UnknownCritical(ctype));
+                }
+                continue;
+            };
+            if !constraint.multiple && !first {
+                push(severity, Some(i), Violation::Duplicate(ctype));
+            }
+            if constraint.before_plte && plte.is_some_and(|p| i > p) {
+                push(severity, Some(i), Violation::NotBefore(ctype, ChunkType::PLTE));
+            }
+            if constraint.after_plte && plte.is_some_and(|p| i < p) {
+                push(severity, Some(i), Violation::NotAfter(ctype, ChunkType::PLTE));
+            }
+            if constraint.before_idat && first_idat.is_some_and(|p| i > p) {
+                push(severity, Some(i), Violation::NotBefore(ctype, ChunkType::IDAT));
+            }
+            if let Some(required) = constraint.requires
+                && first && self.position_of(required).is_none() {
+                push(severity, Some(i), Violation::Requires(ctype, required));
+            }
+            // report an exclusive pair once, at whichever of the two comes second
+            if let Some(excluded) = constraint.excludes
+                && first && seen.contains(&excluded) {
+                push(severity, Some(i), Violation::Conflict(excluded, ctype));
+            }
+        }
+        issues
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use std::str::FromStr;
+
+    fn png_of(types: &[&str]) -> Png {
+        let ihdr = Ihdr { width: 1, height: 1, bit_depth: 8, color_type: ColorType::Indexed, interlaced: false };
+        let chunks = types.iter()
+            .map(|&ctype| match ctype {
+                "IHDR" => ihdr.to_chunk(),
+                _ => Chunk::new(ChunkType::from_str(ctype).unwrap(), Vec::new()),
+            })
+            .collect();
+        Png::from_chunks(chunks)
+    }
+
+    fn violations(png: &Png) -> Vec<Violation> {
+        png.validate().into_iter().map(|issue| issue.violation).collect()
+    }
+
+    #[test]
+    fn test_constraint_lookup() {
+        assert!(constraint(ChunkType::GAMA).unwrap().before_plte);
+        assert!(constraint(ChunkType::TEXT).unwrap().multiple);
+        assert!(constraint(ChunkType::from_str("ruSt").unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_validate_ok() {
+        let png = png_of(&["IHDR", "gAMA", "PLTE", "tRNS", "tEXt", "IDAT", "IDAT", "tEXt", "IEND"]);
+        assert_eq!(png.validate(), vec![]);
+    }
+
+    #[test]
+    fn test_validate_critical() {
+        let png = png_of(&["gAMA", "IHDR", "PLTE", "IDAT", "tEXt", "IDAT", "IEND", "ruSt"]);
+        assert_eq!(
+            violations(&png),
+            vec![Violation::IhdrNotFirst, Violation::IdatNotConsecutive, Violation::AfterIend(1)]
+        );
+        assert!(png.validate().iter().all(|issue| issue.severity == Severity::Error));
+        assert_eq!(violations(&png_of(&[]))[0], Violation::Missing(ChunkType::IHDR));
+    }
+
+    #[test]
+    fn test_validate_ancillary() {
+        let png = png_of(&["IHDR", "gAMA", "tRNS", "PLTE", "gAMA", "sRGB", "iCCP", "IDAT", "pHYs", "IEND"]);
+        assert_eq!(
+            png.validate(),
+            vec![
+                Issue { severity: Severity::Warning, chunk: Some(2), violation: Violation::NotAfter(ChunkType::TRNS, ChunkType::PLTE) },
+                Issue { severity: Severity::Warning, chunk: Some(4), violation: Violation::Duplicate(ChunkType::GAMA) },
+                Issue { severity: Severity::Warning, chunk: Some(4), violation: Violation::NotBefore(ChunkType::GAMA, ChunkType::PLTE) },
+                Issue { severity: Severity::Warning, chunk: Some(5), violation: Violation::NotBefore(ChunkType::SRGB, ChunkType::PLTE) },
+                Issue { severity: Severity::Warning, chunk: Some(6), violation: Violation::NotBefore(ChunkType::ICCP, ChunkType::PLTE) },
+                Issue { severity: Severity::Warning, chunk: Some(6), violation: Violation::Conflict(ChunkType::SRGB, ChunkType::ICCP) },
+                Issue { severity: Severity::Warning, chunk: Some(8), violation: Violation::NotBefore(ChunkType::PHYS, ChunkType::IDAT) },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_ihdr() {
+        let mut png = png_of(&["IHDR", "PLTE", "IDAT", "IEND"]);
+        png.remove_first_chunk("IHDR").unwrap();
+        png.insert_chunk_at(0, Chunk::new(ChunkType::IHDR, vec![0; 13])).unwrap();
+        assert_eq!(violations(&png), vec![Violation::InvalidIhdr(IhdrError::Dimensions(0, 0))]);
+
+        let ihdr = Ihdr { width: 1, height: 1, bit_depth: 8, color_type: ColorType::Grayscale, interlaced: false };
+        png.remove_first_chunk("IHDR").unwrap();
+        png.insert_chunk_at(0, ihdr.to_chunk()).unwrap();
+        assert_eq!(violations(&png), vec![Violation::PlteNotAllowed(ColorType::Grayscale)]);
+    }
+
+    #[test]
+    fn test_validate_unknown_chunks() {
+        let png = png_of(&["IHDR", "hIST", "RuSt", "ruSt", "IDAT", "IEND"]);
+        assert_eq!(
+            violations(&png),
+            vec![
+                Violation::Missing(ChunkType::PLTE),
+                Violation::Requires(ChunkType::HIST, ChunkType::PLTE),
+                Violation::UnknownCritical(ChunkType::from_str("RuSt").unwrap()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,141 @@
+// Runs the pngme binary on small images made with the library
+use pngme::chunk::Chunk;
+use pngme::chunk_type::ChunkType;
+use pngme::encode::EncodeOptions;
+use pngme::ihdr::ColorType;
+use pngme::pixels::Pixels;
+use pngme::png::Png;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+// a fresh directory per test, so they can run in parallel
+fn scratch(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("pngme-cli-{}-{}", std::process::id(), test));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn pngme(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_pngme")).args(args).output().unwrap()
+}
+
+fn write_png(path: &PathBuf, color_type: ColorType, samples: Vec<u16>) {
+    let width = (samples.len() / color_type.channels() as usize) as u32;
+    let pixels = Pixels { width, height: 1, color_type, bit_depth: 8, samples };
+    fs::write(path, Png::from_pixels(&pixels, None, &EncodeOptions::default()).unwrap().as_bytes()).unwrap();
+}
+
+fn read_png(path: &PathBuf) -> Png {
+    Png::try_from(fs::read(path).unwrap().as_slice()).unwrap()
+}
+
+#[test]
+fn convert_renders_over_a_background() {
+    let dir = scratch("render-background");
+    let (input, output) = (dir.join("in.png"), dir.join("out.png"));
+    write_png(&input, ColorType::Rgba, vec![255, 0, 0, 255, 0, 0, 0, 0]);
+    let result = pngme(&["convert", input.to_str().unwrap(), "-o", output.to_str().unwrap(), "--background", "00ff00"]);
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    let png = read_png(&output);
+    let pixels = png.pixels().unwrap();
+    assert_eq!((pixels.color_type, pixels.samples), (ColorType::Rgb, vec![255, 0, 0, 0, 255, 0]));
+    assert!(png.position_of(ChunkType::SRGB).is_some());
+}
+
+#[test]
+fn convert_renders_linear_light() {
+    let dir = scratch("render-linear");
+    let (input, output) = (dir.join("in.png"), dir.join("out.png"));
+    write_png(&input, ColorType::Grayscale, vec![0, 128, 255]);
+    let result = pngme(&["convert", input.to_str().unwrap(), "-o", output.to_str().unwrap(), "--render", "linear"]);
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    let png = read_png(&output);
+    // sRGB 128 is 21.6% of the light
+    assert_eq!(png.pixels().unwrap().samples, vec![0, 55, 255]);
+    assert_eq!(png.gamma(), Some(1.0));
+}
+
+#[test]
+fn convert_renders_only_png() {
+    let dir = scratch("render-pnm");
+    let (input, output) = (dir.join("in.pgm"), dir.join("out.png"));
+    fs::write(&input, b"P5 1 1 255\n\x80").unwrap();
+    let result = pngme(&["convert", input.to_str().unwrap(), "-o", output.to_str().unwrap(), "--render", "srgb"]);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("Only png input can be rendered"));
+    let result = pngme(&["convert", input.to_str().unwrap(), "-o", output.to_str().unwrap(), "--background", "red"]);
+    assert!(String::from_utf8_lossy(&result.stderr).contains("isn't a RRGGBB hex color"));
+}
+
+#[test]
+fn remove_matches_latin1_text() {
+    let dir = scratch("remove-latin1");
+    let path = dir.join("text.png");
+    write_png(&path, ColorType::Grayscale, vec![0]);
+    let mut png = read_png(&path);
+    for text in [&b"Title\0caf\xe9"[..], b"Title\0tea"] {
+        png.insert_ancillary(Chunk::new(ChunkType::TEXT, text.to_vec())).unwrap();
+    }
+    fs::write(&path, png.as_bytes()).unwrap();
+    let result = pngme(&["remove", path.to_str().unwrap(), "tEXt", "--all", "--matching", "café"]);
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    let texts: Vec<Vec<u8>> = read_png(&path).chunks_by_type(ChunkType::TEXT).map(|chunk| chunk.data().to_vec()).collect();
+    assert_eq!(texts, vec![b"Title\0tea".to_vec()]);
+}
+
+#[test]
+fn convert_keeps_sub_byte_transparency() {
+    let dir = scratch("convert-trns");
+    let (input, output) = (dir.join("in.png"), dir.join("out.png"));
+    let pixels = Pixels { width: 8, height: 1, color_type: ColorType::Grayscale, bit_depth: 1, samples: vec![0, 1, 0, 1, 1, 1, 0, 0] };
+    let mut png = Png::from_pixels(&pixels, None, &EncodeOptions::default()).unwrap();
+    png.insert_ancillary(Chunk::new(ChunkType::TRNS, vec![0, 1])).unwrap();
+    fs::write(&input, png.as_bytes()).unwrap();
+    let result = pngme(&["convert", input.to_str().unwrap(), "-o", output.to_str().unwrap()]);
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    let converted = read_png(&output).pixels().unwrap();
+    assert_eq!((converted.color_type, converted.bit_depth), (ColorType::GrayscaleAlpha, 8));
+    assert_eq!(converted.samples[..4], [0, 255, 255, 0]);
+}
+
+#[test]
+fn batch_outputs_keep_the_directory_layout() {
+    let dir = scratch("batch-layout");
+    let (input, output) = (dir.join("in"), dir.join("out"));
+    for name in ["a/x.png", "b/x.png"] {
+        fs::create_dir_all(input.join(name).parent().unwrap()).unwrap();
+        write_png(&input.join(name), ColorType::Grayscale, vec![0, 255]);
+    }
+    let result = pngme(&["flip", input.to_str().unwrap(), "-o", output.to_str().unwrap()]);
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    for name in ["a/x.png", "b/x.png"] {
+        assert_eq!(read_png(&output.join(name)).pixels().unwrap().samples, vec![255, 0]);
+    }
+    // a directory with a single file is still a batch, written into the output directory
+    let (single, output) = (input.join("a"), dir.join("single"));
+    let result = pngme(&["flip", single.to_str().unwrap(), "-o", output.to_str().unwrap()]);
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    assert!(output.join("x.png").is_file());
+    // files given directly with the same name would overwrite each other
+    let (a, b) = (input.join("a/x.png"), input.join("b/x.png"));
+    let result = pngme(&["flip", a.to_str().unwrap(), b.to_str().unwrap(), "-o", dir.join("flat").to_str().unwrap()]);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("would both be written to the same output file"));
+}
+
+#[test]
+fn convert_expands_every_extension_of_the_format() {
+    let dir = scratch("convert-extensions");
+    let (input, output) = (dir.join("in"), dir.join("out"));
+    fs::create_dir_all(&input).unwrap();
+    fs::write(input.join("gray.pgm"), b"P5 1 1 255\n\x80").unwrap();
+    fs::write(input.join("color.ppm"), b"P6 1 1 255\n\x01\x02\x03").unwrap();
+    fs::write(input.join("bits.pbm"), b"P4 1 1\n\x80").unwrap();
+    let result = pngme(&["convert", input.to_str().unwrap(), "--from", "pnm", "--to", "png", "-o", output.to_str().unwrap()]);
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    for name in ["gray.png", "color.png", "bits.png"] {
+        assert!(output.join(name).is_file(), "{}", name);
+    }
+}
@@ -0,0 +1,238 @@
+#!/usr/bin/env python3
+"""Regenerates the extra test images in this directory.
+
+These are not PngSuite, which is vendored in tests/pngsuite. They are our own
+images named in PngSuite's scheme for the properties they cover (color type,
+bit depth, interlacing, filters, compression level, IDAT split, ancillary
+chunks and the x* corruptions), for the cases that need known pixels: every
+valid image comes with a NAME.raw holding them, the unfiltered, de-interlaced
+scanlines in the image's own sample format, without filter bytes.
+
+Only the python standard library is used, run it from anywhere:
+    python3 tests/generated/generate.py
+"""
+import os
+import struct
+import zlib
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+SIGNATURE = b"\x89PNG\r\n\x1a\n"
+CHANNELS = {0: 1, 2: 3, 3: 1, 4: 2, 6: 4}
+# x0, y0, dx, dy of the seven Adam7 passes
+ADAM7 = [(0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2)]
+
+
+def chunk(ctype, data):
+    crc = zlib.crc32(ctype + data) & 0xFFFFFFFF
+    return struct.pack(">I", len(data)) + ctype + data + struct.pack(">I", crc)
+
+
+def ihdr(width, height, depth, color, interlace):
+    return struct.pack(">IIBBBBB", width, height, depth, color, 0, 0, interlace)
+
+
+def sample(x, y, c, depth):
+    if depth == 16:
+        return (x * 2113 + y * 4177 + c * 9001 + x * y * 37) & 0xFFFF
+    return (x * 3 + y * 5 + c * 7 + x * y) % (1 << depth)
+
+
+def pixels(width, height, depth, color, sbit=None):
+    """Rows of pixels, each pixel a tuple of samples"""
+    channels = CHANNELS[color]
+    rows = []
+    for y in range(height):
+        row = []
+        for x in range(width):
+            if sbit:
+                # significant bits replicated into the low bits, the way a
+                # conforming encoder scales them up
+                px = []
+                for c in range(channels):
+                    v = sample(x, y, c, sbit)
+                    out, shift = 0, depth - sbit
+                    while shift > -sbit:
+                        out |= v << shift if shift >= 0 else v >> -shift
+                        shift -= sbit
+                    px.append(out)
+                row.append(tuple(px))
+            else:
+                row.append(tuple(sample(x, y, c, depth) for c in range(channels)))
+        rows.append(row)
+    return rows
+
+
+def pack(row, depth):
+    """Packs one row of pixels into bytes, big-endian, high bits first"""
+    if depth == 16:
+        return b"".join(struct.pack(">H", s) for px in row for s in px)
+    if depth == 8:
+        return bytes(s for px in row for s in px)
+    out, acc, bits = bytearray(), 0, 0
+    for px in row:
+        for s in px:
+            acc, bits = (acc << depth) | s, bits + depth
+            if bits == 8:
+                out.append(acc)
+                acc, bits = 0, 0
+    if bits:
+        out.append(acc << (8 - bits))
+    return bytes(out)
+
+
+def paeth(a, b, c):
+    p = a + b - c
+    pa, pb, pc = abs(p - a), abs(p - b), abs(p - c)
+    if pa <= pb and pa <= pc:
+        return a
+    return b if pb <= pc else c
+
+
+def filter_row(kind, row, prev, bpp):
+    out = bytearray([kind])
+    for i, x in enumerate(row):
+        a = row[i - bpp] if i >= bpp else 0
+        b = prev[i] if prev else 0
+        c = prev[i - bpp] if prev and i >= bpp else 0
+        predictor = [0, a, b, (a + b) // 2, paeth(a, b, c)][kind]
+        out.append((x - predictor) & 0xFF)
+    return bytes(out)
+
+
+def scanlines(rows, depth, color, filters):
+    """Filtered scanlines of one (sub)image; filter i is used for row i"""
+    bpp = max(1, depth * CHANNELS[color] // 8)
+    out, prev = b"", None
+    for y, row in enumerate(rows):
+        packed = pack(row, depth)
+        out += filter_row(filters[y % len(filters)], packed, prev, bpp)
+        prev = packed
+    return out
+
+
+def image_data(rows, depth, color, interlace, filters):
+    if not interlace:
+        return scanlines(rows, depth, color, filters)
+    height, width = len(rows), len(rows[0])
+    out = b""
+    for x0, y0, dx, dy in ADAM7:
+        sub = [row[x0::dx] for row in rows[y0::dy]]
+        if sub and sub[0]:
+            out += scanlines(sub, depth, color, filters)
+    return out
+
+
+def palette(depth):
+    entries = 1 << depth
+    return b"".join(bytes([(i * 71) % 256, (i * 113 + 40) % 256, (255 - i * 37) % 256]) for i in range(entries))
+
+
+def build(name, width=32, height=32, depth=8, color=0, interlace=0, filters=(0, 1, 2, 3, 4),
+          level=9, idat_chunks=1, before=(), after=(), sbit=None):
+    rows = pixels(width, height, depth, color, sbit)
+    data = zlib.compress(image_data(rows, depth, color, interlace, filters), level)
+    body = chunk(b"IHDR", ihdr(width, height, depth, color, interlace))
+    for ctype, payload in before:
+        body += chunk(ctype, payload)
+    if color == 3:
+        body += chunk(b"PLTE", palette(depth))
+    for ctype, payload in after:
+        body += chunk(ctype, payload)
+    if idat_chunks == "bytes":
+        pieces = [data[i:i + 1] for i in range(len(data))]
+    else:
+        size = -(-len(data) // idat_chunks)
+        pieces = [data[i:i + size] for i in range(0, len(data), size)]
+    for piece in pieces:
+        body += chunk(b"IDAT", piece)
+    body += chunk(b"IEND", b"")
+    png = SIGNATURE + body
+    raw = b"".join(pack(row, depth) for row in rows)
+    return png, raw
+
+
+def write(name, png, raw=None):
+    with open(os.path.join(HERE, name + ".png"), "wb") as f:
+        f.write(png)
+    if raw is not None:
+        with open(os.path.join(HERE, name + ".raw"), "wb") as f:
+            f.write(raw)
+
+
+def valid(name, **kwargs):
+    write(name, *build(name, **kwargs))
+
+
+def corrupt(name, png):
+    write(name, png)
+
+
+def set_ihdr(png, **fields):
+    """Rewrites IHDR fields of a built png, with a correct crc"""
+    width, height, depth, color, comp, filt, interlace = struct.unpack(">IIBBBBB", png[16:29])
+    values = dict(width=width, height=height, depth=depth, color=color, interlace=interlace)
+    values.update(fields)
+    new = chunk(b"IHDR", ihdr(values["width"], values["height"], values["depth"], values["color"], values["interlace"]))
+    return png[:8] + new + png[8 + len(new):]
+
+
+def main():
+    formats = [(0, 1), (0, 2), (0, 4), (0, 8), (0, 16), (2, 8), (2, 16), (3, 1), (3, 2), (3, 4), (3, 8),
+               (4, 8), (4, 16), (6, 8), (6, 16)]
+    kind = {0: "g", 2: "c", 3: "p", 4: "a", 6: "a"}
+    for color, depth in formats:
+        for interlace, letter in [(0, "n"), (1, "i")]:
+            valid("bas%s%d%s%02d" % (letter, color, kind[color], depth), depth=depth, color=color, interlace=interlace)
+    # one filter type for every row
+    for f in range(5):
+        valid("f%02dn2c08" % f, color=2, filters=(f,))
+        valid("f%02dn0g08" % f, color=0, filters=(f,))
+    # odd sizes, which leave some Adam7 passes empty
+    for size in range(1, 10):
+        for interlace, letter in [(0, "n"), (1, "i")]:
+            valid("s%02d%s3p%02d" % (size, letter, 1 if size < 5 else 2), width=size, height=size,
+                  depth=1 if size < 5 else 2, color=3, interlace=interlace)
+    for level in (0, 3, 6, 9):
+        valid("z%02dn2c08" % level, color=2, level=level)
+    for count, name in [(1, "oi1n0g16"), (2, "oi2n0g16"), (4, "oi4n0g16"), ("bytes", "oi9n0g16")]:
+        valid(name, color=0, depth=16, idat_chunks=count)
+    # ancillary chunks
+    valid("tbrn2c08", color=2, after=[(b"tRNS", struct.pack(">HHH", 255, 255, 255))])
+    valid("tbbn3p08", color=3, after=[(b"tRNS", bytes(range(255, 0, -1))), (b"bKGD", bytes([0]))])
+    valid("bgwn6a08", color=6, after=[(b"bKGD", struct.pack(">HHH", 255, 255, 255))])
+    valid("bggn4a16", color=4, depth=16, after=[(b"bKGD", struct.pack(">H", 43908))])
+    valid("g04n0g16", color=0, depth=16, before=[(b"gAMA", struct.pack(">I", 40000))])
+    valid("g10n2c08", color=2, before=[(b"gAMA", struct.pack(">I", 100000))])
+    valid("ccwn2c08", color=2, before=[
+        (b"gAMA", struct.pack(">I", 100000)),
+        (b"cHRM", struct.pack(">8I", 31270, 32900, 64000, 33000, 30000, 60000, 15000, 6000)),
+    ])
+    valid("cs5n2c08", color=2, sbit=5, before=[(b"sBIT", bytes([5, 5, 5]))])
+    valid("cs3n2c16", color=2, depth=16, sbit=13, before=[(b"sBIT", bytes([13, 13, 13]))])
+
+    # corrupt images, each made from a valid one
+    g01, _ = build("basn0g01", depth=1)
+    g04, _ = build("basn0g04", depth=4)
+    g08, _ = build("basn0g08")
+    c08, _ = build("basn2c08", color=2)
+    corrupt("xs1n0g01", bytes([g01[0] & 0x7F]) + g01[1:])
+    corrupt("xs2n0g01", g01[:1] + b"Q" + g01[2:])
+    corrupt("xs4n0g01", g01[:3] + b"g" + g01[4:])
+    corrupt("xs7n0g01", g01[:6] + b" " + g01[7:])
+    corrupt("xcrn0g04", g04.replace(b"\n", b"\r\n"))
+    corrupt("xlfn0g04", g04.replace(b"\r", b"\r\n"))
+    corrupt("xhdn0g08", g08[:29] + bytes([g08[29] ^ 0xFF]) + g08[30:])
+    corrupt("xc1n0g08", set_ihdr(g08, color=1))
+    corrupt("xc9n2c08", set_ihdr(c08, color=9))
+    corrupt("xd0n2c08", set_ihdr(c08, depth=0))
+    corrupt("xd3n2c08", set_ihdr(c08, depth=3))
+    corrupt("xd9n2c08", set_ihdr(c08, depth=99))
+    idat = g01.index(b"IDAT") - 4
+    length = struct.unpack(">I", g01[idat:idat + 4])[0]
+    corrupt("xdtn0g01", g01[:idat] + g01[idat + 12 + length:])
+    crc = idat + 8 + length
+    corrupt("xcsn0g01", g01[:crc] + bytes([g01[crc] ^ 0xFF]) + g01[crc + 1:])
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,285 @@
+// Conformance tests over PngSuite, fetched into tests/pngsuite with netpbm's
+// decoding of each image as the reference, and over our own extra images in
+// tests/generated, described by generate.py there. Both are named in PngSuite's
+// scheme, x* files are the corrupt ones. The PngSuite tests are ignored until
+// tests/pngsuite/fetch.sh has been run and its output committed.
+use pngme::chunk::ChunkError;
+use pngme::chunk_type::ChunkType;
+use pngme::decode::DecodeError;
+use pngme::diff;
+use pngme::encode::EncodeOptions;
+use pngme::formats::{self, Format};
+use pngme::ihdr::{ColorType, IhdrError};
+use pngme::limits::Limits;
+use pngme::ops::ResizeFilter;
+use pngme::pixels::Palette;
+use pngme::png::{Png, PngError};
+use pngme::render::RenderOptions;
+use pngme::signature::Mangling;
+use pngme::validate::{Severity, Violation};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn pngsuite() -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/pngsuite");
+    assert!(dir.join("PngSuite.LICENSE").is_file(), "PngSuite isn't vendored, run tests/pngsuite/fetch.sh");
+    dir
+}
+
+fn generated() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/generated")
+}
+
+fn images(dir: &Path, corrupt: bool) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "png"))
+        .map(|path| path.file_stem().unwrap().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with('x') == corrupt)
+        .collect();
+    names.sort();
+    names
+}
+
+fn read(dir: &Path, name: &str, extension: &str) -> Vec<u8> {
+    fs::read(dir.join(name).with_extension(extension)).unwrap()
+}
+
+// everything short of decoding the pixels
+fn check(bytes: &[u8]) -> Result<Png, PngError> {
+    let png = Png::try_from(bytes)?;
+    png.ihdr()?;
+    Ok(png)
+}
+
+#[test]
+fn valid_images_parse() {
+    let dir = generated();
+    let names = images(&dir, false);
+    assert_eq!(names.len(), 75);
+    for name in names {
+        let png = check(&read(&dir, &name, "png")).unwrap_or_else(|e| panic!("{}: {}", name, e));
+        let errors: Vec<_> = png.validate().into_iter().filter(|issue| issue.severity == Severity::Error).collect();
+        assert!(errors.is_empty(), "{}: {:?}", name, errors);
+        let raw = read(&dir, &name, "raw");
+        assert_eq!(raw.len() as u64, png.ihdr().unwrap().image_bytes(), "{}", name);
+    }
+}
+
+#[test]
+fn valid_images_decode() {
+    let dir = generated();
+    for name in images(&dir, false) {
+        let png = check(&read(&dir, &name, "png")).unwrap();
+        let image = png.decode().unwrap_or_else(|e| panic!("{}: {}", name, e));
+        assert!(image.data == read(&dir, &name, "raw"), "{}: pixels differ", name);
+    }
+}
+
+#[test]
+fn valid_images_convert() {
+    let dir = generated();
+    for name in images(&dir, false) {
+        let png = check(&read(&dir, &name, "png")).unwrap();
+        let image = png.decode().unwrap();
+        let pixels = image.to_pixels();
+        assert_eq!(pixels.to_image().unwrap().data, image.data, "{}", name);
+        let rgba = pixels.to_color_type(ColorType::Rgba, png.palette().as_ref()).unwrap();
+        assert_eq!(rgba.samples.len(), 4 * (pixels.width * pixels.height) as usize, "{}", name);
+        let depth = rgba.bit_depth;
+        assert_eq!(rgba.to_bit_depth(16).unwrap().to_bit_depth(depth).unwrap(), rgba, "{}", name);
+        if pixels.color_type != ColorType::Indexed
+            && depth == 8
+            && let Ok(palette) = Palette::from_pixels(&pixels)
+        {
+            let indexed = pixels.to_color_type(ColorType::Indexed, Some(&palette)).unwrap();
+            assert_eq!(indexed.to_color_type(ColorType::Rgba, Some(&palette)).unwrap(), rgba, "{}", name);
+        }
+    }
+}
+
+// generate.py's palette
+#[test]
+fn palette_is_read() {
+    let dir = generated();
+    let png = check(&read(&dir, "basn3p08", "png")).unwrap();
+    let palette = png.palette().unwrap();
+    assert_eq!(palette.colors.len(), 256);
+    for (i, color) in palette.colors.iter().enumerate() {
+        let i = i as i32;
+        let expected = [i * 71, i * 113 + 40, 255 - i * 37].map(|value| value.rem_euclid(256) as u8);
+        assert_eq!(*color, expected);
+    }
+    let png = check(&read(&dir, "tbbn3p08", "png")).unwrap();
+    assert_eq!(png.palette().unwrap().rgba(0), Some([0, 40, 255, 255]));
+}
+
+// The sBIT images hold their significant bits replicated into the low ones,
+// expanding them has to land within one of that
+#[test]
+fn significant_bits_expand() {
+    let dir = generated();
+    for (name, bits) in [("cs5n2c08", 5), ("cs3n2c16", 13)] {
+        let png = check(&read(&dir, name, "png")).unwrap();
+        let sbit = png.significant_bits().unwrap().unwrap();
+        assert_eq!(sbit, [bits; 3]);
+        let pixels = png.pixels().unwrap();
+        let expanded = pixels.expand_significant_bits(&sbit).unwrap();
+        let shift = pixels.bit_depth - bits;
+        for (&before, &after) in pixels.samples.iter().zip(&expanded.samples) {
+            assert_eq!(before >> shift, after >> shift, "{}", name);
+            assert!(before.abs_diff(after) <= 1, "{}: {} became {}", name, before, after);
+        }
+    }
+}
+
+#[test]
+fn valid_images_render() {
+    let dir = generated();
+    let white = RenderOptions { background: Some([255, 255, 255]), ..Default::default() };
+    for name in images(&dir, false) {
+        let png = check(&read(&dir, &name, "png")).unwrap();
+        let rendered = png.render(&RenderOptions::default()).unwrap_or_else(|e| panic!("{}: {}", name, e));
+        assert_eq!(rendered.samples.len(), (rendered.width * rendered.height) as usize * rendered.channels(), "{}", name);
+        let composited = png.render(&white).unwrap();
+        assert!(!composited.color_type.has_alpha(), "{}", name);
+    }
+    // untagged 8-bit images come out as they are
+    let png = check(&read(&dir, "basn2c08", "png")).unwrap();
+    assert_eq!(png.render(&RenderOptions::default()).unwrap(), png.pixels().unwrap());
+    // the file's own background wins
+    for name in ["bgwn6a08", "bggn4a16", "tbbn3p08"] {
+        let png = check(&read(&dir, name, "png")).unwrap();
+        assert!(!png.render(&RenderOptions::default()).unwrap().color_type.has_alpha(), "{}", name);
+    }
+}
+
+// every image survives being transformed and encoded again, interlaced ones included
+#[test]
+fn valid_images_transform() {
+    let dir = generated();
+    for name in images(&dir, false) {
+        let original = check(&read(&dir, &name, "png")).unwrap();
+        let pixels = original.pixels().unwrap();
+        let mut png = original.clone();
+        png.set_pixels(&pixels.rotate_90(), &EncodeOptions::default()).unwrap_or_else(|e| panic!("{}: {}", name, e));
+        assert_eq!(png.ihdr().unwrap().interlaced, original.ihdr().unwrap().interlaced, "{}", name);
+        assert_eq!(png.pixels().unwrap().rotate_270(), pixels, "{}", name);
+        assert_eq!(png.palette(), original.palette(), "{}", name);
+
+        let thumbnail = original.continuous_pixels().unwrap().resize(7, 5, ResizeFilter::Lanczos).unwrap();
+        let mut png = original.clone();
+        png.set_pixels(&thumbnail, &EncodeOptions::default()).unwrap();
+        assert_eq!(png.pixels().unwrap(), thumbnail, "{}", name);
+    }
+}
+
+#[test]
+fn valid_images_convert_formats() {
+    let dir = generated();
+    for name in images(&dir, false) {
+        let bytes = read(&dir, &name, "png");
+        let pixels = formats::read(&bytes, Format::Png, Limits::default()).unwrap();
+        for format in [Format::Png, Format::Pnm, Format::Pam, Format::Bmp, Format::Farbfeld] {
+            let converted = formats::write(&pixels, format, &EncodeOptions::default()).unwrap_or_else(|e| panic!("{} to {}: {}", name, format, e));
+            let back = formats::read(&converted, format, Limits::default()).unwrap_or_else(|e| panic!("{} from {}: {}", name, format, e));
+            assert_eq!((back.width, back.height), (pixels.width, pixels.height), "{} as {}", name, format);
+            if format == Format::Pam || format == Format::Png {
+                assert_eq!(back, pixels, "{} as {}", name, format);
+            }
+        }
+    }
+}
+
+// interlacing changes how pixels are stored, not what they look like
+#[test]
+fn interlaced_images_look_the_same() {
+    let dir = generated();
+    let names = images(&dir, false);
+    let pairs: Vec<_> = names.iter().filter(|name| name.starts_with("basi")).collect();
+    assert!(!pairs.is_empty());
+    for interlaced in pairs {
+        let plain = interlaced.replacen("basi", "basn", 1);
+        let left = check(&read(&dir, interlaced, "png")).unwrap().continuous_pixels().unwrap();
+        let right = check(&read(&dir, &plain, "png")).unwrap().continuous_pixels().unwrap();
+        let difference = diff::compare(&left, &right, 0.0).unwrap();
+        assert_eq!(difference.differing, 0, "{} and {}", interlaced, plain);
+        assert!(difference.psnr.is_infinite());
+    }
+}
+
+// PngSuite names spell out the header: the 4th letter is n or i for interlacing,
+// then the color type and, in the last two digits, the bit depth
+#[test]
+fn names_match_headers() {
+    let dir = generated();
+    for name in images(&dir, false) {
+        let ihdr = check(&read(&dir, &name, "png")).unwrap().ihdr().unwrap();
+        assert_eq!(ihdr.interlaced, &name[3..4] == "i", "{}", name);
+        assert_eq!(ihdr.color_type as u8, name[4..5].parse::<u8>().unwrap(), "{}", name);
+        assert_eq!(ihdr.bit_depth, name[6..8].parse::<u8>().unwrap(), "{}", name);
+    }
+}
+
+type Expected = (&'static str, fn(&PngError) -> bool);
+
+// The errors PngSuite's x* images are described as having, which ours copy.
+// Only our copies are checked until the real ones are vendored.
+fn check_corrupt(dir: &Path) {
+    let expected: &[Expected] = &[
+        ("xs1n0g01", |e| matches!(e, PngError::MangledSignature(Mangling::HighBitCleared))),
+        ("xs2n0g01", |e| matches!(e, PngError::PngSignature)),
+        ("xs4n0g01", |e| matches!(e, PngError::PngSignature)),
+        ("xs7n0g01", |e| matches!(e, PngError::PngSignature)),
+        ("xcrn0g04", |e| matches!(e, PngError::MangledSignature(Mangling::LfToCrlf))),
+        ("xlfn0g04", |e| matches!(e, PngError::PngSignature)),
+        ("xhdn0g08", |e| matches!(e, PngError::ChunkType(ChunkError::Crc))),
+        ("xcsn0g01", |e| matches!(e, PngError::ChunkType(ChunkError::Crc))),
+        ("xc1n0g08", |e| matches!(e, PngError::Ihdr(IhdrError::ColorType(1)))),
+        ("xc9n2c08", |e| matches!(e, PngError::Ihdr(IhdrError::ColorType(9)))),
+        ("xd0n2c08", |e| matches!(e, PngError::Ihdr(IhdrError::BitDepth(0, ColorType::Rgb)))),
+        ("xd3n2c08", |e| matches!(e, PngError::Ihdr(IhdrError::BitDepth(3, ColorType::Rgb)))),
+        ("xd9n2c08", |e| matches!(e, PngError::Ihdr(IhdrError::BitDepth(99, ColorType::Rgb)))),
+    ];
+    for (name, is_expected) in expected {
+        match check(&read(dir, name, "png")) {
+            Ok(_) => panic!("{} was accepted", name),
+            Err(e) => assert!(is_expected(&e), "{}: unexpected error {:?}", name, e),
+        }
+    }
+    // structurally fine, it's the validator that catches it
+    let png = check(&read(dir, "xdtn0g01", "png")).unwrap();
+    assert!(png.validate().iter().any(|issue| issue.violation == Violation::Missing(ChunkType::IDAT)));
+    assert!(matches!(png.decode(), Err(DecodeError::Png(PngError::MissingChunk(ChunkType::IDAT)))));
+
+    let covered = expected.len() + 1;
+    assert_eq!(images(dir, true).len(), covered, "every corrupt image needs an expected error");
+}
+
+#[test]
+fn corrupt_images_are_rejected() {
+    check_corrupt(&generated());
+}
+
+// every valid PngSuite image decodes to the same pixels as it does with netpbm
+#[test]
+#[ignore = "needs the files from tests/pngsuite/fetch.sh"]
+fn pngsuite_matches_references() {
+    let dir = pngsuite();
+    let names = images(&dir, false);
+    assert!(!names.is_empty());
+    for name in names {
+        let png = check(&read(&dir, &name, "png")).unwrap_or_else(|e| panic!("{}: {}", name, e));
+        let pixels = png.continuous_pixels().unwrap_or_else(|e| panic!("{}: {}", name, e));
+        let reference = formats::read(&read(&dir, &name, "pam"), Format::Pam, Limits::default()).unwrap();
+        let difference = diff::compare(&pixels, &reference, 0.0).unwrap_or_else(|e| panic!("{}: {}", name, e));
+        assert_eq!(difference.differing, 0, "{}: {} pixels differ from netpbm's", name, difference.differing);
+    }
+}
+
+#[test]
+#[ignore = "needs the files from tests/pngsuite/fetch.sh"]
+fn pngsuite_corrupt_images_are_rejected() {
+    check_corrupt(&pngsuite());
+}
@@ -0,0 +1,20 @@
+#!/bin/sh
+# Vendors Willem van Schaik's PngSuite into this directory, with its licence,
+# and writes the reference pixels tests/pngsuite.rs compares against: each valid
+# image decoded by netpbm's pngtopam, independently of pngme, as NAME.pam.
+#
+# Needs curl, tar and netpbm. Run it from anywhere and commit what it writes:
+#     sh tests/pngsuite/fetch.sh
+# then drop the #[ignore]s on the pngsuite_* tests in tests/pngsuite.rs.
+set -eu
+cd "$(dirname "$0")"
+curl -fsSL -o PngSuite.tgz http://www.schaik.com/pngsuite/PngSuite-2017jul19.tgz
+tar -xzf PngSuite.tgz
+rm PngSuite.tgz
+test -f PngSuite.LICENSE
+for png in *.png; do
+    case "$png" in
+        x*) ;;
+        *) pngtopam -alphapam "$png" > "${png%.png}.pam" ;;
+    esac
+done